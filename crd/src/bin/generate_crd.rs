@@ -1,15 +1,19 @@
+use stackable_zookeeper_crd::znode::ZookeeperZnode;
 use stackable_zookeeper_crd::ZookeeperCluster;
 use std::fs;
 
 fn main() {
-    let target_file = "./crd/zookeepercluster.crd.yaml";
-    let schema = ZookeeperCluster::crd();
+    write_crd("./crd/zookeepercluster.crd.yaml", ZookeeperCluster::crd());
+    write_crd("./crd/zookeeperznode.crd.yaml", ZookeeperZnode::crd());
+}
+
+fn write_crd<T: serde::Serialize>(target_file: &str, schema: T) {
     let string_schema = match serde_yaml::to_string(&schema) {
         Ok(schema) => schema,
         Err(err) => panic!("Failed to retrieve CRD: [{}]", err),
     };
     match fs::write(target_file, string_schema) {
-        Ok(()) => println!("Successfully wrote CRD to file."),
+        Ok(()) => println!("Successfully wrote CRD to [{}].", target_file),
         Err(err) => println!("Failed to write file: [{}]", err),
     }
 }
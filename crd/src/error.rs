@@ -6,9 +6,34 @@ pub enum Error {
     #[error("Illegal znode [{znode}]: {reason}")]
     IllegalZnode { znode: String, reason: String },
 
+    #[error("Invalid ZooKeeper configuration: {errors:?}")]
+    InvalidConfig { errors: Vec<String> },
+
+    #[error("Downgrade from [{from}] to [{to}] is not allowed by the downgrade policy [{downgrade_policy:?}]")]
+    DowngradeNotAllowed {
+        from: String,
+        to: String,
+        downgrade_policy: crate::DowngradePolicy,
+    },
+
+    #[error("Version [{version}] is not supported, supported versions are: {supported:?}")]
+    UnsupportedVersion {
+        version: String,
+        supported: Vec<String>,
+    },
+
+    #[error("Invalid version: {source}")]
+    InvalidVersion {
+        #[from]
+        source: semver::SemVerError,
+    },
+
     #[error("Pod has no hostname assignment, this is most probably a transitive failure and should be retried: [{pod}]")]
     PodWithoutHostname { pod: String },
 
+    #[error("ZooKeeper cluster [{cluster}] has no servers yet, this is most probably a transitive failure and should be retried")]
+    ClusterWithoutServers { cluster: String },
+
     #[error("Pod [{pod}] is missing the following required labels: [{labels:?}]")]
     PodMissingLabels { pod: String, labels: Vec<String> },
 
@@ -1,20 +1,48 @@
 pub mod error;
 pub mod util;
+pub mod version;
+pub mod znode;
 
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector};
+use crate::error::{Error, ZookeeperOperatorResult};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector, Time};
 use kube::CustomResource;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
 use schemars::JsonSchema;
-use semver::{SemVerError, Version};
 use serde::{Deserialize, Serialize};
 use stackable_operator::label_selector;
 use stackable_operator::Crd;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+pub use crate::version::ZookeeperVersion;
 
 pub const APP_NAME: &str = "zookeeper";
 pub const MANAGED_BY: &str = "stackable-zookeeper";
 
-// TODO: We need to validate the name of the cluster because it is used in pod and configmap names, it can't bee too long
-// This probably also means we shouldn't use the node_names in the pod_name...
+/// The slot of the server on its node (see `instancesPerNode`), pods without it are in slot 0
+pub const NODE_SLOT_LABEL: &str = "zookeeper.stackable.tech/node-slot";
+
+pub const DEFAULT_TICK_TIME: u32 = 2000;
+pub const DEFAULT_INIT_LIMIT: u32 = 5;
+pub const DEFAULT_SYNC_LIMIT: u32 = 2;
+pub const DEFAULT_CLIENT_PORT: u16 = 2181;
+pub const DEFAULT_SECURE_CLIENT_PORT: u16 = 2281;
+pub const DEFAULT_QUORUM_PORT: u16 = 2888;
+pub const DEFAULT_LEADER_ELECTION_PORT: u16 = 3888;
+pub const DEFAULT_DATA_DIR: &str = "/var/lib/zookeeper/data";
+pub const DEFAULT_DATA_LOG_DIR: &str = "/var/lib/zookeeper/datalog";
+pub const DEFAULT_HOST_PATH: &str = "/var/lib/stackable/zookeeper";
+pub const DEFAULT_INSTANCES: u16 = 1;
+pub const DEFAULT_INSTANCES_PER_NODE: u8 = 1;
+pub const DEFAULT_ID_QUARANTINE_SECONDS: u64 = 3600;
+pub const DEFAULT_CERTIFICATE_VALIDITY_DAYS: u16 = 90;
+
+// ZooKeeper silently raises any lower value to this, we'd rather tell the user
+const MIN_AUTOPURGE_SNAP_RETAIN_COUNT: u32 = 3;
+
+// The name of the cluster is used in pod and configmap names so it can't be too long, this is
+// checked by the validating admission webhook (see `stackable_zookeeper_operator::webhook`)
 #[derive(Clone, CustomResource, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[kube(
     group = "zookeeper.stackable.tech",
@@ -26,7 +54,105 @@ pub const MANAGED_BY: &str = "stackable-zookeeper";
 #[kube(status = "ZookeeperClusterStatus")]
 pub struct ZookeeperClusterSpec {
     pub version: ZookeeperVersion,
+    /// Whether `version` may be lower than the version the cluster currently runs, defaults to `Forbid`
+    #[schemars(default = "DowngradePolicy::default")]
+    pub downgrade_policy: Option<DowngradePolicy>,
+    /// Cluster wide configuration, can be overridden per role group
+    #[schemars(schema_with = "cluster_config_schema", default = "empty_object")]
+    pub config: Option<ZookeeperConfig>,
+    /// How long the `myid` of a removed server is not given to a new server, in seconds, defaults to 3600
+    #[schemars(default = "default_id_quarantine_seconds")]
+    pub id_quarantine_seconds: Option<u64>,
+    /// Whether every server gets its own Service in addition to the client and the headless Service, defaults to false
+    #[schemars(default = "default_per_server_services")]
+    pub per_server_services: Option<bool>,
     pub servers: RoleGroups<ZookeeperConfig>,
+    /// Servers that serve clients and follow the ensemble but don't vote, so they can scale reads
+    /// without slowing down writes. The role group names must differ from the ones of `servers`.
+    pub observers: Option<RoleGroups<ZookeeperConfig>>,
+    /// Clients connect using TLS if set
+    pub tls: Option<TlsConfig>,
+    /// SASL authentication of clients and between the servers
+    pub authentication: Option<AuthenticationConfig>,
+}
+
+impl ZookeeperClusterSpec {
+    pub fn id_quarantine_period(&self) -> Duration {
+        Duration::from_secs(
+            self.id_quarantine_seconds
+                .unwrap_or(DEFAULT_ID_QUARANTINE_SECONDS),
+        )
+    }
+
+    /// All role groups of `servers` and `observers` with the peer type of their servers, sorted
+    /// by name.
+    pub fn role_groups(&self) -> Vec<(&String, &SelectorAndConfig<ZookeeperConfig>, PeerType)> {
+        let mut role_groups = self
+            .servers
+            .selectors
+            .iter()
+            .map(|(name, selector)| (name, selector, PeerType::Participant))
+            .chain(
+                self.observers
+                    .iter()
+                    .flat_map(|observers| observers.selectors.iter())
+                    .map(|(name, selector)| (name, selector, PeerType::Observer)),
+            )
+            .collect::<Vec<_>>();
+        role_groups.sort_by_key(|(name, _, peer_type)| (*name, *peer_type));
+        role_groups
+    }
+
+    /// The role group with the given name, a role group of `servers` takes precedence over one
+    /// of `observers` with the same name.
+    pub fn role_group(
+        &self,
+        role_group: &str,
+    ) -> Option<(&SelectorAndConfig<ZookeeperConfig>, PeerType)> {
+        self.servers
+            .selectors
+            .get(role_group)
+            .map(|selector| (selector, PeerType::Participant))
+            .or_else(|| {
+                self.observers
+                    .as_ref()?
+                    .selectors
+                    .get(role_group)
+                    .map(|selector| (selector, PeerType::Observer))
+            })
+    }
+
+    /// Returns the effective configuration for the given role group: Every value set on the
+    /// role group takes precedence over the cluster wide value.
+    /// Unknown role groups will return the cluster wide configuration.
+    pub fn merged_config(&self, role_group: &str) -> ZookeeperConfig {
+        let cluster_config = self.config.clone().unwrap_or_default();
+
+        match self
+            .role_group(role_group)
+            .and_then(|(selector, _)| selector.config.as_ref())
+        {
+            None => cluster_config,
+            Some(role_group_config) => cluster_config.merge(role_group_config),
+        }
+    }
+
+    /// The stage quorum connections are switched to, `Tls` if `tls.quorum` is set.
+    pub fn target_quorum_tls_stage(&self) -> QuorumTlsStage {
+        match &self.tls {
+            Some(tls) if tls.quorum() => QuorumTlsStage::Tls,
+            _ => QuorumTlsStage::Plaintext,
+        }
+    }
+
+    /// The stage quorum authentication is switched to, `Required` if `authentication.quorum` is
+    /// set.
+    pub fn target_quorum_sasl_stage(&self) -> QuorumSaslStage {
+        match &self.authentication {
+            Some(authentication) if authentication.quorum() => QuorumSaslStage::Required,
+            _ => QuorumSaslStage::Disabled,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
@@ -38,61 +164,707 @@ pub struct RoleGroups<T> {
 #[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SelectorAndConfig<T> {
+    #[serde(default = "default_instances")]
     pub instances: u16,
+    #[serde(default = "default_instances_per_node")]
     pub instances_per_node: u8,
     pub config: Option<T>,
-    #[schemars(schema_with = "label_selector::schema")]
+    /// Nodes the servers of this role group may run on, all nodes if not set
+    #[schemars(schema_with = "label_selector::schema", default = "empty_object")]
     pub selector: Option<LabelSelector>,
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
-pub struct ZookeeperConfig {}
+impl<T> SelectorAndConfig<T> {
+    /// The selector of this role group, an unset selector matches all nodes.
+    pub fn selector(&self) -> LabelSelector {
+        self.selector.clone().unwrap_or_default()
+    }
+}
+
+fn default_id_quarantine_seconds() -> u64 {
+    DEFAULT_ID_QUARANTINE_SECONDS
+}
+
+fn default_per_server_services() -> bool {
+    false
+}
+
+/// The schema default of objects which may be left out, the API server fills in the defaults of
+/// their properties then as well.
+fn empty_object() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+/// The schema of the cluster wide configuration: The one of `ZookeeperConfig` with the defaults
+/// of `ZookeeperConfig::with_defaults`.
+/// The defaults can't be part of `ZookeeperConfig` itself, the role group configurations would
+/// get them as well and override the cluster wide values when merging.
+fn cluster_config_schema(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = ZookeeperConfig::json_schema(gen).into_object();
+    schema
+        .extensions
+        .insert("nullable".to_string(), serde_json::Value::Bool(true));
+
+    if let (Some(object), Ok(serde_json::Value::Object(defaults))) = (
+        schema.object.as_mut(),
+        serde_json::to_value(ZookeeperConfig::default().with_defaults()),
+    ) {
+        for (name, default) in defaults {
+            if let (Some(Schema::Object(property)), false) =
+                (object.properties.get_mut(&name), default.is_null())
+            {
+                property.metadata().default = Some(default);
+            }
+        }
+    }
+
+    Schema::Object(schema)
+}
+
+fn default_instances() -> u16 {
+    DEFAULT_INSTANCES
+}
+
+fn default_instances_per_node() -> u8 {
+    DEFAULT_INSTANCES_PER_NODE
+}
+
+/// Typed representation of the supported `zoo.cfg` settings.
+/// All values are optional, unset values either fall back to our defaults or to the ones
+/// ZooKeeper uses internally.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ZookeeperConfig {
+    /// The length of a single tick in milliseconds (`tickTime`)
+    pub tick_time: Option<u32>,
+    /// Number of ticks followers may take to connect and sync to a leader (`initLimit`)
+    pub init_limit: Option<u32>,
+    /// Number of ticks followers may lag behind the leader (`syncLimit`)
+    pub sync_limit: Option<u32>,
+    /// Maximum number of concurrent connections from a single client, 0 means unlimited (`maxClientCnxns`)
+    pub max_client_cnxns: Option<u32>,
+    /// Number of snapshots to keep when purging (`autopurge.snapRetainCount`)
+    pub autopurge_snap_retain_count: Option<u32>,
+    /// Purge interval in hours, 0 disables purging (`autopurge.purgeInterval`)
+    pub autopurge_purge_interval: Option<u32>,
+    /// Minimum session timeout in milliseconds (`minSessionTimeout`)
+    pub min_session_timeout: Option<u32>,
+    /// Maximum session timeout in milliseconds (`maxSessionTimeout`)
+    pub max_session_timeout: Option<u32>,
+    /// The port clients connect to (`clientPort`)
+    pub client_port: Option<u16>,
+    /// The port clients connect to with TLS (`secureClientPort`), only used if `tls` is set,
+    /// defaults to 2281
+    pub secure_client_port: Option<u16>,
+    /// The port followers use to connect to the leader (first port in the `server.N` entries)
+    pub quorum_port: Option<u16>,
+    /// The port used for leader election (second port in the `server.N` entries)
+    pub leader_election_port: Option<u16>,
+    /// Directory for the snapshots and the `myid` file (`dataDir`)
+    pub data_dir: Option<String>,
+    /// Directory for the transaction log (`dataLogDir`)
+    pub data_log_dir: Option<String>,
+    /// Where the data and transaction log directories are stored, defaults to a host path
+    pub storage: Option<ZookeeperStorage>,
+}
+
+/// The backing storage for the data and transaction log directories of a server.
+/// Both directories live on the same volume. At most one of the sources may be set, if none is
+/// set a host path is used.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ZookeeperStorage {
+    pub host_path: Option<HostPathStorage>,
+    pub persistent_volume_claim: Option<PersistentVolumeClaimStorage>,
+}
+
+/// A directory on the node the server runs on.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostPathStorage {
+    /// Every server gets its own subdirectory below this path, defaults to `/var/lib/stackable/zookeeper`
+    pub path: Option<String>,
+}
+
+impl HostPathStorage {
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(DEFAULT_HOST_PATH)
+    }
+}
+
+/// A PersistentVolumeClaim per server which will be created by the operator.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistentVolumeClaimStorage {
+    pub storage_class_name: Option<String>,
+    /// The requested size as a Kubernetes quantity (e.g. `10Gi`)
+    pub size: String,
+}
+
+impl ZookeeperConfig {
+    /// Returns a new config where every value that is set in `other` overrides the value in `self`.
+    pub fn merge(&self, other: &Self) -> Self {
+        ZookeeperConfig {
+            tick_time: other.tick_time.or(self.tick_time),
+            init_limit: other.init_limit.or(self.init_limit),
+            sync_limit: other.sync_limit.or(self.sync_limit),
+            max_client_cnxns: other.max_client_cnxns.or(self.max_client_cnxns),
+            autopurge_snap_retain_count: other
+                .autopurge_snap_retain_count
+                .or(self.autopurge_snap_retain_count),
+            autopurge_purge_interval: other
+                .autopurge_purge_interval
+                .or(self.autopurge_purge_interval),
+            min_session_timeout: other.min_session_timeout.or(self.min_session_timeout),
+            max_session_timeout: other.max_session_timeout.or(self.max_session_timeout),
+            client_port: other.client_port.or(self.client_port),
+            secure_client_port: other.secure_client_port.or(self.secure_client_port),
+            quorum_port: other.quorum_port.or(self.quorum_port),
+            leader_election_port: other.leader_election_port.or(self.leader_election_port),
+            data_dir: other.data_dir.clone().or_else(|| self.data_dir.clone()),
+            data_log_dir: other
+                .data_log_dir
+                .clone()
+                .or_else(|| self.data_log_dir.clone()),
+            storage: other.storage.clone().or_else(|| self.storage.clone()),
+        }
+    }
+
+    /// Returns a copy where every value the operator has a default for is set.
+    /// Values without a default (e.g. `maxClientCnxns`) are left to ZooKeeper.
+    ///
+    /// This must only be used for the cluster wide configuration, otherwise the defaults of a
+    /// role group would override the cluster wide values when merging.
+    pub fn with_defaults(&self) -> Self {
+        ZookeeperConfig {
+            tick_time: Some(self.tick_time.unwrap_or(DEFAULT_TICK_TIME)),
+            init_limit: Some(self.init_limit.unwrap_or(DEFAULT_INIT_LIMIT)),
+            sync_limit: Some(self.sync_limit.unwrap_or(DEFAULT_SYNC_LIMIT)),
+            client_port: Some(self.client_port()),
+            quorum_port: Some(self.quorum_port()),
+            leader_election_port: Some(self.leader_election_port()),
+            data_dir: Some(self.data_dir().to_string()),
+            data_log_dir: Some(self.data_log_dir().to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the configuration of the server in the given slot of a node.
+    ///
+    /// Several servers of a role group may run on the same node (see `instancesPerNode`), every
+    /// one of them needs its own ports and directories: The ports are increased by the slot and
+    /// the directories get the slot as suffix. The first server on a node (slot 0) uses the
+    /// configuration as it is.
+    pub fn for_node_slot(&self, node_slot: u8) -> Self {
+        if node_slot == 0 {
+            return self.clone();
+        }
+
+        let offset = u16::from(node_slot);
+        ZookeeperConfig {
+            client_port: Some(self.client_port().saturating_add(offset)),
+            secure_client_port: Some(self.secure_client_port().saturating_add(offset)),
+            quorum_port: Some(self.quorum_port().saturating_add(offset)),
+            leader_election_port: Some(self.leader_election_port().saturating_add(offset)),
+            data_dir: Some(format!("{}-{}", self.data_dir(), node_slot)),
+            data_log_dir: Some(format!("{}-{}", self.data_log_dir(), node_slot)),
+            ..self.clone()
+        }
+    }
+
+    pub fn data_dir(&self) -> &str {
+        self.data_dir.as_deref().unwrap_or(DEFAULT_DATA_DIR)
+    }
+
+    pub fn data_log_dir(&self) -> &str {
+        self.data_log_dir.as_deref().unwrap_or(DEFAULT_DATA_LOG_DIR)
+    }
+
+    pub fn storage(&self) -> ZookeeperStorage {
+        self.storage.clone().unwrap_or_default()
+    }
+
+    pub fn client_port(&self) -> u16 {
+        self.client_port.unwrap_or(DEFAULT_CLIENT_PORT)
+    }
+
+    pub fn secure_client_port(&self) -> u16 {
+        self.secure_client_port
+            .unwrap_or(DEFAULT_SECURE_CLIENT_PORT)
+    }
+
+    pub fn quorum_port(&self) -> u16 {
+        self.quorum_port.unwrap_or(DEFAULT_QUORUM_PORT)
+    }
+
+    pub fn leader_election_port(&self) -> u16 {
+        self.leader_election_port
+            .unwrap_or(DEFAULT_LEADER_ELECTION_PORT)
+    }
+
+    /// Checks the values against the constraints ZooKeeper has for them.
+    /// All violations are collected and reported at once.
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::InvalidConfig`] if any of the values is not acceptable
+    pub fn validate(&self) -> ZookeeperOperatorResult<()> {
+        let mut errors = vec![];
+
+        for (name, value) in &[
+            ("tickTime", self.tick_time),
+            ("initLimit", self.init_limit),
+            ("syncLimit", self.sync_limit),
+            ("minSessionTimeout", self.min_session_timeout),
+            ("maxSessionTimeout", self.max_session_timeout),
+        ] {
+            if value == &Some(0) {
+                errors.push(format!("{} must be greater than 0", name));
+            }
+        }
+
+        if let Some(count) = self.autopurge_snap_retain_count {
+            if count < MIN_AUTOPURGE_SNAP_RETAIN_COUNT {
+                errors.push(format!(
+                    "autopurge.snapRetainCount must be at least {} but is {}",
+                    MIN_AUTOPURGE_SNAP_RETAIN_COUNT, count
+                ));
+            }
+        }
+
+        if let (Some(min), Some(max)) = (self.min_session_timeout, self.max_session_timeout) {
+            if min > max {
+                errors.push(format!(
+                    "minSessionTimeout ({}) must not be greater than maxSessionTimeout ({})",
+                    min, max
+                ));
+            }
+        }
+
+        for (name, dir) in &[
+            ("dataDir", self.data_dir()),
+            ("dataLogDir", self.data_log_dir()),
+        ] {
+            if !dir.starts_with('/') {
+                errors.push(format!(
+                    "{} must be an absolute path but is [{}]",
+                    name, dir
+                ));
+            }
+        }
+        if self.data_dir() == self.data_log_dir() {
+            errors.push("dataDir and dataLogDir must not be the same directory".to_string());
+        }
+
+        let storage = self.storage();
+        if storage.host_path.is_some() && storage.persistent_volume_claim.is_some() {
+            errors.push(
+                "storage must only contain one of hostPath and persistentVolumeClaim".to_string(),
+            );
+        }
+        if let Some(PersistentVolumeClaimStorage { size, .. }) = &storage.persistent_volume_claim {
+            if size.is_empty() {
+                errors.push("the size of a persistentVolumeClaim must not be empty".to_string());
+            }
+        }
+
+        let mut ports = vec![
+            ("clientPort", self.client_port()),
+            ("quorumPort", self.quorum_port()),
+            ("leaderElectionPort", self.leader_election_port()),
+        ];
+        // The default is only checked when TLS is used, see the admission webhook
+        if let Some(secure_client_port) = self.secure_client_port {
+            ports.push(("secureClientPort", secure_client_port));
+        }
+        for (index, (name, port)) in ports.iter().enumerate() {
+            if *port == 0 {
+                errors.push(format!("{} must not be 0", name));
+            }
+            for (other_name, other_port) in ports.iter().skip(index + 1) {
+                if port == other_port {
+                    errors.push(format!(
+                        "{} and {} must not use the same port [{}]",
+                        name, other_name, port
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig { errors })
+        }
+    }
+}
 
 impl Crd for ZookeeperCluster {
     const RESOURCE_NAME: &'static str = "zookeeperclusters.zookeeper.stackable.tech";
     const CRD_DEFINITION: &'static str = include_str!("../../deploy/crd/zookeepercluster.crd.yaml");
 }
 
-#[allow(non_camel_case_types)]
-#[derive(
-    Clone,
-    Debug,
-    Deserialize,
-    Eq,
-    JsonSchema,
-    PartialEq,
-    Serialize,
-    strum_macros::Display,
-    strum_macros::EnumString,
-)]
-pub enum ZookeeperVersion {
-    #[serde(rename = "3.4.14")]
-    #[strum(serialize = "3.4.14")]
-    v3_4_14,
+/// TLS for client connections: The servers additionally listen on `secureClientPort` and clients
+/// are told to use it.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfig {
+    /// The Secret with the key store and the trust store of the servers, see `format` for the
+    /// expected keys. The operator issues a certificate for every server if not set.
+    pub secret_name: Option<String>,
+    /// The Secret with the PEM encoded certificate (`ca.crt`) and PKCS#8 private key (`ca.key`) of
+    /// the CA signing the certificates the operator issues. Defaults to `<cluster name>-tls-ca`,
+    /// which is created by the operator if it doesn't exist.
+    pub ca_secret_name: Option<String>,
+    /// How long certificates issued by the operator are valid, in days, defaults to 90. They are
+    /// renewed once less than a third of that is left.
+    pub certificate_validity_days: Option<u16>,
+    /// How the stores are kept in the Secret, defaults to `Jks`
+    pub format: Option<KeyStoreFormat>,
+    /// Whether clients have to present a certificate, defaults to `Need`
+    pub client_auth: Option<ClientAuth>,
+    /// Whether the servers also use the stores for quorum and leader election connections,
+    /// defaults to `false`. Running clusters are switched in stages, see `QuorumTlsStage`.
+    pub quorum: Option<bool>,
+}
 
-    #[serde(rename = "3.5.8")]
-    #[strum(serialize = "3.5.8")]
-    v3_5_8,
+impl TlsConfig {
+    pub fn format(&self) -> KeyStoreFormat {
+        self.format.unwrap_or_default()
+    }
+
+    pub fn client_auth(&self) -> ClientAuth {
+        self.client_auth.unwrap_or_default()
+    }
+
+    pub fn quorum(&self) -> bool {
+        self.quorum.unwrap_or_default()
+    }
+
+    /// Whether the operator issues the certificates of the servers
+    pub fn is_managed(&self) -> bool {
+        self.secret_name.is_none()
+    }
+
+    pub fn ca_secret_name(&self, cluster_name: &str) -> String {
+        self.ca_secret_name
+            .clone()
+            .unwrap_or_else(|| default_ca_secret_name(cluster_name))
+    }
+
+    pub fn certificate_validity(&self) -> Duration {
+        let days = self
+            .certificate_validity_days
+            .unwrap_or(DEFAULT_CERTIFICATE_VALIDITY_DAYS);
+        Duration::from_secs(u64::from(days) * 24 * 60 * 60)
+    }
 }
 
-impl ZookeeperVersion {
-    pub fn is_valid_upgrade(&self, to: &Self) -> Result<bool, SemVerError> {
-        let from_version = Version::parse(&self.to_string())?;
-        let to_version = Version::parse(&to.to_string())?;
+/// The Secret with the CA the operator creates for a cluster
+pub fn default_ca_secret_name(cluster_name: &str) -> String {
+    format!("{}-tls-ca", cluster_name)
+}
+
+/// The stages a running cluster goes through to switch quorum connections to TLS, each one is
+/// applied with a rolling restart. Switching back to plaintext goes through them in reverse.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum QuorumTlsStage {
+    /// Servers connect to each other in plaintext
+    Plaintext,
+    /// Servers accept TLS and plaintext connections (`portUnification`) but still connect in
+    /// plaintext
+    PortUnification,
+    /// Servers connect with TLS (`sslQuorum`) and still accept plaintext connections
+    TlsWithPortUnification,
+    /// Servers only accept TLS connections
+    Tls,
+}
+
+impl QuorumTlsStage {
+    const ORDER: [QuorumTlsStage; 4] = [
+        QuorumTlsStage::Plaintext,
+        QuorumTlsStage::PortUnification,
+        QuorumTlsStage::TlsWithPortUnification,
+        QuorumTlsStage::Tls,
+    ];
+
+    /// The stage the servers are switched to next on the way to `target`, `None` if this is
+    /// the target already
+    pub fn next_towards(&self, target: QuorumTlsStage) -> Option<QuorumTlsStage> {
+        next_stage_towards(&Self::ORDER, *self, target)
+    }
+
+    /// Whether the servers connect to each other with TLS (`sslQuorum`)
+    pub fn ssl_quorum(&self) -> bool {
+        matches!(
+            self,
+            QuorumTlsStage::TlsWithPortUnification | QuorumTlsStage::Tls
+        )
+    }
+
+    /// Whether the servers accept TLS and plaintext on the quorum ports (`portUnification`)
+    pub fn port_unification(&self) -> bool {
+        matches!(
+            self,
+            QuorumTlsStage::PortUnification | QuorumTlsStage::TlsWithPortUnification
+        )
+    }
+}
+
+impl Default for QuorumTlsStage {
+    fn default() -> Self {
+        QuorumTlsStage::Plaintext
+    }
+}
+
+/// The stages a running cluster goes through to let the servers authenticate each other with
+/// SASL, each one is applied with a rolling restart. Switching it off goes through them in
+/// reverse.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum QuorumSaslStage {
+    /// Servers don't authenticate each other
+    Disabled,
+    /// Servers authenticate when connecting to each other (`quorum.auth.enableSasl`) but don't
+    /// require it
+    Enabled,
+    /// Learners only connect to servers that authenticate (`quorum.auth.learnerRequireSasl`)
+    LearnerRequired,
+    /// Servers also only accept learners that authenticate (`quorum.auth.serverRequireSasl`)
+    Required,
+}
+
+impl QuorumSaslStage {
+    const ORDER: [QuorumSaslStage; 4] = [
+        QuorumSaslStage::Disabled,
+        QuorumSaslStage::Enabled,
+        QuorumSaslStage::LearnerRequired,
+        QuorumSaslStage::Required,
+    ];
+
+    /// The stage the servers are switched to next on the way to `target`, `None` if this is
+    /// the target already
+    pub fn next_towards(&self, target: QuorumSaslStage) -> Option<QuorumSaslStage> {
+        next_stage_towards(&Self::ORDER, *self, target)
+    }
+
+    pub fn enable_sasl(&self) -> bool {
+        *self != QuorumSaslStage::Disabled
+    }
 
-        Ok(to_version > from_version)
+    pub fn learner_require_sasl(&self) -> bool {
+        matches!(
+            self,
+            QuorumSaslStage::LearnerRequired | QuorumSaslStage::Required
+        )
     }
 
-    pub fn package_name(&self) -> String {
+    pub fn server_require_sasl(&self) -> bool {
+        *self == QuorumSaslStage::Required
+    }
+}
+
+impl Default for QuorumSaslStage {
+    fn default() -> Self {
+        QuorumSaslStage::Disabled
+    }
+}
+
+/// The neighbour of `current` in `order` on the way to `target`, `None` if `current` is the
+/// target already
+fn next_stage_towards<T: Copy + PartialEq>(order: &[T], current: T, target: T) -> Option<T> {
+    let position = |stage: T| {
+        order
+            .iter()
+            .position(|candidate| *candidate == stage)
+            .unwrap_or_default()
+    };
+    let (current, target) = (position(current), position(target));
+
+    if current < target {
+        Some(order[current + 1])
+    } else if current > target {
+        Some(order[current - 1])
+    } else {
+        None
+    }
+}
+
+/// The format of the key store and the trust store.
+///
+/// * `Jks` and `Pkcs12`: The keys `keystore.jks` and `truststore.jks` (or `keystore.p12` and
+///   `truststore.p12`) with the stores and `password` with the password of both.
+/// * `Pem`: The keys `keystore.pem` with the unencrypted private key and the certificate chain and
+///   `truststore.pem` with the trusted certificates.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum KeyStoreFormat {
+    Jks,
+    Pkcs12,
+    Pem,
+}
+
+impl KeyStoreFormat {
+    /// The name ZooKeeper uses in `ssl.keyStore.type` and `ssl.trustStore.type`
+    pub fn store_type(&self) -> &'static str {
         match self {
-            ZookeeperVersion::v3_4_14 => {
-                format!("zookeeper-{}", self.to_string())
-            }
-            ZookeeperVersion::v3_5_8 => {
-                format!("apache-zookeeper-{}-bin", self.to_string())
-            }
+            KeyStoreFormat::Jks => "JKS",
+            KeyStoreFormat::Pkcs12 => "PKCS12",
+            KeyStoreFormat::Pem => "PEM",
+        }
+    }
+
+    pub fn keystore_file(&self) -> &'static str {
+        match self {
+            KeyStoreFormat::Jks => "keystore.jks",
+            KeyStoreFormat::Pkcs12 => "keystore.p12",
+            KeyStoreFormat::Pem => "keystore.pem",
+        }
+    }
+
+    pub fn truststore_file(&self) -> &'static str {
+        match self {
+            KeyStoreFormat::Jks => "truststore.jks",
+            KeyStoreFormat::Pkcs12 => "truststore.p12",
+            KeyStoreFormat::Pem => "truststore.pem",
         }
     }
+
+    /// PEM files are expected to be unencrypted
+    pub fn has_password(&self) -> bool {
+        !matches!(self, KeyStoreFormat::Pem)
+    }
+}
+
+impl Default for KeyStoreFormat {
+    fn default() -> Self {
+        KeyStoreFormat::Jks
+    }
+}
+
+/// Whether clients have to authenticate with a certificate (`ssl.clientAuth`).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum ClientAuth {
+    Need,
+    Want,
+    None,
+}
+
+impl ClientAuth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientAuth::Need => "need",
+            ClientAuth::Want => "want",
+            ClientAuth::None => "none",
+        }
+    }
+}
+
+impl Default for ClientAuth {
+    fn default() -> Self {
+        ClientAuth::Need
+    }
+}
+
+/// SASL authentication of clients and between the servers: The servers get a JAAS configuration
+/// with the enabled mechanisms and the credentials for each other.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationConfig {
+    /// Clients can authenticate with DIGEST-MD5 as one of the users in a Secret
+    pub digest: Option<DigestAuthentication>,
+    /// Clients can authenticate with Kerberos (GSSAPI)
+    pub kerberos: Option<KerberosAuthentication>,
+    /// Whether clients have to authenticate (`requireClientAuthScheme=sasl`), defaults to `false`
+    pub require_client_auth: Option<bool>,
+    /// Whether the servers authenticate each other with DIGEST-MD5 on the quorum and leader
+    /// election ports, defaults to `false`. Running clusters are switched in stages, see
+    /// `QuorumSaslStage`.
+    pub quorum: Option<bool>,
+    /// The Secret with the credentials (`username` and `password`) the servers authenticate each
+    /// other with, required for `quorum`
+    pub quorum_secret_name: Option<String>,
+}
+
+impl AuthenticationConfig {
+    pub fn require_client_auth(&self) -> bool {
+        self.require_client_auth.unwrap_or_default()
+    }
+
+    pub fn quorum(&self) -> bool {
+        self.quorum.unwrap_or_default()
+    }
+
+    /// Whether clients can authenticate, i.e. `digest` or `kerberos` is set
+    pub fn authenticates_clients(&self) -> bool {
+        self.digest.is_some() || self.kerberos.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DigestAuthentication {
+    /// The Secret with the users, every key is the name of a user and its value the password
+    pub secret_name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KerberosAuthentication {
+    /// The Secret with the keytab (key `keytab`) containing the principals of all servers
+    pub keytab_secret_name: String,
+    /// The principal of a server, `{host}` is replaced with its DNS name, e.g.
+    /// `zookeeper/{host}@EXAMPLE.COM`
+    pub principal: String,
+    /// The ConfigMap with the `krb5.conf` of the realm, the one of the image is used if not set
+    pub krb5_config_map_name: Option<String>,
+}
+
+impl KerberosAuthentication {
+    /// The principal of the server with the given DNS name
+    pub fn server_principal(&self, host: &str) -> String {
+        self.principal.replace("{host}", host)
+    }
+}
+
+/// Decides which changes to a lower version are applied to a running cluster.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum DowngradePolicy {
+    /// Downgrades are never applied
+    Forbid,
+    /// Downgrades to another patch release of the same minor version are applied
+    SameMinor,
+    /// All downgrades are applied, older versions might not be able to read the data of newer ones
+    Force,
+}
+
+impl Default for DowngradePolicy {
+    fn default() -> Self {
+        DowngradePolicy::Forbid
+    }
+}
+
+/// Whether a server votes in the ensemble or only observes it.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, Ord, PartialEq, PartialOrd, Serialize,
+)]
+pub enum PeerType {
+    Participant,
+    Observer,
+}
+
+impl PeerType {
+    /// The name ZooKeeper uses in `peerType` and in the `server.N` entries
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerType::Participant => "participant",
+            PeerType::Observer => "observer",
+        }
+    }
+}
+
+impl Default for PeerType {
+    fn default() -> Self {
+        PeerType::Participant
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
@@ -105,30 +877,164 @@ pub struct ZookeeperClusterStatus {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     #[schemars(schema_with = "stackable_operator::conditions::schema")]
     pub conditions: Vec<Condition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rolling_restart: Option<RollingRestartStatus>,
+    /// When the last server was replaced during an upgrade, used to detect upgrades that are stuck
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub last_upgrade_progress: Option<Time>,
+    /// The version of the last upgrade that failed and was rolled back to `currentVersion`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rolled_back_version: Option<ZookeeperVersion>,
+    /// All servers of the ensemble by name (`<role group>-<ordinal>`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub servers: BTreeMap<String, ServerStatus>,
+    /// Ids of removed servers that are not reused yet, see `idQuarantineSeconds`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quarantined_ids: Vec<QuarantinedId>,
+    /// The health of the ensemble as last observed by the operator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<EnsembleHealth>,
+    /// How far quorum connections are switched to TLS, see `tls.quorum`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quorum_tls_stage: Option<QuorumTlsStage>,
+    /// How far the servers are switched to authenticate each other, see `authentication.quorum`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quorum_sasl_stage: Option<QuorumSaslStage>,
+}
+
+/// The `myid` of a removed server. Other servers may still know about it (e.g. from transactions
+/// that are not yet written to disk), so it is only reused after a while.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedId {
+    pub id: usize,
+    /// When the server was removed
+    #[schemars(with = "String")]
+    pub removed_at: Time,
+}
+
+/// The identity of a server and the node it is currently bound to.
+/// A server keeps its name, its `myid` and its DNS name when it is moved to another node, so
+/// the other members of the ensemble don't need to be told about the move.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub role_group: String,
+    /// Position of the server in its role group, from 0 to `instances - 1`
+    pub ordinal: u16,
+    /// The `myid` of the server
+    pub id: usize,
+    pub node_name: String,
+    /// Servers on the same node are told apart by their slot, see `instancesPerNode`
+    pub node_slot: u8,
+    /// Servers recorded before observers were supported are participants
+    #[serde(default)]
+    pub peer_type: PeerType,
+}
+
+impl ServerStatus {
+    /// The name servers are identified by, this is also their host name.
+    pub fn server_name(role_group: &str, ordinal: u16) -> String {
+        format!("{}-{}", role_group, ordinal).to_lowercase()
+    }
+}
+
+/// The health of the ensemble as reported by its servers (using `srvr`).
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnsembleHealth {
+    /// Whether there is a leader and a majority of the servers serves requests
+    pub quorum: bool,
+    #[schemars(with = "String")]
+    pub observed_at: Time,
+    /// The health of every server in `servers` by name
+    #[serde(default)]
+    pub servers: BTreeMap<String, ServerHealth>,
+}
+
+/// The health of a single server, everything is missing while it can't be reached.
+/// Missing values are serialized as `null`, so they are removed by merge patches.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerHealth {
+    /// `leader`, `follower`, `observer` or `standalone`, missing while the server doesn't serve
+    /// requests
+    pub mode: Option<String>,
+    /// The last transaction id the server has seen (e.g. `0x100000002`)
+    pub zxid: Option<String>,
+    /// Requests that are queued because the server can't keep up
+    pub outstanding_requests: Option<u64>,
+    /// Request latencies in milliseconds
+    pub min_latency: Option<u64>,
+    pub avg_latency: Option<f64>,
+    pub max_latency: Option<u64>,
+}
+
+/// Progress of a rolling restart of servers whose configuration is outdated.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollingRestartStatus {
+    /// The pod that was restarted last, it needs to rejoin the ensemble before the next one is restarted
+    pub current_pod: String,
+    /// Pods that still run with an outdated configuration
+    #[serde(default)]
+    pub pending_pods: Vec<String>,
 }
 
 impl ZookeeperClusterStatus {
     pub fn target_image_name(&self) -> Option<String> {
         self.target_version
             .as_ref()
-            .map(|version| format!("stackable/zookeeper:{}", version.to_string()))
+            .and_then(|version| version.image_name().ok())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ZookeeperVersion;
+    use crate::{
+        ClientAuth, DowngradePolicy, HostPathStorage, KeyStoreFormat, PeerType,
+        PersistentVolumeClaimStorage, QuorumSaslStage, QuorumTlsStage, TlsConfig, ZookeeperCluster,
+        ZookeeperClusterSpec, ZookeeperConfig, ZookeeperStorage, ZookeeperVersion,
+        DEFAULT_CLIENT_PORT, DEFAULT_DATA_LOG_DIR, DEFAULT_INIT_LIMIT, DEFAULT_INSTANCES,
+        DEFAULT_INSTANCES_PER_NODE, DEFAULT_LEADER_ELECTION_PORT, DEFAULT_QUORUM_PORT,
+        DEFAULT_SECURE_CLIENT_PORT,
+    };
+    use indoc::indoc;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+    use rstest::rstest;
+    use serde_json::json;
     use std::str::FromStr;
+    use std::time::Duration;
+
+    fn version(version: &str) -> ZookeeperVersion {
+        ZookeeperVersion::from_str(version).unwrap()
+    }
 
     #[test]
     fn test_version_upgrade() {
-        assert!(ZookeeperVersion::v3_4_14
-            .is_valid_upgrade(&ZookeeperVersion::v3_5_8)
-            .unwrap());
+        assert!(version("3.4.14").is_valid_upgrade(&version("3.5.8")));
+
+        assert!(!version("3.5.8").is_valid_upgrade(&version("3.4.14")));
+    }
 
-        assert!(!ZookeeperVersion::v3_5_8
-            .is_valid_upgrade(&ZookeeperVersion::v3_4_14)
-            .unwrap());
+    #[rstest]
+    #[case::upgrade("3.4.14", "3.5.8", DowngradePolicy::Forbid, true)]
+    #[case::same_version("3.5.8", "3.5.8", DowngradePolicy::Forbid, true)]
+    #[case::forbidden("3.5.8", "3.4.14", DowngradePolicy::Forbid, false)]
+    #[case::same_minor("3.5.9", "3.5.8", DowngradePolicy::SameMinor, true)]
+    #[case::other_minor("3.5.8", "3.4.14", DowngradePolicy::SameMinor, false)]
+    #[case::forced("3.5.8", "3.4.14", DowngradePolicy::Force, true)]
+    #[case::unsupported("3.5.8", "3.9.0", DowngradePolicy::Force, false)]
+    fn test_check_version_change(
+        #[case] from: &str,
+        #[case] to: &str,
+        #[case] downgrade_policy: DowngradePolicy,
+        #[case] allowed: bool,
+    ) {
+        let result = version(from)
+            .check_version_change(&ZookeeperVersion::parse(to).unwrap(), downgrade_policy);
+        assert_eq!(result.is_ok(), allowed, "{:?}", result);
     }
 
     #[test]
@@ -141,15 +1047,390 @@ mod tests {
     #[test]
     fn test_package_name() {
         assert_eq!(
-            ZookeeperVersion::v3_4_14.package_name(),
-            format!("zookeeper-{}", ZookeeperVersion::v3_4_14.to_string())
+            version("3.4.14").package_name().unwrap(),
+            format!("zookeeper-{}", version("3.4.14").to_string())
         );
         assert_eq!(
-            ZookeeperVersion::v3_5_8.package_name(),
-            format!(
-                "apache-zookeeper-{}-bin",
-                ZookeeperVersion::v3_5_8.to_string()
-            )
+            version("3.5.8").package_name().unwrap(),
+            format!("apache-zookeeper-{}-bin", version("3.5.8").to_string())
+        );
+    }
+
+    #[test]
+    fn test_merged_config() {
+        let spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.4.14
+            config:
+              tickTime: 3000
+              initLimit: 10
+            servers:
+              selectors:
+                default:
+                  selector:
+                    matchLabels:
+                      kubernetes.io/hostname: debian
+                  instances: 1
+                  instancesPerNode: 1
+                  config:
+                    initLimit: 20
+                    maxClientCnxns: 100
+        "})
+        .unwrap();
+
+        let config = spec.merged_config("default");
+        assert_eq!(config.tick_time, Some(3000));
+        assert_eq!(config.init_limit, Some(20));
+        assert_eq!(config.max_client_cnxns, Some(100));
+        assert_eq!(config.sync_limit, None);
+
+        assert_eq!(config.storage(), ZookeeperStorage::default());
+
+        let config = spec.merged_config("unknown");
+        assert_eq!(config.init_limit, Some(10));
+        assert_eq!(config.max_client_cnxns, None);
+    }
+
+    #[test]
+    fn test_tls() {
+        let spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.5.8
+            servers:
+              selectors:
+                default: {}
+            tls:
+              secretName: simple-tls
+        "})
+        .unwrap();
+
+        let tls = spec.tls.unwrap();
+        assert_eq!(tls.secret_name, Some("simple-tls".to_string()));
+        assert!(!tls.is_managed());
+        assert_eq!(tls.format(), KeyStoreFormat::Jks);
+        assert_eq!(tls.client_auth(), ClientAuth::Need);
+        assert_eq!(tls.format().keystore_file(), "keystore.jks");
+        assert!(tls.format().has_password());
+
+        let tls: TlsConfig = serde_yaml::from_str(indoc! {"
+            secretName: simple-tls
+            format: Pem
+            clientAuth: None
+        "})
+        .unwrap();
+        assert_eq!(tls.format().store_type(), "PEM");
+        assert_eq!(tls.format().truststore_file(), "truststore.pem");
+        assert!(!tls.format().has_password());
+        assert_eq!(tls.client_auth().as_str(), "none");
+        assert!(!tls.quorum());
+    }
+
+    #[test]
+    fn test_managed_tls() {
+        let tls: TlsConfig = serde_yaml::from_str(indoc! {"
+            format: Pkcs12
+        "})
+        .unwrap();
+        assert!(tls.is_managed());
+        assert_eq!(tls.ca_secret_name("simple"), "simple-tls-ca");
+        assert_eq!(
+            tls.certificate_validity(),
+            Duration::from_secs(90 * 24 * 60 * 60)
         );
+
+        let tls: TlsConfig = serde_yaml::from_str(indoc! {"
+            caSecretName: company-ca
+            certificateValidityDays: 7
+        "})
+        .unwrap();
+        assert_eq!(tls.ca_secret_name("simple"), "company-ca");
+        assert_eq!(
+            tls.certificate_validity(),
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn test_authentication() {
+        let spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.5.8
+            servers:
+              selectors:
+                default: {}
+            authentication:
+              digest:
+                secretName: simple-users
+              kerberos:
+                keytabSecretName: simple-keytab
+                principal: zookeeper/{host}@EXAMPLE.COM
+        "})
+        .unwrap();
+
+        let authentication = spec.authentication.unwrap();
+        assert!(!authentication.require_client_auth());
+        assert!(authentication.authenticates_clients());
+        assert!(!authentication.quorum());
+        assert_eq!(
+            authentication.digest.unwrap().secret_name,
+            "simple-users".to_string()
+        );
+        let kerberos = authentication.kerberos.unwrap();
+        assert_eq!(
+            kerberos.server_principal("default-0.simple-servers.default.svc"),
+            "zookeeper/default-0.simple-servers.default.svc@EXAMPLE.COM"
+        );
+        assert_eq!(kerberos.krb5_config_map_name, None);
+    }
+
+    #[rstest]
+    #[case::upgrade(
+        QuorumTlsStage::Plaintext,
+        QuorumTlsStage::Tls,
+        Some(QuorumTlsStage::PortUnification)
+    )]
+    #[case::upgrade_continued(
+        QuorumTlsStage::PortUnification,
+        QuorumTlsStage::Tls,
+        Some(QuorumTlsStage::TlsWithPortUnification)
+    )]
+    #[case::upgrade_finished(
+        QuorumTlsStage::TlsWithPortUnification,
+        QuorumTlsStage::Tls,
+        Some(QuorumTlsStage::Tls)
+    )]
+    #[case::downgrade(
+        QuorumTlsStage::Tls,
+        QuorumTlsStage::Plaintext,
+        Some(QuorumTlsStage::TlsWithPortUnification)
+    )]
+    #[case::downgrade_finished(
+        QuorumTlsStage::PortUnification,
+        QuorumTlsStage::Plaintext,
+        Some(QuorumTlsStage::Plaintext)
+    )]
+    #[case::done(QuorumTlsStage::Tls, QuorumTlsStage::Tls, None)]
+    fn test_quorum_tls_stage_next_towards(
+        #[case] current: QuorumTlsStage,
+        #[case] target: QuorumTlsStage,
+        #[case] expected: Option<QuorumTlsStage>,
+    ) {
+        assert_eq!(current.next_towards(target), expected);
+    }
+
+    #[test]
+    fn test_target_quorum_tls_stage() {
+        let mut spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.5.8
+            servers:
+              selectors:
+                default: {}
+            tls:
+              secretName: simple-tls
+              quorum: true
+        "})
+        .unwrap();
+        assert_eq!(spec.target_quorum_tls_stage(), QuorumTlsStage::Tls);
+
+        spec.tls.as_mut().unwrap().quorum = Some(false);
+        assert_eq!(spec.target_quorum_tls_stage(), QuorumTlsStage::Plaintext);
+
+        spec.tls = None;
+        assert_eq!(spec.target_quorum_tls_stage(), QuorumTlsStage::Plaintext);
+    }
+
+    #[rstest]
+    #[case::upgrade(
+        QuorumSaslStage::Disabled,
+        QuorumSaslStage::Required,
+        Some(QuorumSaslStage::Enabled)
+    )]
+    #[case::upgrade_continued(
+        QuorumSaslStage::Enabled,
+        QuorumSaslStage::Required,
+        Some(QuorumSaslStage::LearnerRequired)
+    )]
+    #[case::downgrade(
+        QuorumSaslStage::Required,
+        QuorumSaslStage::Disabled,
+        Some(QuorumSaslStage::LearnerRequired)
+    )]
+    #[case::done(QuorumSaslStage::Disabled, QuorumSaslStage::Disabled, None)]
+    fn test_quorum_sasl_stage_next_towards(
+        #[case] current: QuorumSaslStage,
+        #[case] target: QuorumSaslStage,
+        #[case] expected: Option<QuorumSaslStage>,
+    ) {
+        assert_eq!(current.next_towards(target), expected);
+    }
+
+    #[test]
+    fn test_target_quorum_sasl_stage() {
+        let mut spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.5.8
+            servers:
+              selectors:
+                default: {}
+            authentication:
+              quorum: true
+              quorumSecretName: simple-quorum
+        "})
+        .unwrap();
+        assert_eq!(spec.target_quorum_sasl_stage(), QuorumSaslStage::Required);
+        assert!(!spec
+            .authentication
+            .as_ref()
+            .unwrap()
+            .authenticates_clients());
+
+        spec.authentication.as_mut().unwrap().quorum = Some(false);
+        assert_eq!(spec.target_quorum_sasl_stage(), QuorumSaslStage::Disabled);
+
+        spec.authentication = None;
+        assert_eq!(spec.target_quorum_sasl_stage(), QuorumSaslStage::Disabled);
+    }
+
+    #[test]
+    fn test_role_groups() {
+        let spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.5.8
+            servers:
+              selectors:
+                primary: {}
+                secondary: {}
+            observers:
+              selectors:
+                remote:
+                  instances: 2
+                  config:
+                    clientPort: 12181
+        "})
+        .unwrap();
+
+        let role_groups = spec
+            .role_groups()
+            .into_iter()
+            .map(|(name, _, peer_type)| (name.as_str(), peer_type))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            role_groups,
+            vec![
+                ("primary", PeerType::Participant),
+                ("remote", PeerType::Observer),
+                ("secondary", PeerType::Participant)
+            ]
+        );
+
+        let (remote, peer_type) = spec.role_group("remote").unwrap();
+        assert_eq!(remote.instances, 2);
+        assert_eq!(peer_type, PeerType::Observer);
+        assert_eq!(spec.merged_config("remote").client_port(), 12181);
+        assert!(spec.role_group("unknown").is_none());
+    }
+
+    #[test]
+    fn test_role_group_defaults() {
+        let spec: ZookeeperClusterSpec = serde_yaml::from_str(indoc! {"
+            version: 3.4.14
+            servers:
+              selectors:
+                default: {}
+        "})
+        .unwrap();
+
+        let role_group = spec.servers.selectors.get("default").unwrap();
+        assert_eq!(role_group.instances, DEFAULT_INSTANCES);
+        assert_eq!(role_group.instances_per_node, DEFAULT_INSTANCES_PER_NODE);
+        assert_eq!(role_group.selector(), LabelSelector::default());
+    }
+
+    #[test]
+    fn test_config_with_defaults() {
+        let config = ZookeeperConfig {
+            tick_time: Some(3000),
+            max_client_cnxns: Some(100),
+            ..ZookeeperConfig::default()
+        }
+        .with_defaults();
+
+        assert_eq!(config.tick_time, Some(3000));
+        assert_eq!(config.init_limit, Some(DEFAULT_INIT_LIMIT));
+        assert_eq!(config.client_port, Some(DEFAULT_CLIENT_PORT));
+        assert_eq!(config.data_log_dir.as_deref(), Some(DEFAULT_DATA_LOG_DIR));
+        assert_eq!(config.max_client_cnxns, Some(100));
+        assert_eq!(config.min_session_timeout, None);
+        assert_eq!(config.storage, None);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_crd_defaults() {
+        let crd = serde_json::to_value(ZookeeperCluster::crd()).unwrap();
+        let spec = crd
+            .pointer("/spec/versions/0/schema/openAPIV3Schema/properties/spec/properties")
+            .unwrap();
+        let default = |path: &str| spec.pointer(&format!("{}/default", path)).cloned();
+
+        assert_eq!(default("/downgradePolicy"), Some(json!("Forbid")));
+        assert_eq!(default("/idQuarantineSeconds"), Some(json!(3600)));
+        assert_eq!(default("/perServerServices"), Some(json!(false)));
+        assert_eq!(default("/config"), Some(json!({})));
+        assert_eq!(default("/config/properties/clientPort"), Some(json!(2181)));
+        assert_eq!(
+            default("/config/properties/dataDir"),
+            Some(json!("/var/lib/zookeeper/data"))
+        );
+        assert_eq!(default("/config/properties/maxClientCnxns"), None);
+
+        // Role groups must not get the defaults, they would override the cluster wide values
+        let role_group = "/servers/properties/selectors/additionalProperties/properties";
+        assert_eq!(
+            default(&format!("{}/config/properties/clientPort", role_group)),
+            None
+        );
+        assert_eq!(
+            default(&format!("{}/selector", role_group)),
+            Some(json!({}))
+        );
+    }
+
+    #[test]
+    fn test_config_for_node_slot() {
+        let config = ZookeeperConfig {
+            client_port: Some(12181),
+            data_dir: Some("/data/zk".to_string()),
+            ..ZookeeperConfig::default()
+        };
+
+        assert_eq!(config.for_node_slot(0), config);
+
+        let config = config.for_node_slot(2);
+        assert_eq!(config.client_port(), 12183);
+        assert_eq!(config.secure_client_port(), DEFAULT_SECURE_CLIENT_PORT + 2);
+        assert_eq!(config.quorum_port(), DEFAULT_QUORUM_PORT + 2);
+        assert_eq!(
+            config.leader_election_port(),
+            DEFAULT_LEADER_ELECTION_PORT + 2
+        );
+        assert_eq!(config.data_dir(), "/data/zk-2");
+        assert_eq!(config.data_log_dir(), format!("{}-2", DEFAULT_DATA_LOG_DIR));
+    }
+
+    #[rstest]
+    #[case::empty(ZookeeperConfig::default(), true)]
+    #[case::valid(ZookeeperConfig { tick_time: Some(2000), autopurge_snap_retain_count: Some(3), min_session_timeout: Some(4000), max_session_timeout: Some(4000), ..ZookeeperConfig::default() }, true)]
+    #[case::zero_tick_time(ZookeeperConfig { tick_time: Some(0), ..ZookeeperConfig::default() }, false)]
+    #[case::zero_sync_limit(ZookeeperConfig { sync_limit: Some(0), ..ZookeeperConfig::default() }, false)]
+    #[case::too_few_snapshots(ZookeeperConfig { autopurge_snap_retain_count: Some(2), ..ZookeeperConfig::default() }, false)]
+    #[case::min_above_max_timeout(ZookeeperConfig { min_session_timeout: Some(5000), max_session_timeout: Some(4000), ..ZookeeperConfig::default() }, false)]
+    #[case::custom_ports(ZookeeperConfig { client_port: Some(12181), quorum_port: Some(12888), leader_election_port: Some(13888), ..ZookeeperConfig::default() }, true)]
+    #[case::zero_port(ZookeeperConfig { client_port: Some(0), ..ZookeeperConfig::default() }, false)]
+    #[case::port_collision_with_default(ZookeeperConfig { client_port: Some(2888), ..ZookeeperConfig::default() }, false)]
+    #[case::port_collision(ZookeeperConfig { quorum_port: Some(4000), leader_election_port: Some(4000), ..ZookeeperConfig::default() }, false)]
+    #[case::secure_port_collision(ZookeeperConfig { secure_client_port: Some(2181), ..ZookeeperConfig::default() }, false)]
+    #[case::custom_dirs(ZookeeperConfig { data_dir: Some("/data/zk".to_string()), data_log_dir: Some("/log/zk".to_string()), ..ZookeeperConfig::default() }, true)]
+    #[case::relative_data_dir(ZookeeperConfig { data_dir: Some("data".to_string()), ..ZookeeperConfig::default() }, false)]
+    #[case::same_dirs(ZookeeperConfig { data_dir: Some("/data".to_string()), data_log_dir: Some("/data".to_string()), ..ZookeeperConfig::default() }, false)]
+    #[case::empty_pvc_size(ZookeeperConfig { storage: Some(ZookeeperStorage { host_path: None, persistent_volume_claim: Some(PersistentVolumeClaimStorage { storage_class_name: None, size: "".to_string() }) }), ..ZookeeperConfig::default() }, false)]
+    #[case::two_storage_sources(ZookeeperConfig { storage: Some(ZookeeperStorage { host_path: Some(HostPathStorage::default()), persistent_volume_claim: Some(PersistentVolumeClaimStorage { storage_class_name: None, size: "1Gi".to_string() }) }), ..ZookeeperConfig::default() }, false)]
+    fn test_validate_config(#[case] config: ZookeeperConfig, #[case] valid: bool) {
+        assert_eq!(config.validate().is_ok(), valid);
     }
 }
@@ -1,19 +1,12 @@
 use crate::error::Error::{
-    IllegalZnode, IllegalZookeeperPath, ObjectWithoutName, OperatorFrameworkError,
-    PodMissingLabels, PodWithoutHostname,
+    ClusterWithoutServers, IllegalZnode, IllegalZookeeperPath, OperatorFrameworkError,
 };
 use crate::error::ZookeeperOperatorResult;
-use crate::util::TicketReferences::ErrZkPodWithoutName;
-use crate::{ZookeeperCluster, ZookeeperClusterSpec, APP_NAME, MANAGED_BY};
-use k8s_openapi::api::core::v1::Pod;
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use crate::{ServerStatus, ZookeeperCluster, ZookeeperClusterSpec};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use stackable_operator::client::Client;
 use stackable_operator::error::OperatorResult;
-use stackable_operator::labels::{
-    APP_INSTANCE_LABEL, APP_MANAGED_BY_LABEL, APP_NAME_LABEL, APP_ROLE_GROUP_LABEL,
-};
 use std::collections::{BTreeMap, HashSet};
 use std::string::ToString;
 use strum_macros::Display;
@@ -51,10 +44,18 @@ pub struct ZookeeperConnectionInformation {
     //  - server1:2181,server2:2181
     //  - server1:2181,server2:2181/application
     pub connection_string: String,
+    // Whether clients need to connect using TLS, the connection string contains the secure
+    // client ports in this case
+    pub tls: bool,
 }
 
 /// Returns connection information for a ZookeeperCluster custom resource
 ///
+/// The connection string lists the DNS names of the servers recorded in the status of the
+/// cluster, they are registered with its headless Service and don't change when a server is
+/// moved to another node. It is the same connection string the operator publishes in the
+/// discovery ConfigMap of the cluster.
+///
 /// # Arguments
 ///
 /// * `client` - A [`stackable_operator::client::Client`] used to access the Kubernetes cluster
@@ -79,14 +80,24 @@ pub async fn get_zk_connection_info(
     let zk_cluster =
         check_zookeeper_reference(client, &zk_reference.name, &zk_reference.namespace).await?;
 
-    let zk_pods = client
-        .list_with_label_selector(None, &get_match_labels(&zk_reference.name))
-        .await?;
-
-    let connection_string =
-        get_zk_connection_string_from_pods(zk_cluster.spec, zk_pods, clean_chroot.as_deref())?;
-
-    Ok(ZookeeperConnectionInformation { connection_string })
+    let servers = zk_cluster
+        .status
+        .map(|status| status.servers)
+        .unwrap_or_default();
+
+    let tls = zk_cluster.spec.tls.is_some();
+    let connection_string = get_zk_connection_string(
+        &zk_reference.name,
+        &zk_reference.namespace,
+        &zk_cluster.spec,
+        &servers,
+        clean_chroot.as_deref(),
+    )?;
+
+    Ok(ZookeeperConnectionInformation {
+        connection_string,
+        tls,
+    })
 }
 
 // Left pads the chroot string with a / if necessary - mostly for convenience, so users do not
@@ -233,20 +244,6 @@ fn contains_illegal_character(node_name: &str) -> HashSet<char> {
         .collect()
 }
 
-// Build a Labelselector that applies only to pods belonging to the cluster instance referenced
-// by `name`
-fn get_match_labels(name: &str) -> LabelSelector {
-    let mut zk_pod_matchlabels = BTreeMap::new();
-    zk_pod_matchlabels.insert(String::from(APP_NAME_LABEL), String::from(APP_NAME));
-    zk_pod_matchlabels.insert(String::from(APP_MANAGED_BY_LABEL), String::from(MANAGED_BY));
-    zk_pod_matchlabels.insert(String::from(APP_INSTANCE_LABEL), name.to_string());
-
-    LabelSelector {
-        match_labels: Some(zk_pod_matchlabels),
-        ..Default::default()
-    }
-}
-
 // Check in kubernetes, whether the zookeeper object referenced by `zk_name` and `zk_namespace`
 // exists.
 // If it exists the object will be returned
@@ -271,61 +268,35 @@ async fn check_zookeeper_reference(
                     OperatorFrameworkError {source: err}})
 }
 
-// Builds the actual connection string after all necessary information has been retrieved.
-// Takes a list of pods belonging to this cluster from which the hostnames are retrieved and
-// the cluster spec itself, from which the port per host will be retrieved (unimplemented at
-// this time)
-fn get_zk_connection_string_from_pods(
-    zookeeper_spec: ZookeeperClusterSpec,
-    zk_pods: Vec<Pod>,
+// Builds the actual connection string from the servers recorded in the status of the cluster.
+// The servers are addressed by their DNS names and client ports, like in the discovery ConfigMap
+// the operator publishes for the cluster.
+fn get_zk_connection_string(
+    cluster_name: &str,
+    namespace: &str,
+    zookeeper_spec: &ZookeeperClusterSpec,
+    servers: &BTreeMap<String, ServerStatus>,
     chroot: Option<&str>,
 ) -> ZookeeperOperatorResult<String> {
     if let Some(chroot) = chroot {
         is_valid_zookeeper_path(chroot)?;
     }
-    let mut server_and_port_list = Vec::new();
-
-    for pod in zk_pods {
-        let pod_name = match pod.metadata.name {
-            None => {
-                return Err(ObjectWithoutName {
-                    reference: ErrZkPodWithoutName.to_string(),
-                })
-            }
-            Some(pod_name) => pod_name,
-        };
-
-        let node_name = match pod.spec.and_then(|spec| spec.node_name) {
-            None => {
-                debug!("Pod [{:?}] is does not have node_name set, might not be scheduled yet, aborting.. ",
-                       pod_name);
-                return Err(PodWithoutHostname { pod: pod_name });
-            }
-            Some(node_name) => node_name,
-        };
-
-        let role_group = match pod
-            .metadata
-            .labels
-            .unwrap_or_default()
-            .get(APP_ROLE_GROUP_LABEL)
-        {
-            None => {
-                return Err(PodMissingLabels {
-                    labels: vec![String::from(APP_ROLE_GROUP_LABEL)],
-                    pod: pod_name,
-                })
-            }
-            Some(role_group) => role_group.to_owned(),
-        };
-
-        server_and_port_list.push((node_name, get_zk_port(&zookeeper_spec, &role_group)?));
+    if servers.is_empty() {
+        debug!(
+            "ZooKeeper cluster [{}/{}] has no servers yet, aborting.. ",
+            namespace, cluster_name
+        );
+        return Err(ClusterWithoutServers {
+            cluster: format!("{}/{}", namespace, cluster_name),
+        });
     }
 
-    // Sort list by hostname to make resulting connection strings predictable
+    // Sort list by hostname and port to make resulting connection strings predictable
     // Shouldn't matter for connectivity but makes testing easier and avoids unnecessary
     // changes to the infrastructure
-    server_and_port_list.sort_by(|(host1, _), (host2, _)| host1.cmp(host2));
+    let mut server_and_port_list =
+        server_client_addresses(cluster_name, namespace, zookeeper_spec, servers);
+    server_and_port_list.sort();
 
     let conn_string = server_and_port_list
         .iter()
@@ -340,15 +311,44 @@ fn get_zk_connection_string_from_pods(
     }
 }
 
-// Retrieve the port for the specified rolegroup from the cluster spec
-// TODO: Currently hard coded to 2181 as we do not support this setting yet.
-//  Depends on https://github.com/stackabletech/zookeeper-operator/pull/71
-//  Depends on https://github.com/stackabletech/zookeeper-operator/issues/85
-fn get_zk_port(
-    _zk_cluster: &ZookeeperClusterSpec,
-    _role_group: &str,
-) -> ZookeeperOperatorResult<u16> {
-    Ok(2181)
+/// The name of the headless Service the servers of a cluster are registered with.
+pub fn headless_service_name(cluster_name: &str) -> String {
+    format!("{}-servers", cluster_name)
+}
+
+/// The DNS name of a server, it is registered with the headless Service.
+pub fn server_dns_name(server_name: &str, cluster_name: &str, namespace: &str) -> String {
+    format!(
+        "{}.{}.{}.svc",
+        server_name,
+        headless_service_name(cluster_name),
+        namespace
+    )
+}
+
+/// The DNS name and the client port of every server, the ports are the secure client ports if
+/// clients need to use TLS. Role group settings take precedence over the cluster wide ones and
+/// servers sharing a node use the ports of their node slot.
+pub fn server_client_addresses(
+    cluster_name: &str,
+    namespace: &str,
+    zookeeper_spec: &ZookeeperClusterSpec,
+    servers: &BTreeMap<String, ServerStatus>,
+) -> Vec<(String, u16)> {
+    servers
+        .iter()
+        .map(|(name, server)| {
+            let config = zookeeper_spec
+                .merged_config(&server.role_group)
+                .for_node_slot(server.node_slot);
+            let port = if zookeeper_spec.tls.is_some() {
+                config.secure_client_port()
+            } else {
+                config.client_port()
+            };
+            (server_dns_name(name, cluster_name, namespace), port)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -357,35 +357,6 @@ mod tests {
     use indoc::indoc;
     use rstest::rstest;
 
-    #[test]
-    fn get_labels_from_name() {
-        let test_name = "testcluster";
-        let selector = get_match_labels(test_name);
-
-        assert!(selector.match_expressions.is_none());
-
-        let generated_labels_selector = selector.match_labels.expect("labels were None");
-        assert!(generated_labels_selector.len() == 3);
-        assert_eq!(
-            generated_labels_selector
-                .get("app.kubernetes.io/name")
-                .unwrap(),
-            "zookeeper"
-        );
-        assert_eq!(
-            generated_labels_selector
-                .get("app.kubernetes.io/instance")
-                .unwrap(),
-            "testcluster"
-        );
-        assert_eq!(
-            generated_labels_selector
-                .get("app.kubernetes.io/managed-by")
-                .unwrap(),
-            "stackable-zookeeper"
-        );
-    }
-
     #[rstest]
     #[case::simple("/test")]
     #[case::spade_multiple_periods("/Spade: ♠/aite.../test12")]
@@ -460,112 +431,168 @@ mod tests {
     }
 
     #[rstest]
-    #[case::single_pod_no_chroot(
+    #[case::single_server_no_chroot(
       indoc! {"
         version: 3.4.14
         servers:
           selectors:
             default:
-              selector:
-                matchLabels:
-                  kubernetes.io/hostname: debian
               instances: 1
-              instancesPerNode: 1
       "},
       indoc! {"
-        - apiVersion: v1
-          kind: Pod
-          metadata:
-            name: test
-            labels:
-              app.kubernetes.io/name: zookeeper
-              app.kubernetes.io/role-group: default
-              app.kubernetes.io/instance: test
-          spec:
-            nodeName: debian
-            containers: []
+        default-0:
+          roleGroup: default
+          ordinal: 0
+          id: 1
+          nodeName: debian
+          nodeSlot: 0
       "},
       None,
-      "debian:2181"
+      "default-0.test-servers.default.svc:2181"
     )]
-    #[case::single_pod_with_chroot(
+    #[case::single_server_with_chroot(
       indoc! {"
         version: 3.4.14
         servers:
           selectors:
             default:
-              selector:
-                matchLabels:
-                  kubernetes.io/hostname: worker-1.stackable.tech
               instances: 1
-              instancesPerNode: 1
       "},
       indoc! {"
-        - apiVersion: v1
-          kind: Pod
-          metadata:
-            name: test
-            labels:
-              app.kubernetes.io/name: zookeeper
-              app.kubernetes.io/role-group: default
-              app.kubernetes.io/instance: test
-          spec:
-            nodeName: worker-1.stackable.tech
-            containers: []
+        default-0:
+          roleGroup: default
+          ordinal: 0
+          id: 1
+          nodeName: worker-1.stackable.tech
+          nodeSlot: 0
       "},
       Some("/dev"),
-      "worker-1.stackable.tech:2181/dev"
+      "default-0.test-servers.default.svc:2181/dev"
     )]
-    #[case::multiple_pods_wrong_order(
+    #[case::multiple_servers(
       indoc! {"
         version: 3.4.14
         servers:
           selectors:
             default:
-              selector:
-                matchLabels:
-                  kubernetes.io/hostname: debian
-              instances: 1
-              instancesPerNode: 1
+              instances: 2
       "},
       indoc! {"
-        - apiVersion: v1
-          kind: Pod
-          metadata:
-            name: test
-            labels:
-              app.kubernetes.io/name: zookeeper
-              app.kubernetes.io/role-group: default
-              app.kubernetes.io/instance: test
-          spec:
-            nodeName: worker-2.stackable.demo
-            containers: []
-        - apiVersion: v1
-          kind: Pod
-          metadata:
-            name: test
-            labels:
-              app.kubernetes.io/name: zookeeper
-              app.kubernetes.io/role-group: default
-              app.kubernetes.io/instance: test
-          spec:
-            nodeName: worker-1.stackable.demo
-            containers: []
+        default-1:
+          roleGroup: default
+          ordinal: 1
+          id: 2
+          nodeName: worker-1.stackable.demo
+          nodeSlot: 0
+        default-0:
+          roleGroup: default
+          ordinal: 0
+          id: 1
+          nodeName: worker-2.stackable.demo
+          nodeSlot: 0
       "},
       Some("/prod"),
-      "worker-1.stackable.demo:2181,worker-2.stackable.demo:2181/prod"
+      "default-0.test-servers.default.svc:2181,default-1.test-servers.default.svc:2181/prod"
+    )]
+    #[case::role_groups_with_different_ports(
+      indoc! {"
+        version: 3.4.14
+        config:
+          clientPort: 2182
+        servers:
+          selectors:
+            default:
+              instances: 1
+            custom:
+              instances: 1
+              config:
+                clientPort: 12181
+      "},
+      indoc! {"
+        custom-0:
+          roleGroup: custom
+          ordinal: 0
+          id: 2
+          nodeName: worker-2.stackable.demo
+          nodeSlot: 0
+        default-0:
+          roleGroup: default
+          ordinal: 0
+          id: 1
+          nodeName: worker-1.stackable.demo
+          nodeSlot: 0
+      "},
+      None,
+      "custom-0.test-servers.default.svc:12181,default-0.test-servers.default.svc:2182"
+    )]
+    #[case::several_servers_per_node(
+      indoc! {"
+        version: 3.4.14
+        servers:
+          selectors:
+            default:
+              instances: 2
+              instancesPerNode: 2
+      "},
+      indoc! {"
+        default-0:
+          roleGroup: default
+          ordinal: 0
+          id: 1
+          nodeName: worker-1.stackable.demo
+          nodeSlot: 0
+        default-1:
+          roleGroup: default
+          ordinal: 1
+          id: 2
+          nodeName: worker-1.stackable.demo
+          nodeSlot: 1
+      "},
+      None,
+      "default-0.test-servers.default.svc:2181,default-1.test-servers.default.svc:2182"
+    )]
+    #[case::tls(
+      indoc! {"
+        version: 3.5.8
+        servers:
+          selectors:
+            default:
+              instances: 1
+            custom:
+              instances: 1
+              config:
+                secureClientPort: 12281
+        tls:
+          secretName: test-tls
+      "},
+      indoc! {"
+        custom-0:
+          roleGroup: custom
+          ordinal: 0
+          id: 2
+          nodeName: worker-2.stackable.demo
+          nodeSlot: 0
+        default-0:
+          roleGroup: default
+          ordinal: 0
+          id: 1
+          nodeName: worker-1.stackable.demo
+          nodeSlot: 1
+      "},
+      None,
+      "custom-0.test-servers.default.svc:12281,default-0.test-servers.default.svc:2282"
     )]
     fn get_connection_string(
         #[case] zookeeper_spec: &str,
-        #[case] zk_pods: &str,
+        #[case] servers: &str,
         #[case] chroot: Option<&str>,
         #[case] expected_result: &str,
     ) {
-        let pods = parse_pod_list_from_yaml(zk_pods);
+        let servers = parse_servers_from_yaml(servers);
         let zk = parse_zk_from_yaml(zookeeper_spec);
 
-        let conn_string =
-            get_zk_connection_string_from_pods(zk, pods, chroot).expect("should not fail");
+        let conn_string = get_zk_connection_string("test", "default", &zk, &servers, chroot)
+            .expect("should not fail");
         assert_eq!(expected_result, conn_string);
     }
 
@@ -585,86 +612,24 @@ mod tests {
         );
     }
 
-    #[rstest]
-    #[case::missing_mandatory_label(
-      indoc! {"
-        version: 3.4.14
-        servers:
-            selectors:
-              default:
-                selector:
-                  matchLabels:
-                    kubernetes.io/hostname: debian
-                instances: 1
-                instancesPerNode: 1
-      "},
-      indoc! {"
-        - apiVersion: v1
-          kind: Pod
-          metadata:
-            name: test
-            labels:
-              app.kubernetes.io/name: zookeeper
-              app.kubernetes.io/instance: test
-          spec:
-            nodeName: worker-1.stackable.demo
-            containers: []
-          status:
-            phase: Running
-            conditions:
-              - type: Ready
-                status: True
-      "},
-      Some("/prod"),
-    )]
-    #[case::missing_hostname(
-      indoc! {"
-        version: 3.4.14
-        servers:
-          selectors:
-            default:
-              selector:
-                matchLabels:
-                  kubernetes.io/hostname: debian
-              instances: 1
-              instancesPerNode: 1
-      "},
-      indoc! {"
-        - apiVersion: v1
-          kind: Pod
-          metadata:
-            name: test
-            labels:
-              app.kubernetes.io/name: zookeeper
-              app.kubernetes.io/role-group: default
-              app.kubernetes.io/instance: test
-          spec:
-            containers: []
-          status:
-            phase: Running
-            conditions:
-              - type: Ready
-                status: True
-      "},
-      Some("/prod"),
-    )]
-    fn get_connection_string_should_fail(
-        #[case] zookeeper_spec: &str,
-        #[case] zk_pods: &str,
-        #[case] chroot: Option<&str>,
-    ) {
-        let pods = parse_pod_list_from_yaml(zk_pods);
-        let zk = parse_zk_from_yaml(zookeeper_spec);
+    #[test]
+    fn get_connection_string_without_servers() {
+        let zk = parse_zk_from_yaml(indoc! {"
+            version: 3.4.14
+            servers:
+              selectors:
+                default:
+                  instances: 1
+        "});
 
-        let conn_string = get_zk_connection_string_from_pods(zk, pods, chroot);
+        let conn_string =
+            get_zk_connection_string("test", "default", &zk, &BTreeMap::new(), Some("/prod"));
 
         assert!(conn_string.is_err())
     }
 
-    fn parse_pod_list_from_yaml(pod_config: &str) -> Vec<Pod> {
-        let kube_pods: Vec<k8s_openapi::api::core::v1::Pod> =
-            serde_yaml::from_str(pod_config).unwrap();
-        kube_pods.iter().map(|pod| pod.to_owned()).collect()
+    fn parse_servers_from_yaml(servers: &str) -> BTreeMap<String, ServerStatus> {
+        serde_yaml::from_str(servers).unwrap()
     }
 
     fn parse_zk_from_yaml(zk_config: &str) -> ZookeeperClusterSpec {
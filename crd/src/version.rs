@@ -0,0 +1,272 @@
+//! ZooKeeper versions and what the operator knows about them.
+//!
+//! A [`ZookeeperVersion`] can be any semantic version, the versions the operator is able to run
+//! are listed in [`SUPPORTED_VERSIONS`] together with the [`VersionTraits`] that differ between
+//! releases. Supporting a new release only requires a new entry in that table (if it isn't
+//! covered by an existing range already).
+use crate::error::{Error, ZookeeperOperatorResult};
+use crate::DowngradePolicy;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Everything that differs between ZooKeeper releases.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VersionTraits {
+    /// Name of the directory the release tarball extracts to, `{version}` is replaced with the version
+    package_name: &'static str,
+    /// Default image to run this version with, `{version}` is replaced with the version
+    image_name: &'static str,
+    /// Whether `zkServer.sh` accepts `--config <directory>`
+    pub supports_config_flag: bool,
+    /// Whether the members of an ensemble can be changed at runtime using `reconfig`
+    pub supports_dynamic_reconfig: bool,
+    /// Whether the servers can serve clients using TLS (`secureClientPort`)
+    pub supports_tls: bool,
+}
+
+/// A range of versions (in the syntax of [`VersionReq`]) and their traits.
+pub struct SupportedVersions {
+    pub range: &'static str,
+    pub traits: VersionTraits,
+}
+
+const ZOOKEEPER_3_4: VersionTraits = VersionTraits {
+    package_name: "zookeeper-{version}",
+    image_name: "stackable/zookeeper:{version}",
+    supports_config_flag: false,
+    supports_dynamic_reconfig: false,
+    supports_tls: false,
+};
+
+const ZOOKEEPER_3_5: VersionTraits = VersionTraits {
+    package_name: "apache-zookeeper-{version}-bin",
+    image_name: "stackable/zookeeper:{version}",
+    supports_config_flag: true,
+    supports_dynamic_reconfig: true,
+    supports_tls: true,
+};
+
+/// All versions the operator can run, the first matching range wins.
+pub const SUPPORTED_VERSIONS: &[SupportedVersions] = &[
+    SupportedVersions {
+        range: ">=3.4.14, <3.5.0",
+        traits: ZOOKEEPER_3_4,
+    },
+    SupportedVersions {
+        range: ">=3.5.8, <3.6.0",
+        traits: ZOOKEEPER_3_5,
+    },
+    SupportedVersions {
+        range: ">=3.6.0, <3.7.0",
+        traits: ZOOKEEPER_3_5,
+    },
+    SupportedVersions {
+        range: ">=3.7.0, <3.8.0",
+        traits: ZOOKEEPER_3_5,
+    },
+    SupportedVersions {
+        range: ">=3.8.0, <3.9.0",
+        traits: ZOOKEEPER_3_5,
+    },
+];
+
+/// A ZooKeeper version like `3.5.8`.
+///
+/// Deserializing accepts every valid semantic version so a resource with a version the operator
+/// doesn't support (anymore) can still be read, [`ZookeeperVersion::traits`] has to be used to
+/// check whether the version is supported.
+/// Parsing with [`FromStr`] only accepts supported versions.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ZookeeperVersion(Version);
+
+impl ZookeeperVersion {
+    /// Parses any valid semantic version, whether it is supported or not.
+    pub fn parse(version: &str) -> ZookeeperOperatorResult<Self> {
+        Ok(ZookeeperVersion(Version::parse(version)?))
+    }
+
+    /// Returns the traits of this version or an error if it is not supported.
+    pub fn traits(&self) -> ZookeeperOperatorResult<&'static VersionTraits> {
+        SUPPORTED_VERSIONS
+            .iter()
+            .find(|supported| {
+                VersionReq::parse(supported.range)
+                    .map(|range| range.matches(&self.0))
+                    .unwrap_or(false)
+            })
+            .map(|supported| &supported.traits)
+            .ok_or_else(|| Error::UnsupportedVersion {
+                version: self.to_string(),
+                supported: SUPPORTED_VERSIONS
+                    .iter()
+                    .map(|supported| supported.range.to_string())
+                    .collect(),
+            })
+    }
+
+    pub fn is_valid_upgrade(&self, to: &Self) -> bool {
+        to > self
+    }
+
+    /// Checks whether a running cluster may be changed from this version to `to`.
+    /// The new version needs to be supported, upgrades are always allowed and downgrades depend
+    /// on the `downgrade_policy`.
+    pub fn check_version_change(
+        &self,
+        to: &Self,
+        downgrade_policy: DowngradePolicy,
+    ) -> ZookeeperOperatorResult<()> {
+        to.traits()?;
+
+        let allowed = match downgrade_policy {
+            _ if to >= self => true,
+            DowngradePolicy::Forbid => false,
+            DowngradePolicy::SameMinor => self.0.major == to.0.major && self.0.minor == to.0.minor,
+            DowngradePolicy::Force => true,
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::DowngradeNotAllowed {
+                from: self.to_string(),
+                to: to.to_string(),
+                downgrade_policy,
+            })
+        }
+    }
+
+    /// Dynamic reconfiguration (`reconfig`) is available from ZooKeeper 3.5 onwards.
+    /// Unsupported versions never support it.
+    pub fn supports_dynamic_reconfig(&self) -> bool {
+        self.traits()
+            .map(|traits| traits.supports_dynamic_reconfig)
+            .unwrap_or(false)
+    }
+
+    /// TLS with PEM and PKCS12 stores is available from ZooKeeper 3.5.5 onwards.
+    /// Unsupported versions never support it.
+    pub fn supports_tls(&self) -> bool {
+        self.traits()
+            .map(|traits| traits.supports_tls)
+            .unwrap_or(false)
+    }
+
+    pub fn package_name(&self) -> ZookeeperOperatorResult<String> {
+        Ok(self.render(self.traits()?.package_name))
+    }
+
+    pub fn image_name(&self) -> ZookeeperOperatorResult<String> {
+        Ok(self.render(self.traits()?.image_name))
+    }
+
+    fn render(&self, template: &str) -> String {
+        template.replace("{version}", &self.to_string())
+    }
+}
+
+impl fmt::Display for ZookeeperVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ZookeeperVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = ZookeeperVersion::parse(s)?;
+        version.traits()?;
+        Ok(version)
+    }
+}
+
+impl Serialize for ZookeeperVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ZookeeperVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        ZookeeperVersion::parse(&version).map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for ZookeeperVersion {
+    fn schema_name() -> String {
+        "ZookeeperVersion".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("3.4.14", "zookeeper-3.4.14", false, false)]
+    #[case("3.5.8", "apache-zookeeper-3.5.8-bin", true, true)]
+    #[case("3.6.3", "apache-zookeeper-3.6.3-bin", true, true)]
+    #[case("3.8.0", "apache-zookeeper-3.8.0-bin", true, true)]
+    fn test_supported_versions(
+        #[case] version: &str,
+        #[case] package_name: &str,
+        #[case] supports_dynamic_reconfig: bool,
+        #[case] supports_tls: bool,
+    ) {
+        let version = ZookeeperVersion::from_str(version).unwrap();
+        assert_eq!(version.package_name().unwrap(), package_name);
+        assert_eq!(
+            version.image_name().unwrap(),
+            format!("stackable/zookeeper:{}", version)
+        );
+        assert_eq!(
+            version.supports_dynamic_reconfig(),
+            supports_dynamic_reconfig
+        );
+        assert_eq!(version.supports_tls(), supports_tls);
+    }
+
+    #[rstest]
+    #[case("3.4.13")]
+    #[case("3.5.7")]
+    #[case("3.9.0")]
+    #[case("4.0.0")]
+    fn test_unsupported_versions(#[case] version: &str) {
+        let version = ZookeeperVersion::parse(version).unwrap();
+        assert!(matches!(
+            version.traits(),
+            Err(Error::UnsupportedVersion { .. })
+        ));
+        assert!(!version.supports_dynamic_reconfig());
+        assert!(!version.supports_tls());
+        assert!(version.package_name().is_err());
+    }
+
+    #[test]
+    fn test_serde() {
+        let version: ZookeeperVersion = serde_yaml::from_str("3.9.1").unwrap();
+        assert_eq!(version, ZookeeperVersion::parse("3.9.1").unwrap());
+        assert_eq!(serde_json::to_string(&version).unwrap(), "\"3.9.1\"");
+
+        serde_yaml::from_str::<ZookeeperVersion>("latest").unwrap_err();
+    }
+
+    #[test]
+    fn test_ranges_are_valid() {
+        for supported in SUPPORTED_VERSIONS {
+            VersionReq::parse(supported.range).unwrap();
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! The `ZookeeperZnode` custom resource gives an application its own znode in an ensemble.
+//!
+//! The operator creates the znode with a unique path, publishes a discovery ConfigMap with a
+//! connection string chrooted to it and deletes the znode with everything below it when the
+//! object is deleted.
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use stackable_operator::Crd;
+
+#[derive(Clone, CustomResource, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[kube(
+    group = "zookeeper.stackable.tech",
+    version = "v1",
+    kind = "ZookeeperZnode",
+    shortname = "znode",
+    namespaced
+)]
+#[kube(status = "ZookeeperZnodeStatus")]
+#[serde(rename_all = "camelCase")]
+pub struct ZookeeperZnodeSpec {
+    /// The cluster the znode is created in
+    pub cluster_ref: ZookeeperClusterRef,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ZookeeperClusterRef {
+    pub name: String,
+    /// Defaults to the namespace of the `ZookeeperZnode`
+    pub namespace: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ZookeeperZnodeStatus {
+    /// The path of the znode, it is chosen once and never changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub znode_path: Option<String>,
+}
+
+impl Crd for ZookeeperZnode {
+    const RESOURCE_NAME: &'static str = "zookeeperznodes.zookeeper.stackable.tech";
+    const CRD_DEFINITION: &'static str = include_str!("../../deploy/crd/zookeeperznode.crd.yaml");
+}
+
+/// The path of the znode for the `ZookeeperZnode` with the given uid. The uid makes it unique
+/// across namespaces and across objects that reuse the name of a deleted one.
+pub fn znode_path(uid: &str) -> String {
+    format!("/znode-{}", uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::is_valid_zookeeper_path;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_znode() {
+        let znode: ZookeeperZnodeSpec = serde_yaml::from_str(indoc! {"
+            clusterRef:
+              name: simple
+        "})
+        .unwrap();
+
+        assert_eq!(znode.cluster_ref.name, "simple");
+        assert_eq!(znode.cluster_ref.namespace, None);
+    }
+
+    #[test]
+    fn test_znode_path() {
+        let path = znode_path("9a1b5f6e-3d1c-4f2a-8e7b-0c5d4e3f2a1b");
+
+        assert_eq!(path, "/znode-9a1b5f6e-3d1c-4f2a-8e7b-0c5d4e3f2a1b");
+        assert!(is_valid_zookeeper_path(&path).is_ok());
+    }
+}
@@ -0,0 +1,372 @@
+//! Certificates for clusters whose TLS key material is managed by the operator.
+//!
+//! Every such cluster has a CA, either generated by the operator or referenced by
+//! `tls.caSecretName`, which signs a certificate for every server. The certificate is kept in the
+//! Secret `<pod name>-tls` with the stores in the requested format, so it is mounted the same way
+//! as a Secret provided by the user.
+use crate::config::TLS_PASSWORD_KEY;
+use crate::error::Error;
+use crate::keystore;
+use k8s_openapi::chrono::{self, DateTime, Utc};
+use k8s_openapi::ByteString;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use sha1::Sha1;
+use stackable_zookeeper_crd::KeyStoreFormat;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The PEM encoded certificates of the CA, the first one belongs to the private key
+pub const CA_CERTIFICATE_KEY: &str = "ca.crt";
+/// The PEM encoded PKCS#8 private key of the CA
+pub const CA_PRIVATE_KEY_KEY: &str = "ca.key";
+
+/// The serial number of a certificate, set on its Secret and on the pod that was started with it
+pub const SERIAL_ANNOTATION: &str = "zookeeper.stackable.tech/certificate-serial";
+/// When the certificate in a Secret expires (RFC 3339)
+pub const NOT_AFTER_ANNOTATION: &str = "zookeeper.stackable.tech/certificate-not-after";
+/// A digest of everything the certificate in a Secret was issued for, see `request_fingerprint`
+pub const REQUEST_ANNOTATION: &str = "zookeeper.stackable.tech/certificate-request";
+
+const CA_VALIDITY_DAYS: i64 = 3650;
+const KEY_ALIAS: &str = "zookeeper";
+/// Clocks of the servers may be a little behind the one of the operator
+const NOT_BEFORE_MARGIN_MINUTES: i64 = 5;
+
+pub fn server_tls_secret_name(pod_name: &str) -> String {
+    format!("{}-tls", pod_name)
+}
+
+pub struct CertificateAuthority {
+    signer: Certificate,
+    /// All certificates of `ca.crt`, they are trusted by the servers
+    certificates_pem: Vec<pem::Pem>,
+}
+
+impl CertificateAuthority {
+    /// Generates a self-signed CA and returns the content of its Secret.
+    pub fn generate(
+        cluster_name: &str,
+        now: DateTime<Utc>,
+    ) -> Result<BTreeMap<String, String>, Error> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(
+            DnType::CommonName,
+            format!("ZooKeeper CA of {}", cluster_name),
+        );
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.not_before = now - chrono::Duration::minutes(NOT_BEFORE_MARGIN_MINUTES);
+        params.not_after = now + chrono::Duration::days(CA_VALIDITY_DAYS);
+        params.serial_number = Some(rand::random());
+        let certificate = Certificate::from_params(params)?;
+
+        let mut data = BTreeMap::new();
+        data.insert(CA_CERTIFICATE_KEY.to_string(), certificate.serialize_pem()?);
+        data.insert(
+            CA_PRIVATE_KEY_KEY.to_string(),
+            certificate.serialize_private_key_pem(),
+        );
+        Ok(data)
+    }
+
+    /// Reads a CA from its PEM encoded certificates and private key. Certificates following the
+    /// one of the private key (e.g. the previous CA while switching to a new one) are trusted as
+    /// well.
+    pub fn from_pem(certificates_pem: &str, private_key_pem: &str) -> Result<Self, Error> {
+        let key_pair = KeyPair::from_pem(private_key_pem)?;
+        let params = CertificateParams::from_ca_cert_pem(certificates_pem, key_pair)?;
+        let certificates_pem = pem::parse_many(certificates_pem)
+            .into_iter()
+            .filter(|certificate| certificate.tag == "CERTIFICATE")
+            .collect();
+
+        Ok(CertificateAuthority {
+            signer: Certificate::from_params(params)?,
+            certificates_pem,
+        })
+    }
+
+    /// Issues a certificate for the given DNS names, the first one is used as common name.
+    pub fn issue(
+        &self,
+        dns_names: &[String],
+        now: DateTime<Utc>,
+        validity: Duration,
+    ) -> Result<IssuedCertificate, Error> {
+        let serial = rand::random::<u64>();
+        let not_after = now + chrono::Duration::seconds(validity.as_secs() as i64);
+
+        let mut params = CertificateParams::new(dns_names.to_vec());
+        params.distinguished_name = DistinguishedName::new();
+        if let Some(common_name) = dns_names.first() {
+            params
+                .distinguished_name
+                .push(DnType::CommonName, common_name.clone());
+        }
+        params.not_before = now - chrono::Duration::minutes(NOT_BEFORE_MARGIN_MINUTES);
+        params.not_after = not_after;
+        params.serial_number = Some(serial);
+        // Servers connect to each other for the quorum, so they are clients as well
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        let certificate = Certificate::from_params(params)?;
+
+        Ok(IssuedCertificate {
+            serial,
+            not_after,
+            certificate_der: certificate.serialize_der_with_signer(&self.signer)?,
+            private_key_der: certificate.serialize_private_key_der(),
+        })
+    }
+
+    fn certificates_der(&self) -> Vec<Vec<u8>> {
+        self.certificates_pem
+            .iter()
+            .map(|certificate| certificate.contents.clone())
+            .collect()
+    }
+
+    /// The certificate of the private key
+    fn signer_der(&self) -> Result<&[u8], Error> {
+        self.certificates_pem
+            .first()
+            .map(|certificate| certificate.contents.as_slice())
+            .ok_or_else(|| Error::ReconcileError("The CA contains no certificate".to_string()))
+    }
+}
+
+pub struct IssuedCertificate {
+    pub serial: u64,
+    pub not_after: DateTime<Utc>,
+    certificate_der: Vec<u8>,
+    private_key_der: Vec<u8>,
+}
+
+impl IssuedCertificate {
+    /// The annotations of the Secret holding this certificate
+    pub fn annotations(&self, request_fingerprint: &str) -> BTreeMap<String, String> {
+        let mut annotations = BTreeMap::new();
+        annotations.insert(SERIAL_ANNOTATION.to_string(), self.serial.to_string());
+        annotations.insert(
+            NOT_AFTER_ANNOTATION.to_string(),
+            self.not_after.to_rfc3339(),
+        );
+        annotations.insert(
+            REQUEST_ANNOTATION.to_string(),
+            request_fingerprint.to_string(),
+        );
+        annotations
+    }
+}
+
+/// Builds the content of the Secret of a server: the key store and the trust store in the given
+/// format (with the keys `KeyStoreFormat` describes) and their password.
+pub fn build_server_tls_data(
+    ca: &CertificateAuthority,
+    certificate: &IssuedCertificate,
+    format: KeyStoreFormat,
+    password: &str,
+    now: DateTime<Utc>,
+) -> Result<BTreeMap<String, ByteString>, Error> {
+    let (keystore, truststore) = match format {
+        KeyStoreFormat::Jks => (
+            keystore::jks_key_store(
+                KEY_ALIAS,
+                &certificate.private_key_der,
+                &[
+                    certificate.certificate_der.clone(),
+                    ca.signer_der()?.to_vec(),
+                ],
+                password,
+                now,
+            ),
+            keystore::jks_trust_store(&ca.certificates_der(), password, now),
+        ),
+        KeyStoreFormat::Pkcs12 => (
+            keystore::pkcs12_key_store(
+                KEY_ALIAS,
+                &certificate.private_key_der,
+                &certificate.certificate_der,
+                ca.signer_der()?,
+                password,
+            )?,
+            keystore::pkcs12_trust_store(&ca.certificates_der(), password),
+        ),
+        KeyStoreFormat::Pem => {
+            let keystore = pem::encode_many(&[
+                pem::Pem {
+                    tag: "PRIVATE KEY".to_string(),
+                    contents: certificate.private_key_der.clone(),
+                },
+                pem::Pem {
+                    tag: "CERTIFICATE".to_string(),
+                    contents: certificate.certificate_der.clone(),
+                },
+                pem::Pem {
+                    tag: "CERTIFICATE".to_string(),
+                    contents: ca.signer_der()?.to_vec(),
+                },
+            ]);
+            let truststore = pem::encode_many(&ca.certificates_pem);
+            (keystore.into_bytes(), truststore.into_bytes())
+        }
+    };
+
+    let mut data = BTreeMap::new();
+    data.insert(format.keystore_file().to_string(), ByteString(keystore));
+    data.insert(format.truststore_file().to_string(), ByteString(truststore));
+    if format.has_password() {
+        data.insert(
+            TLS_PASSWORD_KEY.to_string(),
+            ByteString(password.as_bytes().to_vec()),
+        );
+    }
+    Ok(data)
+}
+
+/// A digest of everything a certificate is issued for: its names, the certificates of the CA
+/// and the format of the stores. A certificate issued for another request needs to be replaced.
+pub fn request_fingerprint(
+    dns_names: &[String],
+    ca: &CertificateAuthority,
+    format: KeyStoreFormat,
+) -> String {
+    let mut hash = Sha1::new();
+    hash.update(dns_names.join(",").as_bytes());
+    for certificate in &ca.certificates_pem {
+        hash.update(&certificate.contents);
+    }
+    hash.update(format.store_type().as_bytes());
+    hash.digest().to_string()
+}
+
+/// The serial number of the certificate described by the annotations of its Secret if it can
+/// still be used: It was issued for the same request and more than a third of its validity is
+/// left.
+pub fn usable_certificate_serial(
+    annotations: &BTreeMap<String, String>,
+    request_fingerprint: &str,
+    now: DateTime<Utc>,
+    validity: Duration,
+) -> Option<String> {
+    if annotations.get(REQUEST_ANNOTATION).map(String::as_str) != Some(request_fingerprint) {
+        return None;
+    }
+    let not_after = DateTime::parse_from_rfc3339(annotations.get(NOT_AFTER_ANNOTATION)?)
+        .ok()?
+        .with_timezone(&Utc);
+    let renew_at = not_after - chrono::Duration::seconds(validity.as_secs() as i64 / 3);
+    if now >= renew_at {
+        return None;
+    }
+    annotations.get(SERIAL_ANNOTATION).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn ca() -> CertificateAuthority {
+        let data = CertificateAuthority::generate("simple", Utc::now()).unwrap();
+        CertificateAuthority::from_pem(&data[CA_CERTIFICATE_KEY], &data[CA_PRIVATE_KEY_KEY])
+            .unwrap()
+    }
+
+    fn dns_names() -> Vec<String> {
+        vec![
+            "default-0.simple-servers.default.svc".to_string(),
+            "node-1".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_from_pem_with_additional_certificates() {
+        let current = CertificateAuthority::generate("simple", Utc::now()).unwrap();
+        let previous = CertificateAuthority::generate("simple", Utc::now()).unwrap();
+        let certificates = format!(
+            "{}{}",
+            current[CA_CERTIFICATE_KEY], previous[CA_CERTIFICATE_KEY]
+        );
+
+        let ca =
+            CertificateAuthority::from_pem(&certificates, &current[CA_PRIVATE_KEY_KEY]).unwrap();
+
+        assert_eq!(ca.certificates_der().len(), 2);
+    }
+
+    #[rstest]
+    #[case::jks(KeyStoreFormat::Jks, &["keystore.jks", "password", "truststore.jks"])]
+    #[case::pkcs12(KeyStoreFormat::Pkcs12, &["keystore.p12", "password", "truststore.p12"])]
+    #[case::pem(KeyStoreFormat::Pem, &["keystore.pem", "truststore.pem"])]
+    fn test_build_server_tls_data(#[case] format: KeyStoreFormat, #[case] expected_keys: &[&str]) {
+        let ca = ca();
+        let certificate = ca
+            .issue(&dns_names(), Utc::now(), Duration::from_secs(3600))
+            .unwrap();
+
+        let data =
+            build_server_tls_data(&ca, &certificate, format, "changeit", Utc::now()).unwrap();
+
+        assert_eq!(data.keys().collect::<Vec<_>>(), expected_keys);
+        assert!(data.values().all(|value| !value.0.is_empty()));
+    }
+
+    #[test]
+    fn test_request_fingerprint() {
+        let ca = ca();
+        let fingerprint = request_fingerprint(&dns_names(), &ca, KeyStoreFormat::Jks);
+
+        assert_eq!(
+            fingerprint,
+            request_fingerprint(&dns_names(), &ca, KeyStoreFormat::Jks)
+        );
+        assert_ne!(
+            fingerprint,
+            request_fingerprint(&dns_names(), &ca, KeyStoreFormat::Pkcs12)
+        );
+        assert_ne!(
+            fingerprint,
+            request_fingerprint(&dns_names()[..1], &ca, KeyStoreFormat::Jks)
+        );
+        assert_ne!(
+            fingerprint,
+            request_fingerprint(&dns_names(), &self::ca(), KeyStoreFormat::Jks)
+        );
+    }
+
+    #[rstest]
+    #[case::fresh(0, "request", Some("1234"))]
+    #[case::due_for_renewal(61, "request", None)]
+    #[case::expired(100, "request", None)]
+    #[case::other_request(0, "other", None)]
+    fn test_usable_certificate_serial(
+        #[case] days_passed: i64,
+        #[case] fingerprint: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let issued_at = Utc::now();
+        let validity = Duration::from_secs(90 * 24 * 60 * 60);
+        let mut annotations = BTreeMap::new();
+        annotations.insert(SERIAL_ANNOTATION.to_string(), "1234".to_string());
+        annotations.insert(
+            NOT_AFTER_ANNOTATION.to_string(),
+            (issued_at + chrono::Duration::days(90)).to_rfc3339(),
+        );
+        annotations.insert(REQUEST_ANNOTATION.to_string(), "request".to_string());
+
+        let serial = usable_certificate_serial(
+            &annotations,
+            fingerprint,
+            issued_at + chrono::Duration::days(days_passed),
+            validity,
+        );
+
+        assert_eq!(serial.as_deref(), expected);
+    }
+}
@@ -0,0 +1,604 @@
+use crate::four_letter_words;
+use handlebars::Handlebars;
+use serde_json::json;
+use stackable_zookeeper_crd::{
+    AuthenticationConfig, PeerType, QuorumSaslStage, QuorumTlsStage, TlsConfig, ZookeeperConfig,
+    DEFAULT_INIT_LIMIT, DEFAULT_SYNC_LIMIT, DEFAULT_TICK_TIME,
+};
+use std::collections::BTreeMap;
+
+/// The Secret of `tls` is mounted into this directory
+pub const TLS_DIR: &str = "/etc/zookeeper/tls";
+/// The key of the password of the key store and the trust store in the Secret of `tls`
+pub const TLS_PASSWORD_KEY: &str = "password";
+
+/// A single member of the ensemble as it appears in the `server.N` entries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerEntry {
+    pub host: String,
+    pub quorum_port: u16,
+    pub leader_election_port: u16,
+    pub client_port: u16,
+    pub peer_type: PeerType,
+}
+
+impl ServerEntry {
+    pub fn new(host: &str, config: &ZookeeperConfig, peer_type: PeerType) -> Self {
+        ServerEntry {
+            host: host.to_string(),
+            quorum_port: config.quorum_port(),
+            leader_election_port: config.leader_election_port(),
+            client_port: config.client_port(),
+            peer_type,
+        }
+    }
+
+    /// The value of this server's `server.N` entry in a static `zoo.cfg`, the role is only
+    /// appended for observers since participant is the default
+    pub fn static_entry(&self) -> String {
+        match self.peer_type {
+            PeerType::Participant => format!(
+                "{}:{}:{}",
+                self.host, self.quorum_port, self.leader_election_port
+            ),
+            PeerType::Observer => format!(
+                "{}:{}:{}:{}",
+                self.host,
+                self.quorum_port,
+                self.leader_election_port,
+                self.peer_type.as_str()
+            ),
+        }
+    }
+
+    /// The value of this server's `server.N` entry in a dynamic configuration file, which also
+    /// contains the role and the client port
+    pub fn dynamic_entry(&self) -> String {
+        format!(
+            "{}:{}:{}:{};{}",
+            self.host,
+            self.quorum_port,
+            self.leader_election_port,
+            self.peer_type.as_str(),
+            self.client_port
+        )
+    }
+}
+
+/// Builds all `zoo.cfg` options for a single server from its (already merged) configuration
+/// and its peer type.
+///
+/// `servers` maps a `myid` to the address of that server, this is used to render the
+/// `server.N` entries.
+pub fn build_zoo_cfg(
+    config: &ZookeeperConfig,
+    peer_type: PeerType,
+    servers: &BTreeMap<usize, ServerEntry>,
+) -> BTreeMap<String, String> {
+    let mut options = build_common_options(config, peer_type);
+    options.insert("clientPort".to_string(), config.client_port().to_string());
+
+    for (id, server) in servers {
+        options.insert(format!("server.{}", id), server.static_entry());
+    }
+
+    options
+}
+
+/// Builds the static `zoo.cfg` options for a server that keeps its members in the dynamic
+/// configuration file at `dynamic_config_file` (see [`build_dynamic_cfg`]).
+/// The client port is part of the dynamic configuration in this case.
+pub fn build_reconfig_zoo_cfg(
+    config: &ZookeeperConfig,
+    peer_type: PeerType,
+    dynamic_config_file: &str,
+) -> BTreeMap<String, String> {
+    let mut options = build_common_options(config, peer_type);
+    options.insert("reconfigEnabled".to_string(), "true".to_string());
+    // Otherwise a single server would not run in quorum mode and could not be reconfigured
+    options.insert("standaloneEnabled".to_string(), "false".to_string());
+    options.insert(
+        "dynamicConfigFile".to_string(),
+        dynamic_config_file.to_string(),
+    );
+    options
+}
+
+/// Builds the `server.N` entries of the dynamic configuration file.
+pub fn build_dynamic_cfg(servers: &BTreeMap<usize, ServerEntry>) -> BTreeMap<String, String> {
+    servers
+        .iter()
+        .map(|(id, server)| (format!("server.{}", id), server.dynamic_entry()))
+        .collect()
+}
+
+/// Builds the `zoo.cfg` options for serving clients with TLS on the secure client port.
+///
+/// The passwords of the stores are not part of the options, they are passed to the server as
+/// system properties taken from the Secret so they don't end up in a ConfigMap.
+pub fn build_client_tls_options(
+    config: &ZookeeperConfig,
+    tls: &TlsConfig,
+) -> BTreeMap<String, String> {
+    let format = tls.format();
+
+    let mut options = BTreeMap::new();
+    options.insert(
+        "secureClientPort".to_string(),
+        config.secure_client_port().to_string(),
+    );
+    // Only the Netty based connection factory supports TLS
+    options.insert(
+        "serverCnxnFactory".to_string(),
+        "org.apache.zookeeper.server.NettyServerCnxnFactory".to_string(),
+    );
+    options.insert(
+        "ssl.keyStore.location".to_string(),
+        format!("{}/{}", TLS_DIR, format.keystore_file()),
+    );
+    options.insert(
+        "ssl.keyStore.type".to_string(),
+        format.store_type().to_string(),
+    );
+    options.insert(
+        "ssl.trustStore.location".to_string(),
+        format!("{}/{}", TLS_DIR, format.truststore_file()),
+    );
+    options.insert(
+        "ssl.trustStore.type".to_string(),
+        format.store_type().to_string(),
+    );
+    options.insert(
+        "ssl.clientAuth".to_string(),
+        tls.client_auth().as_str().to_string(),
+    );
+    options
+}
+
+/// Builds the `zoo.cfg` options for the quorum and leader election connections in the given
+/// stage, nothing is added for `Plaintext`.
+///
+/// The servers use the same stores as for clients, the passwords are passed as system properties
+/// like the ones of `build_client_tls_options`.
+pub fn build_quorum_tls_options(
+    tls: &TlsConfig,
+    stage: QuorumTlsStage,
+) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    if stage == QuorumTlsStage::Plaintext {
+        return options;
+    }
+
+    let format = tls.format();
+    options.insert("sslQuorum".to_string(), stage.ssl_quorum().to_string());
+    if stage.port_unification() {
+        options.insert("portUnification".to_string(), "true".to_string());
+    }
+    options.insert(
+        "ssl.quorum.keyStore.location".to_string(),
+        format!("{}/{}", TLS_DIR, format.keystore_file()),
+    );
+    options.insert(
+        "ssl.quorum.keyStore.type".to_string(),
+        format.store_type().to_string(),
+    );
+    options.insert(
+        "ssl.quorum.trustStore.location".to_string(),
+        format!("{}/{}", TLS_DIR, format.truststore_file()),
+    );
+    options.insert(
+        "ssl.quorum.trustStore.type".to_string(),
+        format.store_type().to_string(),
+    );
+    options
+}
+
+/// Builds the `zoo.cfg` options for SASL authentication of clients, the mechanisms themselves
+/// are configured in the JAAS file (see `jaas`).
+pub fn build_authentication_options(
+    authentication: &AuthenticationConfig,
+) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    if !authentication.authenticates_clients() {
+        return options;
+    }
+
+    options.insert(
+        "authProvider.1".to_string(),
+        "org.apache.zookeeper.server.auth.SASLAuthenticationProvider".to_string(),
+    );
+    if authentication.require_client_auth() {
+        options.insert("requireClientAuthScheme".to_string(), "sasl".to_string());
+    }
+    options
+}
+
+/// Builds the `zoo.cfg` options for the authentication between the servers in the given stage,
+/// nothing is added for `Disabled`. The credentials are part of the JAAS file.
+pub fn build_quorum_sasl_options(stage: QuorumSaslStage) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    if stage == QuorumSaslStage::Disabled {
+        return options;
+    }
+
+    options.insert(
+        "quorum.auth.enableSasl".to_string(),
+        stage.enable_sasl().to_string(),
+    );
+    options.insert(
+        "quorum.auth.learnerRequireSasl".to_string(),
+        stage.learner_require_sasl().to_string(),
+    );
+    options.insert(
+        "quorum.auth.serverRequireSasl".to_string(),
+        stage.server_require_sasl().to_string(),
+    );
+    options
+}
+
+fn build_common_options(config: &ZookeeperConfig, peer_type: PeerType) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    options.insert(
+        "tickTime".to_string(),
+        config.tick_time.unwrap_or(DEFAULT_TICK_TIME).to_string(),
+    );
+    options.insert(
+        "initLimit".to_string(),
+        config.init_limit.unwrap_or(DEFAULT_INIT_LIMIT).to_string(),
+    );
+    options.insert(
+        "syncLimit".to_string(),
+        config.sync_limit.unwrap_or(DEFAULT_SYNC_LIMIT).to_string(),
+    );
+    options.insert("dataDir".to_string(), config.data_dir().to_string());
+    options.insert("dataLogDir".to_string(), config.data_log_dir().to_string());
+    options.insert(
+        "4lw.commands.whitelist".to_string(),
+        four_letter_words::WHITELIST.join(","),
+    );
+    // An observer needs to know it is one, the `server.N` entries alone are not enough
+    if peer_type == PeerType::Observer {
+        options.insert("peerType".to_string(), peer_type.as_str().to_string());
+    }
+
+    // These are only rendered when set, otherwise ZooKeeper's own defaults apply
+    for (key, value) in &[
+        ("maxClientCnxns", config.max_client_cnxns),
+        (
+            "autopurge.snapRetainCount",
+            config.autopurge_snap_retain_count,
+        ),
+        ("autopurge.purgeInterval", config.autopurge_purge_interval),
+        ("minSessionTimeout", config.min_session_timeout),
+        ("maxSessionTimeout", config.max_session_timeout),
+    ] {
+        if let Some(value) = value {
+            options.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    options
+}
+
+/// Renders the options as `key=value` lines, sorted by key.
+pub fn render_zoo_cfg(options: &BTreeMap<String, String>) -> String {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    // This is a properties file and not HTML, values must be written verbatim
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars
+        .register_template_string("conf", "{{#each options}}{{@key}}={{this}}\n{{/each}}")
+        .expect("Failure rendering the ZooKeeper config template, this should not happen, please report this issue");
+
+    handlebars
+        .render("conf", &json!({ "options": options }))
+        .expect("Failure rendering the ZooKeeper config template, this should not happen, please report this issue")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use stackable_zookeeper_crd::{DigestAuthentication, KeyStoreFormat};
+
+    #[test]
+    fn test_build_zoo_cfg_defaults() {
+        let mut servers = BTreeMap::new();
+        servers.insert(
+            1,
+            ServerEntry::new("node-1", &ZookeeperConfig::default(), PeerType::Participant),
+        );
+        servers.insert(
+            2,
+            ServerEntry::new("node-2", &ZookeeperConfig::default(), PeerType::Participant),
+        );
+
+        let options = build_zoo_cfg(&ZookeeperConfig::default(), PeerType::Participant, &servers);
+
+        assert_eq!(options.get("tickTime"), Some(&"2000".to_string()));
+        assert_eq!(options.get("initLimit"), Some(&"5".to_string()));
+        assert_eq!(options.get("syncLimit"), Some(&"2".to_string()));
+        assert_eq!(
+            options.get("server.2"),
+            Some(&"node-2:2888:3888".to_string())
+        );
+        assert_eq!(options.get("clientPort"), Some(&"2181".to_string()));
+        assert_eq!(
+            options.get("4lw.commands.whitelist"),
+            Some(&"ruok,srvr".to_string())
+        );
+        assert!(options.get("maxClientCnxns").is_none());
+        assert!(options.get("autopurge.purgeInterval").is_none());
+        assert!(options.get("peerType").is_none());
+    }
+
+    #[test]
+    fn test_build_zoo_cfg_observers() {
+        let config = ZookeeperConfig::default();
+        let mut servers = BTreeMap::new();
+        servers.insert(
+            1,
+            ServerEntry::new("node-1", &config, PeerType::Participant),
+        );
+        servers.insert(2, ServerEntry::new("node-2", &config, PeerType::Observer));
+
+        let options = build_zoo_cfg(&config, PeerType::Observer, &servers);
+
+        assert_eq!(options.get("peerType"), Some(&"observer".to_string()));
+        assert_eq!(
+            options.get("server.1"),
+            Some(&"node-1:2888:3888".to_string())
+        );
+        assert_eq!(
+            options.get("server.2"),
+            Some(&"node-2:2888:3888:observer".to_string())
+        );
+
+        let options = build_reconfig_zoo_cfg(&config, PeerType::Observer, "zoo.cfg.dynamic");
+        assert_eq!(options.get("peerType"), Some(&"observer".to_string()));
+        assert_eq!(
+            render_zoo_cfg(&build_dynamic_cfg(&servers)),
+            "server.1=node-1:2888:3888:participant;2181\n\
+             server.2=node-2:2888:3888:observer;2181\n"
+        );
+    }
+
+    #[test]
+    fn test_build_zoo_cfg_ports() {
+        let custom_ports = ZookeeperConfig {
+            client_port: Some(12181),
+            quorum_port: Some(12888),
+            leader_election_port: Some(13888),
+            ..ZookeeperConfig::default()
+        };
+
+        let mut servers = BTreeMap::new();
+        servers.insert(
+            1,
+            ServerEntry::new("node-1", &custom_ports, PeerType::Participant),
+        );
+        servers.insert(
+            2,
+            ServerEntry::new("node-1", &ZookeeperConfig::default(), PeerType::Participant),
+        );
+
+        let options = build_zoo_cfg(&custom_ports, PeerType::Participant, &servers);
+
+        assert_eq!(options.get("clientPort"), Some(&"12181".to_string()));
+        assert_eq!(
+            options.get("server.1"),
+            Some(&"node-1:12888:13888".to_string())
+        );
+        assert_eq!(
+            options.get("server.2"),
+            Some(&"node-1:2888:3888".to_string())
+        );
+    }
+
+    #[test]
+    fn test_build_reconfig_zoo_cfg() {
+        let config = ZookeeperConfig {
+            client_port: Some(12181),
+            ..ZookeeperConfig::default()
+        };
+        let mut servers = BTreeMap::new();
+        servers.insert(
+            1,
+            ServerEntry::new("node-1", &config, PeerType::Participant),
+        );
+        servers.insert(
+            2,
+            ServerEntry::new("node-2", &ZookeeperConfig::default(), PeerType::Participant),
+        );
+
+        let options =
+            build_reconfig_zoo_cfg(&config, PeerType::Participant, "/data/conf/zoo.cfg.dynamic");
+        assert_eq!(options.get("reconfigEnabled"), Some(&"true".to_string()));
+        assert_eq!(options.get("standaloneEnabled"), Some(&"false".to_string()));
+        assert_eq!(
+            options.get("dynamicConfigFile"),
+            Some(&"/data/conf/zoo.cfg.dynamic".to_string())
+        );
+        assert!(options.get("clientPort").is_none());
+        assert!(options.keys().all(|key| !key.starts_with("server.")));
+
+        assert_eq!(
+            render_zoo_cfg(&build_dynamic_cfg(&servers)),
+            "server.1=node-1:2888:3888:participant;12181\n\
+             server.2=node-2:2888:3888:participant;2181\n"
+        );
+    }
+
+    #[test]
+    fn test_build_client_tls_options() {
+        let config = ZookeeperConfig {
+            secure_client_port: Some(12281),
+            ..ZookeeperConfig::default()
+        };
+        let tls = TlsConfig {
+            secret_name: Some("simple-tls".to_string()),
+            ca_secret_name: None,
+            certificate_validity_days: None,
+            format: Some(KeyStoreFormat::Pkcs12),
+            client_auth: None,
+            quorum: None,
+        };
+
+        assert_eq!(
+            render_zoo_cfg(&build_client_tls_options(&config, &tls)),
+            "secureClientPort=12281\n\
+             serverCnxnFactory=org.apache.zookeeper.server.NettyServerCnxnFactory\n\
+             ssl.clientAuth=need\n\
+             ssl.keyStore.location=/etc/zookeeper/tls/keystore.p12\n\
+             ssl.keyStore.type=PKCS12\n\
+             ssl.trustStore.location=/etc/zookeeper/tls/truststore.p12\n\
+             ssl.trustStore.type=PKCS12\n"
+        );
+    }
+
+    #[test]
+    fn test_render_zoo_cfg_with_tls() {
+        let config = ZookeeperConfig::default();
+        let tls = TlsConfig {
+            secret_name: Some("simple-tls".to_string()),
+            ca_secret_name: None,
+            certificate_validity_days: None,
+            format: None,
+            client_auth: None,
+            quorum: None,
+        };
+
+        let mut options = build_zoo_cfg(&config, PeerType::Participant, &BTreeMap::new());
+        options.extend(build_client_tls_options(&config, &tls));
+        let rendered = render_zoo_cfg(&options);
+
+        // The operator and the probes still use the plain client port
+        assert!(rendered.contains("\nclientPort=2181\n"));
+        assert!(!rendered.contains("clientPortAddress"));
+        assert!(rendered.contains("\nsecureClientPort=2281\n"));
+    }
+
+    #[rstest]
+    #[case::plaintext(QuorumTlsStage::Plaintext, None, None)]
+    #[case::port_unification(QuorumTlsStage::PortUnification, Some("false"), Some("true"))]
+    #[case::tls_with_port_unification(
+        QuorumTlsStage::TlsWithPortUnification,
+        Some("true"),
+        Some("true")
+    )]
+    #[case::tls(QuorumTlsStage::Tls, Some("true"), None)]
+    fn test_build_quorum_tls_options(
+        #[case] stage: QuorumTlsStage,
+        #[case] ssl_quorum: Option<&str>,
+        #[case] port_unification: Option<&str>,
+    ) {
+        let tls = TlsConfig {
+            secret_name: Some("simple-tls".to_string()),
+            ca_secret_name: None,
+            certificate_validity_days: None,
+            format: None,
+            client_auth: None,
+            quorum: Some(true),
+        };
+
+        let options = build_quorum_tls_options(&tls, stage);
+
+        assert_eq!(options.get("sslQuorum").map(String::as_str), ssl_quorum);
+        assert_eq!(
+            options.get("portUnification").map(String::as_str),
+            port_unification
+        );
+        if stage == QuorumTlsStage::Plaintext {
+            assert!(options.is_empty());
+        } else {
+            assert_eq!(
+                options.get("ssl.quorum.keyStore.location"),
+                Some(&"/etc/zookeeper/tls/keystore.jks".to_string())
+            );
+            assert_eq!(
+                options.get("ssl.quorum.trustStore.type"),
+                Some(&"JKS".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_authentication_options() {
+        let mut authentication = AuthenticationConfig {
+            quorum_secret_name: Some("simple-quorum".to_string()),
+            ..AuthenticationConfig::default()
+        };
+        assert!(build_authentication_options(&authentication).is_empty());
+
+        authentication.digest = Some(DigestAuthentication {
+            secret_name: "simple-users".to_string(),
+        });
+        assert_eq!(
+            render_zoo_cfg(&build_authentication_options(&authentication)),
+            "authProvider.1=org.apache.zookeeper.server.auth.SASLAuthenticationProvider\n"
+        );
+
+        authentication.require_client_auth = Some(true);
+        assert_eq!(
+            render_zoo_cfg(&build_authentication_options(&authentication)),
+            "authProvider.1=org.apache.zookeeper.server.auth.SASLAuthenticationProvider\n\
+             requireClientAuthScheme=sasl\n"
+        );
+    }
+
+    #[rstest]
+    #[case::disabled(QuorumSaslStage::Disabled, "")]
+    #[case::enabled(
+        QuorumSaslStage::Enabled,
+        "quorum.auth.enableSasl=true\n\
+         quorum.auth.learnerRequireSasl=false\n\
+         quorum.auth.serverRequireSasl=false\n"
+    )]
+    #[case::learner_required(
+        QuorumSaslStage::LearnerRequired,
+        "quorum.auth.enableSasl=true\n\
+         quorum.auth.learnerRequireSasl=true\n\
+         quorum.auth.serverRequireSasl=false\n"
+    )]
+    #[case::required(
+        QuorumSaslStage::Required,
+        "quorum.auth.enableSasl=true\n\
+         quorum.auth.learnerRequireSasl=true\n\
+         quorum.auth.serverRequireSasl=true\n"
+    )]
+    fn test_build_quorum_sasl_options(#[case] stage: QuorumSaslStage, #[case] expected: &str) {
+        assert_eq!(render_zoo_cfg(&build_quorum_sasl_options(stage)), expected);
+    }
+
+    #[test]
+    fn test_render_zoo_cfg() {
+        let config = ZookeeperConfig {
+            tick_time: Some(3000),
+            max_client_cnxns: Some(0),
+            data_dir: Some("/data/zookeeper".to_string()),
+            autopurge_snap_retain_count: Some(5),
+            autopurge_purge_interval: Some(24),
+            ..ZookeeperConfig::default()
+        };
+
+        let rendered = render_zoo_cfg(&build_zoo_cfg(
+            &config,
+            PeerType::Participant,
+            &BTreeMap::new(),
+        ));
+
+        assert_eq!(
+            rendered,
+            "4lw.commands.whitelist=ruok,srvr\n\
+             autopurge.purgeInterval=24\n\
+             autopurge.snapRetainCount=5\n\
+             clientPort=2181\n\
+             dataDir=/data/zookeeper\n\
+             dataLogDir=/var/lib/zookeeper/datalog\n\
+             initLimit=5\n\
+             maxClientCnxns=0\n\
+             syncLimit=2\n\
+             tickTime=3000\n"
+        );
+    }
+}
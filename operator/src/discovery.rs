@@ -0,0 +1,169 @@
+//! The discovery ConfigMap tells clients how to connect to a cluster.
+//!
+//! It is published as `<cluster name>-discovery` next to the cluster and contains plain strings
+//! only, so consumers can mount it as environment variables or files without knowing anything
+//! about `ZookeeperCluster` objects. Every `ZookeeperZnode` gets its own discovery ConfigMap with
+//! a connection string chrooted to its znode.
+use stackable_zookeeper_crd::util::server_client_addresses;
+use stackable_zookeeper_crd::{ServerStatus, ZookeeperClusterSpec};
+use std::collections::BTreeMap;
+
+/// `host:port[,host:port...][/chroot]` of all servers, the ports are the secure client ports if
+/// clients need to use TLS
+pub const CONNECTION_STRING_KEY: &str = "connectionString";
+/// The (secure) port of the client Service
+pub const CLIENT_PORT_KEY: &str = "clientPort";
+/// `host[,host...]` of all servers
+pub const HOSTS_KEY: &str = "hosts";
+/// Whether clients need to use TLS, `true` or `false`
+pub const TLS_KEY: &str = "tls";
+/// The znode the connection string is chrooted to, only set for `ZookeeperZnode`s
+pub const CHROOT_KEY: &str = "chroot";
+
+pub fn discovery_config_map_name(cluster_name: &str) -> String {
+    format!("{}-discovery", cluster_name)
+}
+
+/// Builds the content of the discovery ConfigMap for the servers recorded in the status of a
+/// cluster. Clients of a cluster with `tls` are told to use the secure client ports.
+pub fn build_cluster_discovery_data(
+    cluster_name: &str,
+    namespace: &str,
+    spec: &ZookeeperClusterSpec,
+    servers: &BTreeMap<String, ServerStatus>,
+    chroot: Option<&str>,
+) -> BTreeMap<String, String> {
+    let tls = spec.tls.is_some();
+    let servers = server_client_addresses(cluster_name, namespace, spec, servers);
+    let cluster_config = spec.config.clone().unwrap_or_default();
+    let client_port = if tls {
+        cluster_config.secure_client_port()
+    } else {
+        cluster_config.client_port()
+    };
+
+    build_discovery_data(&servers, client_port, tls, chroot)
+}
+
+/// Builds the content of the discovery ConfigMap from the address and the client port of every
+/// server. The servers are sorted so the content only changes when the members change.
+pub fn build_discovery_data(
+    servers: &[(String, u16)],
+    client_port: u16,
+    tls: bool,
+    chroot: Option<&str>,
+) -> BTreeMap<String, String> {
+    let mut servers = servers.to_vec();
+    servers.sort();
+
+    let mut connection_string = servers
+        .iter()
+        .map(|(host, port)| format!("{}:{}", host, port))
+        .collect::<Vec<_>>()
+        .join(",");
+    let hosts = servers
+        .iter()
+        .map(|(host, _)| host.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut data = BTreeMap::new();
+    if let Some(chroot) = chroot {
+        connection_string.push_str(chroot);
+        data.insert(CHROOT_KEY.to_string(), chroot.to_string());
+    }
+    data.insert(CONNECTION_STRING_KEY.to_string(), connection_string);
+    data.insert(CLIENT_PORT_KEY.to_string(), client_port.to_string());
+    data.insert(HOSTS_KEY.to_string(), hosts);
+    data.insert(TLS_KEY.to_string(), tls.to_string());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stackable_zookeeper_crd::PeerType;
+
+    #[test]
+    fn test_build_discovery_data() {
+        let servers = vec![
+            ("default-1.simple-servers.default.svc".to_string(), 2181),
+            ("default-0.simple-servers.default.svc".to_string(), 2181),
+            ("secondary-0.simple-servers.default.svc".to_string(), 2182),
+        ];
+
+        let data = build_discovery_data(&servers, 2181, false, None);
+
+        assert_eq!(
+            data.get(CONNECTION_STRING_KEY).unwrap(),
+            "default-0.simple-servers.default.svc:2181,default-1.simple-servers.default.svc:2181,secondary-0.simple-servers.default.svc:2182"
+        );
+        assert_eq!(data.get(CLIENT_PORT_KEY).unwrap(), "2181");
+        assert_eq!(
+            data.get(HOSTS_KEY).unwrap(),
+            "default-0.simple-servers.default.svc,default-1.simple-servers.default.svc,secondary-0.simple-servers.default.svc"
+        );
+        assert_eq!(data.get(TLS_KEY).unwrap(), "false");
+        assert_eq!(data.get(CHROOT_KEY), None);
+    }
+
+    #[test]
+    fn test_build_discovery_data_with_chroot() {
+        let servers = vec![
+            ("default-0.simple-servers.default.svc".to_string(), 2181),
+            ("default-1.simple-servers.default.svc".to_string(), 2181),
+        ];
+
+        let data = build_discovery_data(&servers, 2181, false, Some("/znode-1234"));
+
+        assert_eq!(
+            data.get(CONNECTION_STRING_KEY).unwrap(),
+            "default-0.simple-servers.default.svc:2181,default-1.simple-servers.default.svc:2181/znode-1234"
+        );
+        assert_eq!(
+            data.get(HOSTS_KEY).unwrap(),
+            "default-0.simple-servers.default.svc,default-1.simple-servers.default.svc"
+        );
+        assert_eq!(data.get(CHROOT_KEY).unwrap(), "/znode-1234");
+    }
+
+    #[test]
+    fn test_build_cluster_discovery_data_with_tls() {
+        let spec: ZookeeperClusterSpec = serde_json::from_value(serde_json::json!({
+            "version": "3.5.8",
+            "servers": { "selectors": { "default": {} } },
+            "tls": { "secretName": "simple-tls" }
+        }))
+        .unwrap();
+        let mut servers = BTreeMap::new();
+        servers.insert(
+            "default-0".to_string(),
+            ServerStatus {
+                role_group: "default".to_string(),
+                ordinal: 0,
+                id: 1,
+                node_name: "node-1".to_string(),
+                node_slot: 1,
+                peer_type: PeerType::Participant,
+            },
+        );
+
+        let data = build_cluster_discovery_data("simple", "default", &spec, &servers, None);
+
+        assert_eq!(
+            data.get(CONNECTION_STRING_KEY).unwrap(),
+            "default-0.simple-servers.default.svc:2282"
+        );
+        assert_eq!(data.get(CLIENT_PORT_KEY).unwrap(), "2281");
+        assert_eq!(data.get(TLS_KEY).unwrap(), "true");
+    }
+
+    #[test]
+    fn test_build_discovery_data_without_servers() {
+        let data = build_discovery_data(&[], 2181, true, None);
+
+        assert_eq!(data.get(CONNECTION_STRING_KEY).unwrap(), "");
+        assert_eq!(data.get(HOSTS_KEY).unwrap(), "");
+        assert_eq!(data.get(TLS_KEY).unwrap(), "true");
+    }
+}
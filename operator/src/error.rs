@@ -14,6 +14,12 @@ pub enum Error {
         source: stackable_operator::error::Error,
     },
 
+    #[error("Error from ZooKeeper CRD: {source}")]
+    CrdError {
+        #[from]
+        source: stackable_zookeeper_crd::error::Error,
+    },
+
     #[error("Error from serde_json: {source}")]
     SerdeError {
         #[from]
@@ -26,6 +32,24 @@ pub enum Error {
         source: ParseIntError,
     },
 
+    #[error("IO error: {source}")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+    },
+
+    #[error("Could not create certificate: {source}")]
+    CertificateError {
+        #[from]
+        source: rcgen::RcgenError,
+    },
+
+    #[error("ZooKeeper returned error code [{code}] for operation [{op_code}]")]
+    ZookeeperError { op_code: i32, code: i32 },
+
+    #[error("Unexpected response from ZooKeeper: {0}")]
+    ZookeeperProtocolError(String),
+
     #[error("Error during reconciliation: {0}")]
     ReconcileError(String),
 }
@@ -0,0 +1,274 @@
+//! A minimal client for ZooKeeper's "four letter word" commands (e.g. `srvr`).
+//!
+//! These are sent as plain text to the client port, the server answers with plain text and
+//! closes the connection afterwards. Servers only answer the commands in their
+//! `4lw.commands.whitelist`, which is rendered from [`WHITELIST`].
+use crate::error::Error;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// The commands used by the operator and by the probes of the pods
+pub const WHITELIST: &[&str] = &["ruok", "srvr"];
+
+/// The role a server currently has in the ensemble as reported by `srvr`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServerMode {
+    Leader,
+    Follower,
+    Observer,
+    Standalone,
+}
+
+impl ServerMode {
+    /// The leader (or the only server of a standalone installation) should be restarted last.
+    pub fn is_leader(&self) -> bool {
+        matches!(self, ServerMode::Leader | ServerMode::Standalone)
+    }
+
+    /// The mode as ZooKeeper reports it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerMode::Leader => "leader",
+            ServerMode::Follower => "follower",
+            ServerMode::Observer => "observer",
+            ServerMode::Standalone => "standalone",
+        }
+    }
+}
+
+/// The statistics of a server as reported by `srvr`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerStats {
+    /// `None` if the server is running but not serving requests
+    pub mode: Option<ServerMode>,
+    /// The last transaction id the server has seen as hex number (e.g. `0x100000002`)
+    pub zxid: Option<String>,
+    pub outstanding_requests: Option<u64>,
+    /// Request latencies in milliseconds
+    pub min_latency: Option<u64>,
+    pub avg_latency: Option<f64>,
+    pub max_latency: Option<u64>,
+}
+
+/// Sends a command and returns the full response.
+pub async fn send_command(
+    address: &str,
+    command: &str,
+    request_timeout: Duration,
+) -> Result<String, Error> {
+    let request = async {
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(command.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+
+    match timeout(request_timeout, request).await {
+        Ok(response) => Ok(response?),
+        Err(_) => Err(Error::from(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!("[{}] to [{}] timed out", command, address),
+        ))),
+    }
+}
+
+/// Asks the server for its statistics using `srvr`.
+pub async fn server_stats(address: &str, request_timeout: Duration) -> Result<ServerStats, Error> {
+    let response = send_command(address, "srvr", request_timeout).await?;
+    Ok(parse_stats(&response))
+}
+
+/// Parses a `srvr` response, lines that are missing or can't be parsed are left out.
+pub fn parse_stats(response: &str) -> ServerStats {
+    // Older versions report integers only, e.g. `Latency min/avg/max: 0/1/12`
+    let mut latencies = srvr_value(response, "Latency min/avg/max:")
+        .map(|latencies| latencies.split('/').collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter();
+    let min_latency = latencies.next().and_then(|min| min.parse().ok());
+    let avg_latency = latencies.next().and_then(|avg| avg.parse().ok());
+    let max_latency = latencies.next().and_then(|max| max.parse().ok());
+
+    ServerStats {
+        mode: parse_mode(response),
+        zxid: srvr_value(response, "Zxid:").map(str::to_string),
+        outstanding_requests: srvr_value(response, "Outstanding:")
+            .and_then(|outstanding| outstanding.parse().ok()),
+        min_latency,
+        avg_latency,
+        max_latency,
+    }
+}
+
+/// The value of the line of a `srvr` response that starts with `key`.
+fn srvr_value<'a>(response: &'a str, key: &str) -> Option<&'a str> {
+    response
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(key))
+        .map(str::trim)
+}
+
+/// Parses the `Mode: ...` line of a `srvr` response.
+pub fn parse_mode(response: &str) -> Option<ServerMode> {
+    let mode = response
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Mode:"))?;
+
+    match mode.trim() {
+        "leader" => Some(ServerMode::Leader),
+        "follower" => Some(ServerMode::Follower),
+        "observer" => Some(ServerMode::Observer),
+        "standalone" => Some(ServerMode::Standalone),
+        _ => None,
+    }
+}
+
+/// Builds the command of an exec probe which sends `command` to the server listening on `port`
+/// of the pod and succeeds if a line of the response matches the extended regular expression
+/// `expected`. `nc` is not available in the images, so this uses bash's `/dev/tcp`.
+pub fn probe_command(port: u16, command: &str, expected: &str) -> Vec<String> {
+    vec![
+        "bash".to_string(),
+        "-c".to_string(),
+        format!(
+            "exec 3<>/dev/tcp/127.0.0.1/{} && printf {} >&3 && grep -qE '{}' <&3",
+            port, command, expected
+        ),
+    ]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use indoc::indoc;
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+
+    pub(crate) const SRVR_FOLLOWER: &str = indoc! {"
+        Zookeeper version: 3.5.8-f439ca583e70862c3068a1f2a7d4d068eec33315, built on 05/04/2020 15:07 GMT
+        Latency min/avg/max: 0/0/0
+        Received: 12
+        Sent: 11
+        Connections: 1
+        Outstanding: 0
+        Zxid: 0x100000002
+        Mode: follower
+        Node count: 5
+    "};
+
+    /// Starts a server that answers every command it receives with the given response and
+    /// returns its address.
+    pub(crate) async fn mock_server(responses: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut command = [0; 4];
+                stream.read_exact(&mut command).await.unwrap();
+                let response = responses
+                    .iter()
+                    .find(|(expected, _)| expected.as_bytes() == command)
+                    .map(|(_, response)| response.clone())
+                    .unwrap_or_default();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[rstest]
+    #[case(SRVR_FOLLOWER, Some(ServerMode::Follower))]
+    #[case("Mode: leader\n", Some(ServerMode::Leader))]
+    #[case("Mode: standalone\n", Some(ServerMode::Standalone))]
+    #[case("Mode: observer\n", Some(ServerMode::Observer))]
+    #[case("This ZooKeeper instance is not currently serving requests\n", None)]
+    fn test_parse_mode(#[case] response: &str, #[case] expected: Option<ServerMode>) {
+        assert_eq!(parse_mode(response), expected);
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        assert_eq!(
+            parse_stats(SRVR_FOLLOWER),
+            ServerStats {
+                mode: Some(ServerMode::Follower),
+                zxid: Some("0x100000002".to_string()),
+                outstanding_requests: Some(0),
+                min_latency: Some(0),
+                avg_latency: Some(0.0),
+                max_latency: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_stats_with_fractional_latency() {
+        let stats = parse_stats(indoc! {"
+            Zookeeper version: 3.6.3--6401e4ad2087061bc6b9f80dec2d69f2e3c8660a, built on 04/08/2021 16:35 GMT
+            Latency min/avg/max: 0/0.4821/17
+            Received: 1234
+            Sent: 1233
+            Connections: 4
+            Outstanding: 2
+            Zxid: 0x30000001a
+            Mode: leader
+            Node count: 12
+            Proposal sizes last/min/max: 48/48/120
+        "});
+
+        assert_eq!(stats.mode, Some(ServerMode::Leader));
+        assert_eq!(stats.outstanding_requests, Some(2));
+        assert_eq!(stats.avg_latency, Some(0.4821));
+        assert_eq!(stats.max_latency, Some(17));
+    }
+
+    #[test]
+    fn test_parse_stats_not_serving() {
+        assert_eq!(
+            parse_stats("This ZooKeeper instance is not currently serving requests\n"),
+            ServerStats::default()
+        );
+    }
+
+    #[test]
+    fn test_probe_command() {
+        assert_eq!(
+            probe_command(2182, "ruok", "^imok"),
+            vec![
+                "bash",
+                "-c",
+                "exec 3<>/dev/tcp/127.0.0.1/2182 && printf ruok >&3 && grep -qE '^imok' <&3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_stats() {
+        let address = mock_server(vec![("srvr", SRVR_FOLLOWER.to_string())]).await;
+
+        let stats = server_stats(&address, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(stats.mode, Some(ServerMode::Follower));
+        assert_eq!(stats.zxid, Some("0x100000002".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_server_stats_unreachable() {
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        assert!(server_stats(&address, Duration::from_secs(5))
+            .await
+            .is_err());
+    }
+}
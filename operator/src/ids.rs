@@ -0,0 +1,219 @@
+//! Detects pods that disagree about the `myid` of the servers.
+//!
+//! The ids recorded in `status.servers` are authoritative: a pod whose id label doesn't match the
+//! id of its server is stale and is replaced. When several pods claim the same id and the status
+//! can't tell which one is right (e.g. because the pods were created before servers were recorded
+//! in the status) the conflict has to be resolved by annotating the pod that should keep the id
+//! with [`KEEP_ID_ANNOTATION`].
+use stackable_zookeeper_crd::ServerStatus;
+use std::collections::BTreeMap;
+
+/// Marks the pod that keeps its id when several pods use the same id, all others are deleted
+pub const KEEP_ID_ANNOTATION: &str = "zookeeper.stackable.tech/keep-id";
+
+/// The id a pod runs with as read from its labels and annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PodId {
+    pub pod_name: String,
+    /// The server the pod belongs to, pods created before servers were recorded don't have one
+    pub server_name: Option<String>,
+    pub id: usize,
+    /// Whether the pod is annotated with [`KEEP_ID_ANNOTATION`]
+    pub keep: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct IdConflicts {
+    /// Pods whose id is known to be wrong, they can be deleted and will be recreated with the
+    /// right id
+    pub stale_pods: Vec<String>,
+    /// Ids that are used by several pods without a way to tell which one is right, with the names
+    /// of these pods
+    pub unresolved: BTreeMap<usize, Vec<String>>,
+}
+
+impl IdConflicts {
+    /// A human readable description of the unresolved conflicts.
+    pub fn describe_unresolved(&self) -> String {
+        self.unresolved
+            .iter()
+            .map(|(id, pod_names)| format!("myid [{}] is used by pods {:?}", id, pod_names))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Checks the ids of the `pods` against the ids of the `servers` recorded in the status.
+///
+/// A pod is stale when
+/// * its server is recorded with a different id or
+/// * another pod uses the same id and either belongs to the server recorded with this id or
+///   - if no pod does - is annotated with [`KEEP_ID_ANNOTATION`].
+pub fn find_id_conflicts(pods: &[PodId], servers: &BTreeMap<String, ServerStatus>) -> IdConflicts {
+    let mut conflicts = IdConflicts::default();
+    let mut pods_by_id: BTreeMap<usize, Vec<&PodId>> = BTreeMap::new();
+
+    for pod in pods {
+        let recorded_id = pod
+            .server_name
+            .as_ref()
+            .and_then(|server_name| servers.get(server_name))
+            .map(|server| server.id);
+        match recorded_id {
+            Some(recorded_id) if recorded_id != pod.id => {
+                conflicts.stale_pods.push(pod.pod_name.clone())
+            }
+            _ => pods_by_id.entry(pod.id).or_default().push(pod),
+        }
+    }
+
+    for (id, pods) in pods_by_id {
+        if pods.len() < 2 {
+            continue;
+        }
+
+        let owners = pods
+            .iter()
+            .filter(|pod| {
+                pod.server_name
+                    .as_ref()
+                    .and_then(|server_name| servers.get(server_name))
+                    .is_some()
+            })
+            .collect::<Vec<_>>();
+        let kept = pods.iter().filter(|pod| pod.keep).collect::<Vec<_>>();
+        let winner = match (owners.as_slice(), kept.as_slice()) {
+            ([owner], _) => owner.pod_name.clone(),
+            ([], [kept]) => kept.pod_name.clone(),
+            _ => {
+                conflicts
+                    .unresolved
+                    .insert(id, pods.iter().map(|pod| pod.pod_name.clone()).collect());
+                continue;
+            }
+        };
+
+        conflicts.stale_pods.extend(
+            pods.iter()
+                .filter(|pod| pod.pod_name != winner)
+                .map(|pod| pod.pod_name.clone()),
+        );
+    }
+
+    conflicts.stale_pods.sort();
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stackable_zookeeper_crd::PeerType;
+
+    fn pod(pod_name: &str, server_name: Option<&str>, id: usize, keep: bool) -> PodId {
+        PodId {
+            pod_name: pod_name.to_string(),
+            server_name: server_name.map(str::to_string),
+            id,
+            keep,
+        }
+    }
+
+    fn servers(servers: &[(&str, usize)]) -> BTreeMap<String, ServerStatus> {
+        servers
+            .iter()
+            .map(|(name, id)| {
+                (
+                    name.to_string(),
+                    ServerStatus {
+                        role_group: "default".to_string(),
+                        ordinal: 0,
+                        id: *id,
+                        node_name: "node-1".to_string(),
+                        node_slot: 0,
+                        peer_type: PeerType::Participant,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_no_conflicts() {
+        let pods = vec![
+            pod("pod-0", Some("default-0"), 1, false),
+            pod("pod-1", Some("default-1"), 2, false),
+            pod("pod-2", None, 3, false),
+        ];
+
+        let conflicts = find_id_conflicts(&pods, &servers(&[("default-0", 1), ("default-1", 2)]));
+
+        assert_eq!(conflicts, IdConflicts::default());
+    }
+
+    #[test]
+    fn test_pod_with_wrong_id_is_stale() {
+        let pods = vec![
+            pod("pod-0", Some("default-0"), 1, false),
+            pod("pod-1", Some("default-1"), 1, false),
+        ];
+
+        let conflicts = find_id_conflicts(&pods, &servers(&[("default-0", 1), ("default-1", 2)]));
+
+        assert_eq!(conflicts.stale_pods, vec!["pod-1"]);
+        assert!(conflicts.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_recorded_server_wins() {
+        let pods = vec![
+            pod("pod-0", None, 1, true),
+            pod("pod-1", Some("default-0"), 1, false),
+        ];
+
+        let conflicts = find_id_conflicts(&pods, &servers(&[("default-0", 1)]));
+
+        assert_eq!(conflicts.stale_pods, vec!["pod-0"]);
+        assert!(conflicts.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_unresolved_conflict() {
+        let pods = vec![
+            pod("pod-0", None, 1, false),
+            pod("pod-1", None, 1, false),
+            pod("pod-2", None, 2, false),
+        ];
+
+        let conflicts = find_id_conflicts(&pods, &BTreeMap::new());
+
+        assert!(conflicts.stale_pods.is_empty());
+        assert_eq!(
+            conflicts.describe_unresolved(),
+            r#"myid [1] is used by pods ["pod-0", "pod-1"]"#
+        );
+    }
+
+    #[test]
+    fn test_resolve_conflict_with_annotation() {
+        let pods = vec![
+            pod("pod-0", None, 1, false),
+            pod("pod-1", None, 1, true),
+            pod("pod-2", None, 1, false),
+        ];
+
+        let conflicts = find_id_conflicts(&pods, &BTreeMap::new());
+
+        assert_eq!(conflicts.stale_pods, vec!["pod-0", "pod-2"]);
+        assert!(conflicts.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_several_annotated_pods_are_unresolved() {
+        let pods = vec![pod("pod-0", None, 1, true), pod("pod-1", None, 1, true)];
+
+        let conflicts = find_id_conflicts(&pods, &BTreeMap::new());
+
+        assert!(conflicts.stale_pods.is_empty());
+        assert_eq!(conflicts.unresolved.len(), 1);
+    }
+}
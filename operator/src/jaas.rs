@@ -0,0 +1,254 @@
+//! The JAAS configuration of the servers for SASL authentication.
+//!
+//! It configures the mechanisms enabled in `authentication` and contains the passwords of the
+//! DIGEST-MD5 users, so it is kept in the Secret `<pod name>-jaas` next to the `-config` ConfigMap
+//! instead of in the ConfigMap itself.
+use stackable_zookeeper_crd::AuthenticationConfig;
+use std::collections::BTreeMap;
+
+/// The Secret with the JAAS configuration is mounted into this directory
+pub const JAAS_DIR: &str = "/etc/zookeeper/jaas";
+/// The key of the JAAS configuration in its Secret
+pub const JAAS_FILE: &str = "jaas.conf";
+/// The Secret with the keytab of `kerberos` is mounted into this directory
+pub const KERBEROS_DIR: &str = "/etc/zookeeper/kerberos";
+/// The key of the keytab in its Secret
+pub const KEYTAB_KEY: &str = "keytab";
+/// The ConfigMap with the `krb5.conf` of `kerberos` is mounted into this directory
+pub const KRB5_DIR: &str = "/etc/zookeeper/krb5";
+/// The key of the Kerberos configuration in its ConfigMap
+pub const KRB5_CONF_KEY: &str = "krb5.conf";
+/// The key of the user name in the Secret of `authentication.quorumSecretName`
+pub const QUORUM_USERNAME_KEY: &str = "username";
+/// The key of the password in the Secret of `authentication.quorumSecretName`
+pub const QUORUM_PASSWORD_KEY: &str = "password";
+
+const DIGEST_LOGIN_MODULE: &str = "org.apache.zookeeper.server.auth.DigestLoginModule";
+const KRB5_LOGIN_MODULE: &str = "com.sun.security.auth.module.Krb5LoginModule";
+
+/// The DIGEST-MD5 credentials the servers authenticate each other with
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuorumCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+pub fn jaas_secret_name(pod_name: &str) -> String {
+    format!("{}-jaas", pod_name)
+}
+
+/// Whether the name can be used for a DIGEST-MD5 user, it becomes part of the option
+/// `user_<name>` which must be a single word for the JAAS parser.
+pub fn is_valid_user_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '$')
+}
+
+/// Builds the JAAS configuration of a server: the `Server` section for client connections and -
+/// with `quorum_credentials` - the `QuorumServer` and `QuorumLearner` sections for the
+/// connections between the servers.
+///
+/// `digest_users` maps the names of the DIGEST-MD5 users to their passwords, `host` is the DNS
+/// name of the server which is used in its Kerberos principal.
+pub fn build_jaas_config(
+    authentication: &AuthenticationConfig,
+    digest_users: &BTreeMap<String, String>,
+    quorum_credentials: Option<&QuorumCredentials>,
+    host: &str,
+) -> String {
+    let mut sections = vec![];
+    if authentication.authenticates_clients() {
+        sections.push(section(
+            "Server",
+            &client_login_modules(authentication, digest_users, host),
+        ));
+    }
+    // The sections are rendered in every stage of `QuorumSaslStage`, ZooKeeper only uses them
+    // once `quorum.auth.enableSasl` is set
+    if let Some(credentials) = quorum_credentials {
+        sections.push(section(
+            "QuorumServer",
+            &[login_module(
+                DIGEST_LOGIN_MODULE,
+                vec![(
+                    format!("user_{}", credentials.username),
+                    quote(&credentials.password),
+                )],
+            )],
+        ));
+        sections.push(section(
+            "QuorumLearner",
+            &[login_module(
+                DIGEST_LOGIN_MODULE,
+                vec![
+                    ("username".to_string(), quote(&credentials.username)),
+                    ("password".to_string(), quote(&credentials.password)),
+                ],
+            )],
+        ));
+    }
+
+    sections.concat()
+}
+
+fn client_login_modules(
+    authentication: &AuthenticationConfig,
+    digest_users: &BTreeMap<String, String>,
+    host: &str,
+) -> Vec<String> {
+    let mut modules = vec![];
+    if authentication.digest.is_some() {
+        modules.push(login_module(
+            DIGEST_LOGIN_MODULE,
+            digest_users
+                .iter()
+                .map(|(name, password)| (format!("user_{}", name), quote(password)))
+                .collect(),
+        ));
+    }
+    if let Some(kerberos) = &authentication.kerberos {
+        modules.push(login_module(
+            KRB5_LOGIN_MODULE,
+            vec![
+                ("useKeyTab".to_string(), "true".to_string()),
+                (
+                    "keyTab".to_string(),
+                    quote(&format!("{}/{}", KERBEROS_DIR, KEYTAB_KEY)),
+                ),
+                ("storeKey".to_string(), "true".to_string()),
+                ("useTicketCache".to_string(), "false".to_string()),
+                (
+                    "principal".to_string(),
+                    quote(&kerberos.server_principal(host)),
+                ),
+            ],
+        ));
+    }
+    modules
+}
+
+fn section(name: &str, modules: &[String]) -> String {
+    format!("{} {{\n{}}};\n", name, modules.concat())
+}
+
+fn login_module(class: &str, options: Vec<(String, String)>) -> String {
+    let mut module = format!("  {} required", class);
+    for (key, value) in options {
+        module.push_str(&format!("\n    {}={}", key, value));
+    }
+    module.push_str(";\n");
+    module
+}
+
+/// Quotes a value, backslashes and quotes are escaped as the JAAS parser expects
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use stackable_zookeeper_crd::{DigestAuthentication, KerberosAuthentication};
+
+    #[test]
+    fn test_build_jaas_config() {
+        let authentication = AuthenticationConfig {
+            digest: Some(DigestAuthentication {
+                secret_name: "simple-users".to_string(),
+            }),
+            kerberos: Some(KerberosAuthentication {
+                keytab_secret_name: "simple-keytab".to_string(),
+                principal: "zookeeper/{host}@EXAMPLE.COM".to_string(),
+                krb5_config_map_name: None,
+            }),
+            require_client_auth: None,
+            quorum: None,
+            quorum_secret_name: None,
+        };
+        let mut digest_users = BTreeMap::new();
+        digest_users.insert("kafka".to_string(), "kafka-secret".to_string());
+        digest_users.insert("admin".to_string(), r#"with "quotes" and \"#.to_string());
+
+        assert_eq!(
+            build_jaas_config(
+                &authentication,
+                &digest_users,
+                None,
+                "default-0.simple-servers.default.svc"
+            ),
+            indoc! {r#"
+                Server {
+                  org.apache.zookeeper.server.auth.DigestLoginModule required
+                    user_admin="with \"quotes\" and \\"
+                    user_kafka="kafka-secret";
+                  com.sun.security.auth.module.Krb5LoginModule required
+                    useKeyTab=true
+                    keyTab="/etc/zookeeper/kerberos/keytab"
+                    storeKey=true
+                    useTicketCache=false
+                    principal="zookeeper/default-0.simple-servers.default.svc@EXAMPLE.COM";
+                };
+            "#}
+        );
+    }
+
+    #[test]
+    fn test_build_jaas_config_without_users() {
+        let authentication = AuthenticationConfig {
+            digest: Some(DigestAuthentication {
+                secret_name: "simple-users".to_string(),
+            }),
+            ..AuthenticationConfig::default()
+        };
+
+        assert_eq!(
+            build_jaas_config(&authentication, &BTreeMap::new(), None, "node-1"),
+            "Server {\n  org.apache.zookeeper.server.auth.DigestLoginModule required;\n};\n"
+        );
+    }
+
+    #[test]
+    fn test_build_jaas_config_quorum() {
+        let authentication = AuthenticationConfig {
+            quorum: Some(true),
+            quorum_secret_name: Some("simple-quorum".to_string()),
+            ..AuthenticationConfig::default()
+        };
+        let credentials = QuorumCredentials {
+            username: "zookeeper".to_string(),
+            password: "quorum-secret".to_string(),
+        };
+
+        assert_eq!(
+            build_jaas_config(
+                &authentication,
+                &BTreeMap::new(),
+                Some(&credentials),
+                "node-1"
+            ),
+            indoc! {r#"
+                QuorumServer {
+                  org.apache.zookeeper.server.auth.DigestLoginModule required
+                    user_zookeeper="quorum-secret";
+                };
+                QuorumLearner {
+                  org.apache.zookeeper.server.auth.DigestLoginModule required
+                    username="zookeeper"
+                    password="quorum-secret";
+                };
+            "#}
+        );
+    }
+
+    #[test]
+    fn test_is_valid_user_name() {
+        assert!(is_valid_user_name("kafka"));
+        assert!(is_valid_user_name("super_user-1"));
+        assert!(!is_valid_user_name(""));
+        assert!(!is_valid_user_name("user name"));
+        assert!(!is_valid_user_name("user=name"));
+    }
+}
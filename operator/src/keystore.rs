@@ -0,0 +1,462 @@
+//! Key stores and trust stores for the certificates issued by the operator.
+//!
+//! The JVM only reads key material from JKS and PKCS#12 files. JKS is simple enough to be written
+//! by hand. PKCS#12 key stores are built with the `p12` crate, but its trust stores are written by
+//! hand as well: Java only trusts certificates in PKCS#12 files that carry its proprietary
+//! `trustedKeyUsage` attribute.
+use crate::error::Error;
+use k8s_openapi::chrono::{DateTime, Utc};
+use rand::RngCore;
+use sha1::Sha1;
+
+const JKS_MAGIC: u32 = 0xfeed_feed;
+const JKS_VERSION: u32 = 2;
+const JKS_PRIVATE_KEY_TAG: u32 = 1;
+const JKS_TRUSTED_CERTIFICATE_TAG: u32 = 2;
+/// Mixed into the integrity digest of every JKS file
+const JKS_DIGEST_WHITENER: &[u8] = b"Mighty Aphrodite";
+/// Sun's proprietary algorithm protecting private keys in JKS files
+const JKS_KEY_PROTECTOR_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 42, 2, 17, 1, 1];
+
+const PKCS7_DATA_OID: &[u64] = &[1, 2, 840, 113_549, 1, 7, 1];
+const PKCS12_CERT_BAG_OID: &[u64] = &[1, 2, 840, 113_549, 1, 12, 10, 1, 3];
+const X509_CERTIFICATE_OID: &[u64] = &[1, 2, 840, 113_549, 1, 9, 22, 1];
+const FRIENDLY_NAME_OID: &[u64] = &[1, 2, 840, 113_549, 1, 9, 20];
+const ORACLE_TRUSTED_KEY_USAGE_OID: &[u64] = &[2, 16, 840, 1, 113_894, 746_875, 1, 1];
+const ANY_EXTENDED_KEY_USAGE_OID: &[u64] = &[2, 5, 29, 37, 0];
+const SHA1_OID: &[u64] = &[1, 3, 14, 3, 2, 26];
+const PKCS12_MAC_ITERATIONS: u32 = 2048;
+
+const SALT_LENGTH: usize = 20;
+
+/// A JKS key store with the private key (PKCS#8) and its certificate chain under `alias`.
+pub fn jks_key_store(
+    alias: &str,
+    private_key_der: &[u8],
+    chain_der: &[Vec<u8>],
+    password: &str,
+    created_at: DateTime<Utc>,
+) -> Vec<u8> {
+    let password = utf16_be(password);
+    let mut writer = JksWriter::new(1);
+    writer.write_u32(JKS_PRIVATE_KEY_TAG);
+    writer.write_utf(alias);
+    writer.write_timestamp(created_at);
+    writer.write_bytes(&protect_jks_key(private_key_der, &password, random_salt()));
+    writer.write_u32(chain_der.len() as u32);
+    for certificate in chain_der {
+        writer.write_certificate(certificate);
+    }
+    writer.finish(&password)
+}
+
+/// A JKS trust store with the given certificates.
+pub fn jks_trust_store(
+    certificates_der: &[Vec<u8>],
+    password: &str,
+    created_at: DateTime<Utc>,
+) -> Vec<u8> {
+    let mut writer = JksWriter::new(certificates_der.len());
+    for (index, certificate) in certificates_der.iter().enumerate() {
+        writer.write_u32(JKS_TRUSTED_CERTIFICATE_TAG);
+        writer.write_utf(&trusted_alias(index));
+        writer.write_timestamp(created_at);
+        writer.write_certificate(certificate);
+    }
+    writer.finish(&utf16_be(password))
+}
+
+/// A PKCS#12 key store with the private key (PKCS#8) and its certificate issued by `ca_der`
+/// under `alias`.
+pub fn pkcs12_key_store(
+    alias: &str,
+    private_key_der: &[u8],
+    certificate_der: &[u8],
+    ca_der: &[u8],
+    password: &str,
+) -> Result<Vec<u8>, Error> {
+    p12::PFX::new(
+        certificate_der,
+        private_key_der,
+        Some(ca_der),
+        password,
+        alias,
+    )
+    .map(|pfx| pfx.to_der())
+    .ok_or_else(|| Error::ReconcileError("Could not build the PKCS#12 key store".to_string()))
+}
+
+/// A PKCS#12 trust store with the given certificates. The certificates are not encrypted, only
+/// the integrity of the file is protected by the password.
+pub fn pkcs12_trust_store(certificates_der: &[Vec<u8>], password: &str) -> Vec<u8> {
+    let bags = certificates_der
+        .iter()
+        .enumerate()
+        .map(|(index, certificate)| {
+            der::sequence(&[
+                der::oid(PKCS12_CERT_BAG_OID),
+                der::explicit(
+                    0,
+                    &der::sequence(&[
+                        der::oid(X509_CERTIFICATE_OID),
+                        der::explicit(0, &der::octet_string(certificate)),
+                    ]),
+                ),
+                der::set(&[
+                    der::sequence(&[
+                        der::oid(FRIENDLY_NAME_OID),
+                        der::set(&[der::bmp_string(&trusted_alias(index))]),
+                    ]),
+                    der::sequence(&[
+                        der::oid(ORACLE_TRUSTED_KEY_USAGE_OID),
+                        der::set(&[der::oid(ANY_EXTENDED_KEY_USAGE_OID)]),
+                    ]),
+                ]),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let authenticated_safe = der::sequence(&[data_content_info(&der::sequence(&bags))]);
+
+    let salt = random_salt();
+    let mac_key = pkcs12_mac_key(password, &salt, PKCS12_MAC_ITERATIONS);
+    let mac = hmac_sha1(&mac_key, &authenticated_safe);
+
+    der::sequence(&[
+        der::integer(3),
+        data_content_info(&authenticated_safe),
+        der::sequence(&[
+            der::sequence(&[
+                der::sequence(&[der::oid(SHA1_OID), der::null()]),
+                der::octet_string(&mac),
+            ]),
+            der::octet_string(&salt),
+            der::integer(PKCS12_MAC_ITERATIONS),
+        ]),
+    ])
+}
+
+fn trusted_alias(index: usize) -> String {
+    format!("ca-{}", index)
+}
+
+fn data_content_info(content: &[u8]) -> Vec<u8> {
+    der::sequence(&[
+        der::oid(PKCS7_DATA_OID),
+        der::explicit(0, &der::octet_string(content)),
+    ])
+}
+
+fn random_salt() -> [u8; SALT_LENGTH] {
+    let mut salt = [0; SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// Java passwords are arrays of UTF-16 code units
+fn utf16_be(password: &str) -> Vec<u8> {
+    password
+        .encode_utf16()
+        .flat_map(|unit| unit.to_be_bytes().to_vec())
+        .collect()
+}
+
+fn sha1_digest(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hash = Sha1::new();
+    for part in parts {
+        hash.update(part);
+    }
+    hash.digest().bytes()
+}
+
+/// Protects a private key like `sun.security.provider.KeyProtector`: It is XORed with a stream
+/// of SHA-1 hashes over the password and the previous hash (starting with the salt) and followed
+/// by a hash over the password and the plain key to detect wrong passwords.
+fn protect_jks_key(private_key_der: &[u8], password: &[u8], salt: [u8; SALT_LENGTH]) -> Vec<u8> {
+    let mut protected = salt.to_vec();
+    let mut digest = salt;
+    for chunk in private_key_der.chunks(digest.len()) {
+        digest = sha1_digest(&[password, &digest]);
+        protected.extend(
+            chunk
+                .iter()
+                .zip(digest.iter())
+                .map(|(key, mask)| key ^ mask),
+        );
+    }
+    protected.extend_from_slice(&sha1_digest(&[password, private_key_der]));
+
+    der::sequence(&[
+        der::sequence(&[der::oid(JKS_KEY_PROTECTOR_OID), der::null()]),
+        der::octet_string(&protected),
+    ])
+}
+
+/// The key of the MAC protecting a PKCS#12 file (RFC 7292, appendix B.2 with SHA-1 and the id
+/// `3`). It is as long as a single SHA-1 hash, so only the first block needs to be derived.
+fn pkcs12_mac_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 20] {
+    const BLOCK_LENGTH: usize = 64;
+    let fill_blocks = |bytes: &[u8]| -> Vec<u8> {
+        let length = BLOCK_LENGTH * ((bytes.len() + BLOCK_LENGTH - 1) / BLOCK_LENGTH);
+        bytes.iter().cycle().take(length).copied().collect()
+    };
+
+    // The password is a null terminated BMPString
+    let mut password = utf16_be(password);
+    password.extend_from_slice(&[0, 0]);
+
+    let mut input = vec![3; BLOCK_LENGTH];
+    input.extend(fill_blocks(salt));
+    input.extend(fill_blocks(&password));
+
+    let mut key = sha1_digest(&[&input]);
+    for _ in 1..iterations {
+        key = sha1_digest(&[&key]);
+    }
+    key
+}
+
+/// HMAC (RFC 2104) with SHA-1 for keys up to the block length of 64 bytes.
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    let mut inner_key = [0x36; 64];
+    let mut outer_key = [0x5c; 64];
+    for (index, byte) in key.iter().enumerate() {
+        inner_key[index] ^= byte;
+        outer_key[index] ^= byte;
+    }
+
+    let inner = sha1_digest(&[&inner_key, message]);
+    sha1_digest(&[&outer_key, &inner])
+}
+
+/// Writes the big endian encoding of `java.security.KeyStore` files of type JKS.
+struct JksWriter {
+    data: Vec<u8>,
+}
+
+impl JksWriter {
+    fn new(entries: usize) -> Self {
+        let mut writer = JksWriter { data: vec![] };
+        writer.write_u32(JKS_MAGIC);
+        writer.write_u32(JKS_VERSION);
+        writer.write_u32(entries as u32);
+        writer
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn write_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.data
+            .extend_from_slice(&timestamp.timestamp_millis().to_be_bytes());
+    }
+
+    /// Strings are written like `DataOutputStream.writeUTF`, which only differs from UTF-8 for
+    /// characters aliases don't use
+    fn write_utf(&mut self, value: &str) {
+        self.data
+            .extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    fn write_certificate(&mut self, certificate_der: &[u8]) {
+        self.write_utf("X.509");
+        self.write_bytes(certificate_der);
+    }
+
+    /// Appends the integrity digest over the password and everything written so far.
+    fn finish(mut self, password: &[u8]) -> Vec<u8> {
+        let digest = sha1_digest(&[password, JKS_DIGEST_WHITENER, &self.data]);
+        self.data.extend_from_slice(&digest);
+        self.data
+    }
+}
+
+/// The parts of DER needed for the stores.
+mod der {
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        if content.len() < 0x80 {
+            encoded.push(content.len() as u8);
+        } else {
+            let length = (content.len() as u32).to_be_bytes();
+            let significant = &length[length.iter().take_while(|byte| **byte == 0).count()..];
+            encoded.push(0x80 | significant.len() as u8);
+            encoded.extend_from_slice(significant);
+        }
+        encoded.extend_from_slice(content);
+        encoded
+    }
+
+    pub fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+        tlv(0x30, &items.concat())
+    }
+
+    /// The items of a `SET OF` need to be sorted by their encoding
+    pub fn set(items: &[Vec<u8>]) -> Vec<u8> {
+        let mut items = items.to_vec();
+        items.sort();
+        tlv(0x31, &items.concat())
+    }
+
+    pub fn integer(value: u32) -> Vec<u8> {
+        let bytes = value.to_be_bytes();
+        let mut start = bytes.iter().take_while(|byte| **byte == 0).count();
+        // Keep a leading zero if the value would be negative otherwise
+        if start == bytes.len() || bytes[start] & 0x80 != 0 {
+            start -= 1;
+        }
+        tlv(0x02, &bytes[start..])
+    }
+
+    pub fn octet_string(content: &[u8]) -> Vec<u8> {
+        tlv(0x04, content)
+    }
+
+    pub fn null() -> Vec<u8> {
+        tlv(0x05, &[])
+    }
+
+    pub fn oid(arcs: &[u64]) -> Vec<u8> {
+        let mut content = vec![];
+        let first = arcs[0] * 40 + arcs[1];
+        for arc in std::iter::once(first).chain(arcs[2..].iter().copied()) {
+            let mut base128 = vec![(arc & 0x7f) as u8];
+            let mut rest = arc >> 7;
+            while rest > 0 {
+                base128.push((rest & 0x7f) as u8 | 0x80);
+                rest >>= 7;
+            }
+            content.extend(base128.iter().rev());
+        }
+        tlv(0x06, &content)
+    }
+
+    pub fn bmp_string(value: &str) -> Vec<u8> {
+        tlv(0x1e, &super::utf16_be(value))
+    }
+
+    pub fn explicit(tag_number: u8, content: &[u8]) -> Vec<u8> {
+        tlv(0xa0 | tag_number, content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_be_bytes(bytes)
+    }
+
+    #[test]
+    fn test_jks_trust_store() {
+        let certificates = vec![b"first".to_vec(), b"second".to_vec()];
+
+        let store = jks_trust_store(&certificates, "changeit", Utc::now());
+
+        assert_eq!(read_u32(&store, 0), JKS_MAGIC);
+        assert_eq!(read_u32(&store, 4), JKS_VERSION);
+        assert_eq!(read_u32(&store, 8), 2);
+        assert_eq!(read_u32(&store, 12), JKS_TRUSTED_CERTIFICATE_TAG);
+        // The alias follows as length and bytes
+        assert_eq!(&store[16..22], b"\x00\x04ca-0");
+
+        let (content, digest) = store.split_at(store.len() - 20);
+        assert_eq!(
+            digest,
+            sha1_digest(&[&utf16_be("changeit"), JKS_DIGEST_WHITENER, content])
+        );
+    }
+
+    #[test]
+    fn test_protect_jks_key() {
+        let private_key = (0..50).collect::<Vec<u8>>();
+        let password = utf16_be("changeit");
+        let salt = [7; SALT_LENGTH];
+
+        let protected = protect_jks_key(&private_key, &password, salt);
+
+        // Two nested sequences and the OID header, then the octet string with salt, key and check
+        let encrypted = &protected[protected.len() - (SALT_LENGTH + 50 + 20)..];
+        assert_eq!(&encrypted[..SALT_LENGTH], &salt);
+        let mut mask = salt;
+        let mut recovered = vec![];
+        for chunk in encrypted[SALT_LENGTH..SALT_LENGTH + 50].chunks(20) {
+            mask = sha1_digest(&[&password, &mask]);
+            recovered.extend(
+                chunk
+                    .iter()
+                    .zip(mask.iter())
+                    .map(|(byte, mask)| byte ^ mask),
+            );
+        }
+        assert_eq!(recovered, private_key);
+        assert_eq!(
+            &encrypted[SALT_LENGTH + 50..],
+            &sha1_digest(&[&password, &private_key])
+        );
+    }
+
+    #[test]
+    fn test_pkcs12_trust_store() {
+        let certificates = vec![b"first".to_vec(), b"second".to_vec()];
+
+        let store = pkcs12_trust_store(&certificates, "changeit");
+
+        let pfx = p12::PFX::parse(&store).unwrap();
+        assert!(pfx.verify_mac("changeit"));
+        assert!(!pfx.verify_mac("wrong"));
+        assert_eq!(pfx.cert_x509_bags("changeit").unwrap(), certificates);
+    }
+
+    // Test cases 1 and 2 of RFC 2202
+    #[rstest]
+    #[case(&[0x0b; 20], b"Hi There", "b617318655057264e28bc0b6fb378c8ef146be00")]
+    #[case(
+        b"Jefe",
+        b"what do ya want for nothing?",
+        "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+    )]
+    fn test_hmac_sha1(#[case] key: &[u8], #[case] message: &[u8], #[case] expected: &str) {
+        let mac = hmac_sha1(key, message)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        assert_eq!(mac, expected);
+    }
+
+    #[rstest]
+    #[case(0, &[0x02, 0x01, 0x00])]
+    #[case(3, &[0x02, 0x01, 0x03])]
+    #[case(128, &[0x02, 0x02, 0x00, 0x80])]
+    #[case(2048, &[0x02, 0x02, 0x08, 0x00])]
+    fn test_der_integer(#[case] value: u32, #[case] expected: &[u8]) {
+        assert_eq!(der::integer(value), expected);
+    }
+
+    #[test]
+    fn test_der_oid() {
+        assert_eq!(
+            der::oid(PKCS7_DATA_OID),
+            vec![0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_der_long_length() {
+        let encoded = der::octet_string(&[0; 300]);
+
+        assert_eq!(&encoded[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(encoded.len(), 304);
+    }
+}
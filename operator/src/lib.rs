@@ -1,24 +1,53 @@
+mod certificates;
+mod config;
+mod discovery;
 mod error;
+mod four_letter_words;
+mod ids;
+mod jaas;
+mod keystore;
+mod placement;
+mod reconfig;
+pub mod webhook;
+mod zk_client;
+#[cfg(test)]
+mod zk_stand_in;
+mod znode;
 
+use crate::certificates::{server_tls_secret_name, CertificateAuthority};
+use crate::config::ServerEntry;
 use crate::error::Error;
+use crate::four_letter_words::{ServerMode, ServerStats};
+use crate::ids::{PodId, KEEP_ID_ANNOTATION};
+use crate::placement::ServerLocation;
+use crate::reconfig::{MembershipStrategy, SUPER_DIGEST_KEY, SUPER_PASSWORD_KEY, SUPER_USER};
+use crate::zk_client::{error_code, ZookeeperClient, ZOOKEEPER_CONFIG_NODE};
+pub use crate::znode::create_znode_controller;
 
 use async_trait::async_trait;
-use handlebars::Handlebars;
 use k8s_openapi::api::core::v1::{
-    ConfigMap, ConfigMapVolumeSource, Container, Node, Pod, PodSpec, Volume, VolumeMount,
+    ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, EnvVar, EnvVarSource, ExecAction,
+    HostPathVolumeSource, Node, PersistentVolumeClaim, PersistentVolumeClaimSpec,
+    PersistentVolumeClaimVolumeSource, Pod, PodSpec, Probe, ResourceRequirements, Secret,
+    SecretKeySelector, SecretVolumeSource, Service, ServicePort, ServiceSpec, Volume, VolumeMount,
 };
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
 use kube::api::{ListParams, Resource};
 use kube::Api;
 use serde_json::json;
 use tracing::{debug, error, info, trace, warn};
 
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{
+    Condition, LabelSelector, LabelSelectorRequirement, Time,
+};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::chrono::{DateTime, Utc};
 use stackable_operator::client::Client;
 use stackable_operator::conditions::ConditionStatus;
 use stackable_operator::controller::Controller;
 use stackable_operator::controller::{ControllerStrategy, ReconciliationState};
 use stackable_operator::error::OperatorResult;
-use stackable_operator::k8s_utils::LabelOptionalValueMap;
+use stackable_operator::krustlet;
 use stackable_operator::labels;
 use stackable_operator::metadata;
 use stackable_operator::reconcile::{
@@ -26,10 +55,13 @@ use stackable_operator::reconcile::{
 };
 use stackable_operator::role_utils::RoleGroup;
 use stackable_operator::{config_map, role_utils};
-use stackable_operator::{k8s_utils, krustlet};
+use stackable_zookeeper_crd::error::Error as CrdError;
+use stackable_zookeeper_crd::util::{headless_service_name, server_dns_name};
 use stackable_zookeeper_crd::{
-    ZookeeperCluster, ZookeeperClusterSpec, ZookeeperClusterStatus, ZookeeperVersion, APP_NAME,
-    MANAGED_BY,
+    PeerType, PersistentVolumeClaimStorage, QuarantinedId, QuorumSaslStage, QuorumTlsStage,
+    RollingRestartStatus, ServerHealth, ServerStatus, TlsConfig, ZookeeperCluster,
+    ZookeeperClusterSpec, ZookeeperClusterStatus, ZookeeperConfig, ZookeeperVersion, APP_NAME,
+    MANAGED_BY, NODE_SLOT_LABEL,
 };
 use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
@@ -42,12 +74,60 @@ use strum_macros::EnumIter;
 const FINALIZER_NAME: &str = "zookeeper.stackable.tech/cleanup";
 
 const ID_LABEL: &str = "zookeeper.stackable.tech/id";
+/// The name of the server (see [`ServerStatus`]) running in a pod
+const SERVER_LABEL: &str = "zookeeper.stackable.tech/server";
+
+/// Names of the container ports, Services refer to them so every server can use its own ports
+const CLIENT_PORT_NAME: &str = "client";
+const SECURE_CLIENT_PORT_NAME: &str = "secure-client";
+const QUORUM_PORT_NAME: &str = "quorum";
+const LEADER_ELECTION_PORT_NAME: &str = "leader-election";
+
+const ZOO_CFG: &str = "zoo.cfg";
+const ZOO_CFG_DYNAMIC: &str = "zoo.cfg.dynamic";
+
+const UPGRADE_BLOCKED_CONDITION: &str = "UpgradeBlocked";
+const DEGRADED_CONDITION: &str = "Degraded";
+const AVAILABLE_CONDITION: &str = "Available";
+const ID_CONFLICT_CONDITION: &str = "IdConflict";
+
+/// An upgrade is rolled back if no server could be replaced for this long
+const UPGRADE_STEP_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long to wait for a single request to a ZooKeeper server
+const ZK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the health of the servers is recorded in the status
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for the statistics of a server (during the health check or before the next
+/// step of an upgrade), a server that doesn't answer in time is treated as not serving
+const SERVER_STATS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A server is ready when it serves requests, i.e. when it is part of a quorum or - as an
+/// observer - follows one
+const READINESS_PATTERN: &str = "^Mode: (leader|follower|observer|standalone)";
+/// A server is alive as long as it answers `ruok`, even without a quorum
+const LIVENESS_PATTERN: &str = "^imok";
+/// Loading a large snapshot takes a while, the server doesn't answer before it is done
+const LIVENESS_INITIAL_DELAY_SECONDS: i32 = 60;
 
 type ZookeeperReconcileResult = ReconcileResult<error::Error>;
 
 #[derive(EnumIter, Debug, Display, PartialEq, Eq, Hash)]
 pub enum ZookeeperRole {
     Server,
+    Observer,
+}
+
+impl ZookeeperRole {
+    /// The role of the pods running servers of the given peer type
+    fn for_peer_type(peer_type: PeerType) -> Self {
+        match peer_type {
+            PeerType::Participant => ZookeeperRole::Server,
+            PeerType::Observer => ZookeeperRole::Observer,
+        }
+    }
 }
 
 struct ZookeeperState {
@@ -57,32 +137,42 @@ struct ZookeeperState {
     id_information: Option<IdInformation>,
     existing_pods: Vec<Pod>,
     eligible_nodes: HashMap<ZookeeperRole, HashMap<String, Vec<Node>>>,
+    super_password: Option<String>,
+    /// The serial numbers of the certificates issued for the servers by pod name, see
+    /// `issue_certificates`
+    issued_certificates: BTreeMap<String, String>,
+    /// The DIGEST-MD5 users of `authentication` with their passwords, see `read_digest_users`
+    digest_users: BTreeMap<String, String>,
+    /// The credentials the servers authenticate each other with, see `read_quorum_credentials`
+    quorum_credentials: Option<jaas::QuorumCredentials>,
 }
 
 struct IdInformation {
     used_ids: Vec<usize>,
-    node_name_to_pod: HashMap<String, Pod>,
-    node_name_to_id: HashMap<String, usize>,
+    server_to_pod: HashMap<String, Pod>,
 }
 
 impl IdInformation {
-    fn new(
-        used_ids: Vec<usize>,
-        node_name_to_pod: HashMap<String, Pod>,
-        node_name_to_id: HashMap<String, usize>,
-    ) -> IdInformation {
+    fn new(used_ids: Vec<usize>, server_to_pod: HashMap<String, Pod>) -> IdInformation {
         IdInformation {
             used_ids,
-            node_name_to_pod,
-            node_name_to_id,
+            server_to_pod,
         }
     }
 }
 
-/// This finds the first missing number in a sorted vector.
-/// Zero is not a valid input in the vector.
+/// This finds the first missing number in the used and quarantined ids.
+/// Zero is not a valid input in the vectors.
 /// If you pass in zero the result will be undefined.
-fn find_first_missing(vec: &[usize]) -> usize {
+fn find_first_missing(used_ids: &[usize], quarantined_ids: &[usize]) -> usize {
+    let mut vec = used_ids
+        .iter()
+        .chain(quarantined_ids)
+        .copied()
+        .collect::<Vec<_>>();
+    vec.sort_unstable();
+    vec.dedup();
+
     let mut added_id = None;
     for (index, id) in vec.iter().enumerate() {
         if index + 1 != *id {
@@ -97,6 +187,26 @@ fn find_first_missing(vec: &[usize]) -> usize {
     added_id.unwrap_or_else(|| vec.len() + 1)
 }
 
+/// Returns the ids that are still quarantined at `now`, ids are quarantined for `period` after
+/// their server was removed.
+fn active_quarantine(
+    quarantined_ids: &[QuarantinedId],
+    now: DateTime<Utc>,
+    period: Duration,
+) -> Vec<QuarantinedId> {
+    quarantined_ids
+        .iter()
+        .filter(|quarantined| {
+            (now - quarantined.removed_at.0)
+                .to_std()
+                .map(|elapsed| elapsed < period)
+                // The server was removed in the future according to our clock
+                .unwrap_or(true)
+        })
+        .cloned()
+        .collect()
+}
+
 impl ZookeeperState {
     async fn set_upgrading_condition(
         &self,
@@ -119,6 +229,120 @@ impl ZookeeperState {
         Ok(resource)
     }
 
+    /// Sets a condition based on the conditions currently known in the status.
+    async fn set_condition(
+        &mut self,
+        condition_type: &str,
+        message: &str,
+        reason: &str,
+        status: ConditionStatus,
+    ) -> OperatorResult<()> {
+        let conditions = self
+            .zk_status
+            .as_ref()
+            .map(|status| status.conditions.clone())
+            .unwrap_or_default();
+
+        self.zk_status = self
+            .context
+            .build_and_set_condition(
+                Some(&conditions),
+                message.to_string(),
+                reason.to_string(),
+                status,
+                condition_type.to_string(),
+            )
+            .await?
+            .status;
+
+        Ok(())
+    }
+
+    /// Sets a condition unless it is set with the same status, reason and message already, so
+    /// the status is not written (which triggers another reconciliation) without a change.
+    async fn ensure_condition(
+        &mut self,
+        condition_type: &str,
+        message: &str,
+        reason: &str,
+        active: bool,
+    ) -> OperatorResult<()> {
+        let (status, status_string) = if active {
+            (ConditionStatus::True, "True")
+        } else {
+            (ConditionStatus::False, "False")
+        };
+        let unchanged = self.zk_status.as_ref().map_or(false, |zk_status| {
+            zk_status.conditions.iter().any(|condition| {
+                condition.type_ == condition_type
+                    && condition.status == status_string
+                    && condition.reason == reason
+                    && condition.message == message
+            })
+        });
+        if unchanged {
+            return Ok(());
+        }
+
+        self.set_condition(condition_type, message, reason, status)
+            .await
+    }
+
+    /// Whether an upgrade is being rolled back, see `rollback_failed_upgrade`.
+    fn is_rollback_running(&self) -> bool {
+        self.zk_status.as_ref().map_or(false, |status| {
+            status.rolled_back_version.is_some()
+                && status.target_version.is_some()
+                && status.current_version == status.target_version
+        })
+    }
+
+    /// Whether the condition is currently true.
+    fn has_condition(&self, condition_type: &str) -> bool {
+        self.zk_status.as_ref().map_or(false, |status| {
+            status
+                .conditions
+                .iter()
+                .any(|condition| condition.type_ == condition_type && condition.status == "True")
+        })
+    }
+
+    /// Records that an upgrade made progress, see `rollback_failed_upgrade`.
+    async fn set_upgrade_progress(&mut self) -> OperatorResult<()> {
+        self.zk_status = self
+            .context
+            .client
+            .merge_patch_status(
+                &self.context.resource,
+                &json!({ "lastUpgradeProgress": Time(Utc::now()) }),
+            )
+            .await?
+            .status;
+
+        Ok(())
+    }
+
+    async fn clear_rolled_back_version(&mut self) -> OperatorResult<()> {
+        if self
+            .zk_status
+            .as_ref()
+            .and_then(|status| status.rolled_back_version.as_ref())
+            .is_some()
+        {
+            self.zk_status = self
+                .context
+                .client
+                .merge_patch_status(
+                    &self.context.resource,
+                    &json!({ "rolledBackVersion": null }),
+                )
+                .await?
+                .status;
+        }
+
+        Ok(())
+    }
+
     async fn set_current_version(
         &self,
         version: Option<&ZookeeperVersion>,
@@ -148,30 +372,385 @@ impl ZookeeperState {
         Ok(resource)
     }
 
-    /// For each existing role (server), return a tuple consisting of eligible nodes for a given
-    /// selector. Required to delete excess pods that do not match any node or selector description.
-    // TODO: move to operator-rs
-    pub fn get_full_pod_node_map(&self) -> Vec<(Vec<Node>, LabelOptionalValueMap)> {
-        let mut eligible_nodes_for_role_and_group = vec![];
-
-        for zookeeper_role in ZookeeperRole::iter() {
-            if let Some(eligible_nodes_for_role) = self.eligible_nodes.get(&zookeeper_role) {
-                for (group_name, eligible_nodes) in eligible_nodes_for_role {
-                    // Create labels to identify eligible nodes
-                    trace!(
-                        "Adding [{}] nodes to eligible node list for role [{}] and group [{}].",
-                        eligible_nodes.len(),
-                        zookeeper_role,
-                        group_name
+    async fn set_rolling_restart(
+        &self,
+        rolling_restart: Option<&RollingRestartStatus>,
+    ) -> OperatorResult<ZookeeperCluster> {
+        let resource = self
+            .context
+            .client
+            .merge_patch_status(
+                &self.context.resource,
+                &json!({ "rollingRestart": rolling_restart }),
+            )
+            .await?;
+
+        Ok(resource)
+    }
+
+    /// The servers recorded in the status, see `assign_servers`.
+    fn servers(&self) -> BTreeMap<String, ServerStatus> {
+        self.zk_status
+            .as_ref()
+            .map(|status| status.servers.clone())
+            .unwrap_or_default()
+    }
+
+    /// The stage of quorum TLS recorded in the status, see `advance_quorum_tls_stage`.
+    fn quorum_tls_stage(&self) -> QuorumTlsStage {
+        self.zk_status
+            .as_ref()
+            .and_then(|status| status.quorum_tls_stage)
+            .unwrap_or_default()
+    }
+
+    /// The stage of quorum authentication recorded in the status, see
+    /// `advance_quorum_sasl_stage`.
+    fn quorum_sasl_stage(&self) -> QuorumSaslStage {
+        self.zk_status
+            .as_ref()
+            .and_then(|status| status.quorum_sasl_stage)
+            .unwrap_or_default()
+    }
+
+    async fn set_quorum_sasl_stage(
+        &self,
+        stage: QuorumSaslStage,
+    ) -> OperatorResult<ZookeeperCluster> {
+        let resource = self
+            .context
+            .client
+            .merge_patch_status(&self.context.resource, &json!({ "quorumSaslStage": stage }))
+            .await?;
+
+        Ok(resource)
+    }
+
+    async fn set_quorum_tls_stage(
+        &self,
+        stage: QuorumTlsStage,
+    ) -> OperatorResult<ZookeeperCluster> {
+        let resource = self
+            .context
+            .client
+            .merge_patch_status(&self.context.resource, &json!({ "quorumTlsStage": stage }))
+            .await?;
+
+        Ok(resource)
+    }
+
+    /// The ids of removed servers recorded in the status, see `assign_servers`.
+    fn quarantined_ids(&self) -> Vec<QuarantinedId> {
+        self.zk_status
+            .as_ref()
+            .map(|status| status.quarantined_ids.clone())
+            .unwrap_or_default()
+    }
+
+    /// Records the servers and the quarantined ids in the status, servers missing in `servers`
+    /// are removed.
+    async fn set_servers(
+        &mut self,
+        servers: &BTreeMap<String, ServerStatus>,
+        quarantined_ids: &[QuarantinedId],
+    ) -> Result<(), Error> {
+        // This is a merge patch, so servers that are gone need to be set to null explicitly
+        let mut patch = serde_json::Map::new();
+        for name in self.servers().keys() {
+            patch.insert(name.clone(), serde_json::Value::Null);
+        }
+        for (name, server) in servers {
+            patch.insert(name.clone(), serde_json::to_value(server)?);
+        }
+
+        self.zk_status = self
+            .context
+            .client
+            .merge_patch_status(
+                &self.context.resource,
+                &json!({ "servers": patch, "quarantinedIds": quarantined_ids }),
+            )
+            .await?
+            .status;
+        Ok(())
+    }
+
+    /// Binds every server requested by the spec to a node and records it in the status.
+    ///
+    /// Servers keep their name and their `myid` for their whole lifetime: When their node is no
+    /// longer eligible they are bound to another node, when their role group is scaled down the
+    /// servers with the highest ordinals are removed. New servers get the lowest free id that is
+    /// not quarantined: The ids of removed servers are only reused after `idQuarantineSeconds`.
+    ///
+    /// Pods created before servers were recorded in the status are adopted with the id and the
+    /// node they run with.
+    async fn assign_servers(&mut self) -> ZookeeperReconcileResult {
+        trace!("Assigning servers from the spec to nodes");
+
+        let mut servers = self.servers();
+        if self.adopt_pods(&mut servers).await? {
+            self.set_servers(&servers, &self.quarantined_ids()).await?;
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        let id_information = self.id_information.as_ref().ok_or_else(|| error::Error::ReconcileError(
+                        "id_information missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
+                    ))?;
+        let mut used_ids = id_information
+            .used_ids
+            .iter()
+            .copied()
+            .chain(servers.values().map(|server| server.id))
+            .collect::<Vec<_>>();
+        used_ids.sort_unstable();
+        used_ids.dedup();
+
+        let now = Utc::now();
+        let mut quarantined_ids = active_quarantine(
+            &self.quarantined_ids(),
+            now,
+            self.zk_spec.id_quarantine_period(),
+        );
+        let quarantined = quarantined_ids
+            .iter()
+            .map(|quarantined| quarantined.id)
+            .collect::<Vec<_>>();
+
+        let mut desired_servers = BTreeMap::new();
+        // Sorted so that new servers always get the same ids
+        for (role_group, selector, peer_type) in self.zk_spec.role_groups() {
+            let node_names = self
+                .eligible_nodes
+                .get(&ZookeeperRole::for_peer_type(peer_type))
+                .and_then(|role_groups| role_groups.get(role_group))
+                .map(|nodes| {
+                    nodes
+                        .iter()
+                        .filter_map(|node| node.metadata.name.clone())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let current_bindings = servers
+                .values()
+                .filter(|server| &server.role_group == role_group)
+                .map(|server| {
+                    (
+                        server.ordinal,
+                        ServerLocation::new(&server.node_name, server.node_slot),
+                    )
+                })
+                .collect();
+
+            // The slots of a node are shared with the other role groups, their servers (already
+            // placed or still bound to a location) must not end up with the same ports
+            let reserved = desired_servers
+                .values()
+                .chain(
+                    servers
+                        .values()
+                        .filter(|server| &server.role_group != role_group),
+                )
+                .map(|server| ServerLocation::new(&server.node_name, server.node_slot))
+                .collect();
+
+            let placement = placement::place_servers(
+                selector.instances,
+                selector.instances_per_node,
+                &node_names,
+                &current_bindings,
+                &reserved,
+            );
+            if !placement.unplaced.is_empty() {
+                warn!(
+                    "Role group [{}] requests [{}] servers but the servers [{:?}] don't fit on the [{}] eligible nodes with [{}] servers per node",
+                    role_group,
+                    selector.instances,
+                    placement.unplaced,
+                    node_names.len(),
+                    selector.instances_per_node
+                );
+            }
+            for ordinal in &placement.removed {
+                info!(
+                    "Removing server [{}] after role group [{}] was scaled down",
+                    ServerStatus::server_name(role_group, *ordinal),
+                    role_group
+                );
+            }
+
+            for (ordinal, location) in placement.bindings {
+                let name = ServerStatus::server_name(role_group, ordinal);
+                let id = match servers.get(&name) {
+                    Some(server) => {
+                        if server.node_name != location.node_name
+                            || server.node_slot != location.node_slot
+                        {
+                            info!(
+                                "Moving server [{}] from slot [{}] of node [{}] to slot [{}] of node [{}]",
+                                name,
+                                server.node_slot,
+                                server.node_name,
+                                location.node_slot,
+                                location.node_name
+                            );
+                        }
+                        server.id
+                    }
+                    None => {
+                        // This changes the topology, all other servers will be reconfigured or
+                        // restarted with the new configuration
+                        let new_id = find_first_missing(&used_ids, &quarantined);
+                        used_ids.push(new_id);
+                        used_ids.sort_unstable();
+                        info!(
+                            "Assigning new id [{}] to server [{}] on node [{}]",
+                            new_id, name, location.node_name
+                        );
+                        new_id
+                    }
+                };
+
+                desired_servers.insert(
+                    name,
+                    ServerStatus {
+                        role_group: role_group.clone(),
+                        ordinal,
+                        id,
+                        node_name: location.node_name,
+                        node_slot: location.node_slot,
+                        peer_type,
+                    },
+                );
+            }
+
+            // Servers that could not be placed stay where they are until there is room again,
+            // unless their location was given to another server. They are removed then, two
+            // servers in the same slot of a node would use the same ports.
+            for ordinal in placement.unplaced {
+                let name = ServerStatus::server_name(role_group, ordinal);
+                if placement.retained.contains_key(&ordinal) {
+                    if let Some(server) = servers.remove(&name) {
+                        desired_servers.insert(name, server);
+                    }
+                } else if let Some(server) = servers.get(&name) {
+                    warn!(
+                        "Removing server [{}] because slot [{}] of node [{}] was given to another server and there is no room for it elsewhere",
+                        name, server.node_slot, server.node_name
                     );
-                    eligible_nodes_for_role_and_group.push((
-                        eligible_nodes.clone(),
-                        get_role_and_group_labels(&zookeeper_role, group_name),
-                    ))
                 }
             }
         }
-        eligible_nodes_for_role_and_group
+
+        for (name, server) in &servers {
+            if self.zk_spec.role_group(&server.role_group).is_none() {
+                info!(
+                    "Removing server [{}] because role group [{}] was removed",
+                    name, server.role_group
+                );
+            }
+        }
+
+        for server in servers.values() {
+            let still_used = desired_servers
+                .values()
+                .any(|desired| desired.id == server.id);
+            if !still_used {
+                debug!("Quarantining id [{}]", server.id);
+                quarantined_ids.push(QuarantinedId {
+                    id: server.id,
+                    removed_at: Time(now),
+                });
+            }
+        }
+
+        if desired_servers != self.servers() || quarantined_ids != self.quarantined_ids() {
+            self.set_servers(&desired_servers, &quarantined_ids).await?;
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Records servers for all pods that don't belong to a server yet and labels the pods
+    /// accordingly. Returns whether any pod was adopted.
+    async fn adopt_pods(
+        &self,
+        servers: &mut BTreeMap<String, ServerStatus>,
+    ) -> Result<bool, Error> {
+        let mut unknown_pods = self
+            .existing_pods
+            .iter()
+            .filter(|pod| pod_server_name(pod).is_none())
+            .filter_map(|pod| {
+                let labels = pod.metadata.labels.as_ref()?;
+                let role_group = labels.get(labels::APP_ROLE_GROUP_LABEL)?;
+                let node_name = pod.spec.as_ref()?.node_name.as_ref()?;
+                let id = labels.get(ID_LABEL)?.parse::<usize>().ok()?;
+                Some((id, role_group, node_name, pod))
+            })
+            .collect::<Vec<_>>();
+        unknown_pods.sort_by_key(|(id, _, _, _)| *id);
+
+        for (id, role_group, node_name, pod) in &unknown_pods {
+            // The pod may run a server that is already recorded, e.g. after its label was removed
+            if let Some(name) = servers
+                .iter()
+                .find(|(_, server)| server.id == *id)
+                .map(|(name, _)| name.clone())
+            {
+                info!(
+                    "Adopting pod [{}] with id [{}] as recorded server [{}]",
+                    Resource::name(*pod),
+                    id,
+                    name
+                );
+                self.label_server_pod(pod, &name).await?;
+                continue;
+            }
+
+            let ordinal = (0..=u16::MAX)
+                .find(|ordinal| {
+                    !servers.contains_key(&ServerStatus::server_name(role_group, *ordinal))
+                })
+                .ok_or_else(|| {
+                    Error::ReconcileError(format!(
+                        "No free ordinal left in role group [{}]",
+                        role_group
+                    ))
+                })?;
+            let name = ServerStatus::server_name(role_group, ordinal);
+            info!(
+                "Adopting pod [{}] with id [{}] as server [{}]",
+                Resource::name(*pod),
+                id,
+                name
+            );
+
+            self.label_server_pod(pod, &name).await?;
+            servers.insert(
+                name,
+                ServerStatus {
+                    role_group: role_group.to_string(),
+                    ordinal,
+                    id: *id,
+                    node_name: node_name.to_string(),
+                    node_slot: pod_node_slot(pod),
+                    // Pods without a recorded server were created before observers existed
+                    peer_type: PeerType::Participant,
+                },
+            );
+        }
+
+        Ok(!unknown_pods.is_empty())
+    }
+
+    async fn label_server_pod(&self, pod: &Pod, server_name: &str) -> OperatorResult<Pod> {
+        self.context
+            .client
+            .merge_patch(
+                pod,
+                json!({ "metadata": { "labels": { SERVER_LABEL: server_name } } }),
+            )
+            .await
     }
 
     /// Required labels for pods. Pods without any of these will deleted and/or replaced.
@@ -189,13 +768,50 @@ impl ZookeeperState {
         );
         mandatory_labels.insert(
             labels::APP_VERSION_LABEL.to_string(),
-            Some(vec![self.context.resource.spec.version.to_string()]),
+            Some(
+                self.running_versions()
+                    .iter()
+                    .map(ZookeeperVersion::to_string)
+                    .collect(),
+            ),
         );
         mandatory_labels.insert(ID_LABEL.to_string(), None);
 
         mandatory_labels
     }
 
+    /// The version new pods are created with: the version we are upgrading to or the current one.
+    fn pod_version(&self) -> ZookeeperVersion {
+        let status = self.zk_status.clone().unwrap_or_default();
+        status
+            .target_version
+            .or(status.current_version)
+            .unwrap_or_else(|| self.zk_spec.version.clone())
+    }
+
+    /// All versions pods may currently run with. During an upgrade this is the current and the
+    /// target version (and the failed version during a rollback), pods with any other version
+    /// are deleted.
+    fn running_versions(&self) -> Vec<ZookeeperVersion> {
+        let status = self.zk_status.clone().unwrap_or_default();
+        let upgrading = status.target_version.is_some();
+        let mut versions: Vec<ZookeeperVersion> = vec![];
+        for version in status
+            .current_version
+            .into_iter()
+            .chain(status.target_version)
+            .chain(status.rolled_back_version.filter(|_| upgrading))
+        {
+            if !versions.contains(&version) {
+                versions.push(version);
+            }
+        }
+        if versions.is_empty() {
+            versions.push(self.zk_spec.version.clone());
+        }
+        versions
+    }
+
     /// Will initialize the status object if it's never been set.
     async fn init_status(&mut self) -> ZookeeperReconcileResult {
         // We'll begin by setting an empty status here because later in this method we might
@@ -210,13 +826,24 @@ impl ZookeeperState {
         }
 
         // This should always return either the existing one or the one we just created above.
-        let status = self.zk_status.take().unwrap_or_default();
+        let status = self.zk_status.clone().unwrap_or_default();
         let spec_version = self.zk_spec.version.clone();
 
         match (&status.current_version, &status.target_version) {
             (None, None) => {
                 // No current_version and no target_version must be initial installation.
                 // We'll set the Upgrading condition and the target_version to the version from spec.
+                if let Err(err) = spec_version.traits() {
+                    warn!("{}: Can't install the requested version", err);
+                    self.set_condition(
+                        UPGRADE_BLOCKED_CONDITION,
+                        &err.to_string(),
+                        "UnsupportedVersion",
+                        ConditionStatus::True,
+                    )
+                    .await?;
+                    return Ok(ReconcileFunctionAction::Done);
+                }
                 info!(
                     "Initial installation, now moving towards version [{}]",
                     self.zk_spec.version
@@ -224,7 +851,7 @@ impl ZookeeperState {
                 self.zk_status = self
                     .set_upgrading_condition(
                         &status.conditions,
-                        &format!("Initial installation to version [{:?}]", spec_version),
+                        &format!("Initial installation to version [{}]", spec_version),
                         "InitialInstallation",
                         ConditionStatus::True,
                     )
@@ -247,7 +874,7 @@ impl ZookeeperState {
                 self.zk_status = self
                     .set_upgrading_condition(
                         &status.conditions,
-                        &format!("Initial installation to version [{:?}]", target_version),
+                        &format!("Initial installation to version [{}]", target_version),
                         "InitialInstallation",
                         ConditionStatus::True,
                     )
@@ -260,30 +887,72 @@ impl ZookeeperState {
                 // We'll check if there is a different version in spec and if it is will
                 // set it in target_version, but only if it's actually a compatible upgrade.
                 if current_version != &spec_version {
-                    if current_version.is_valid_upgrade(&spec_version).unwrap() {
-                        let new_version = spec_version;
+                    let downgrade_policy = self.zk_spec.downgrade_policy.unwrap_or_default();
+                    if status.rolled_back_version.as_ref() == Some(&spec_version) {
                         let message = format!(
-                            "Upgrading from [{:?}] to [{:?}]",
-                            current_version, &new_version
+                            "Upgrade to [{}] failed and was rolled back to [{}], set the version back to the current version to try again",
+                            spec_version, current_version
                         );
-                        info!("{}", message);
-                        self.zk_status = self.set_target_version(Some(&new_version)).await?.status;
-                        self.zk_status = self
-                            .set_upgrading_condition(
-                                &status.conditions,
-                                &message,
-                                "Upgrading",
-                                ConditionStatus::True,
-                            )
-                            .await?
-                            .status;
+                        debug!("{}", message);
+                        self.set_condition(
+                            UPGRADE_BLOCKED_CONDITION,
+                            &message,
+                            "UpgradeRolledBack",
+                            ConditionStatus::True,
+                        )
+                        .await?;
                     } else {
-                        // TODO: This should be caught by an validating admission webhook
-                        warn!("Upgrade from [{}] to [{}] not possible but requested in spec: Ignoring, will continue reconcile as if the invalid version weren't set", current_version, spec_version);
+                        match current_version.check_version_change(&spec_version, downgrade_policy)
+                        {
+                            Ok(()) => {
+                                let new_version = spec_version;
+                                let message = format!(
+                                    "Upgrading from [{}] to [{}]",
+                                    current_version, &new_version
+                                );
+                                info!("{}", message);
+                                self.zk_status =
+                                    self.set_target_version(Some(&new_version)).await?.status;
+                                self.zk_status = self
+                                    .set_upgrading_condition(
+                                        &status.conditions,
+                                        &message,
+                                        "Upgrading",
+                                        ConditionStatus::True,
+                                    )
+                                    .await?
+                                    .status;
+                                self.clear_rolled_back_version().await?;
+                                self.set_upgrade_progress().await?;
+                                self.set_condition(
+                                    UPGRADE_BLOCKED_CONDITION,
+                                    "",
+                                    "",
+                                    ConditionStatus::False,
+                                )
+                                .await?;
+                            }
+                            Err(err) => {
+                                let reason = match &err {
+                                    CrdError::DowngradeNotAllowed { .. } => "DowngradeNotAllowed",
+                                    CrdError::UnsupportedVersion { .. } => "UnsupportedVersion",
+                                    _ => return Err(err.into()),
+                                };
+                                // This is caught by the validating admission webhook if it is deployed
+                                warn!("{}: Ignoring the requested version, will continue reconcile as if it weren't set", err);
+                                self.set_condition(
+                                    UPGRADE_BLOCKED_CONDITION,
+                                    &err.to_string(),
+                                    reason,
+                                    ConditionStatus::True,
+                                )
+                                .await?;
+                            }
+                        }
                     }
                 } else {
                     let message = format!(
-                        "No upgrade required [{:?}] is still the current_version",
+                        "No upgrade required [{}] is still the current_version",
                         current_version
                     );
                     trace!("{}", message);
@@ -296,6 +965,20 @@ impl ZookeeperState {
                         )
                         .await?
                         .status;
+
+                    // Going back to the current version unblocks a version that was rolled back
+                    self.clear_rolled_back_version().await?;
+                    if status.conditions.iter().any(|condition| {
+                        condition.type_ == UPGRADE_BLOCKED_CONDITION && condition.status == "True"
+                    }) {
+                        self.set_condition(
+                            UPGRADE_BLOCKED_CONDITION,
+                            "",
+                            "",
+                            ConditionStatus::False,
+                        )
+                        .await?;
+                    }
                 }
             }
             (Some(current_version), Some(target_version)) => {
@@ -310,7 +993,7 @@ impl ZookeeperState {
                     info!("A new target version was requested while we still upgrade from [{}] to [{}], finishing running upgrade first", current_version, target_version)
                 }
                 let message = format!(
-                    "Upgrading from [{:?}] to [{:?}]",
+                    "Upgrading from [{}] to [{}]",
                     current_version, target_version
                 );
 
@@ -347,17 +1030,14 @@ impl ZookeeperState {
         // We never want to use those as long as there's a chance that some process might be actively
         // using it.
         // There can be a maximum of 255 (I believe) ids.
-        let mut used_ids = Vec::with_capacity(self.existing_pods.len());
-        let mut node_name_to_pod = HashMap::new(); // This is going to own the pods
-        let mut node_name_to_id = HashMap::new();
+        let mut pod_ids = Vec::with_capacity(self.existing_pods.len());
 
         // Iterate over all existing pods and read the label which contains the `myid`
         for pod in &self.existing_pods {
             if let (
                 Some(labels),
                 Some(PodSpec {
-                    node_name: Some(node_name),
-                    ..
+                    node_name: Some(_), ..
                 }),
             ) = (&pod.metadata.labels, &pod.spec)
             {
@@ -379,22 +1059,18 @@ impl ZookeeperState {
                             }
                         };
 
-                        // Check if we have seen the same id before
-                        // This should never happen and would currently require manual cleanup
-                        if used_ids.contains(&id) {
-                            // TODO: Update status
-                            error!(
-                                "Found a duplicate `myid` [{}] in Pod [{}], we can't recover \
-                                 from this error and you need to clean up manually",
-                                id,
-                                Resource::name(pod)
-                            );
-                            return Err(Error::ReconcileError("Found duplicate id".to_string()));
-                        }
-
-                        used_ids.push(id);
-                        node_name_to_id.insert(node_name.clone(), id);
-                        node_name_to_pod.insert(node_name.clone(), pod.clone());
+                        pod_ids.push(PodId {
+                            pod_name: Resource::name(pod),
+                            server_name: pod_server_name(pod).cloned(),
+                            id,
+                            keep: pod
+                                .metadata
+                                .annotations
+                                .as_ref()
+                                .and_then(|annotations| annotations.get(KEEP_ID_ANNOTATION))
+                                .map(|keep| keep == "true")
+                                .unwrap_or_default(),
+                        });
                     }
                 };
             } else {
@@ -405,207 +1081,1477 @@ impl ZookeeperState {
             }
         }
 
+        // The ids in the status are authoritative, pods disagreeing with them are replaced
+        let conflicts = ids::find_id_conflicts(&pod_ids, &self.servers());
+        if !conflicts.unresolved.is_empty() {
+            let message = format!(
+                "{}, annotate the pod that should keep its id with [{}=true] to delete the others",
+                conflicts.describe_unresolved(),
+                KEEP_ID_ANNOTATION
+            );
+            error!("ZookeeperCluster {}: {}", self.context.log_name(), message);
+            self.set_condition(
+                ID_CONFLICT_CONDITION,
+                &message,
+                "DuplicateId",
+                ConditionStatus::True,
+            )
+            .await?;
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+        if !conflicts.stale_pods.is_empty() {
+            for pod in &self.existing_pods {
+                let pod_name = Resource::name(pod);
+                if conflicts.stale_pods.contains(&pod_name) {
+                    warn!(
+                        "Deleting pod [{}] because another pod or the status claims its id",
+                        pod_name
+                    );
+                    self.context.client.delete(pod).await?;
+                }
+            }
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+        if self.has_condition(ID_CONFLICT_CONDITION) {
+            self.set_condition(
+                ID_CONFLICT_CONDITION,
+                "All pods use distinct ids",
+                "Resolved",
+                ConditionStatus::False,
+            )
+            .await?;
+        }
+
+        let mut used_ids = pod_ids.iter().map(|pod| pod.id).collect::<Vec<_>>();
+        used_ids.sort_unstable();
+        let server_to_pod = self
+            .existing_pods
+            .iter()
+            .filter_map(|pod| Some((pod_server_name(pod)?.clone(), pod.clone())))
+            .collect();
+
         debug!(
             "ZookeeperCluster {}: Found these myids in use [{:?}]",
             self.context.log_name(),
             used_ids
         );
 
-        let id_information = IdInformation::new(used_ids, node_name_to_pod, node_name_to_id);
+        let id_information = IdInformation::new(used_ids, server_to_pod);
         self.id_information = Some(id_information);
 
         Ok(ReconcileFunctionAction::Continue)
     }
 
-    /// This function looks at all the requested servers from the spec and assigns ids to those
-    /// that don't have one yet.
-    /// We do this here - and not later - because we need the id mapping information for the
-    /// ConfigMap generation later.
-    /// NOTE: This method will _not_ work if multiple servers should run on a single node
-    async fn assign_ids(&mut self) -> ZookeeperReconcileResult {
-        trace!("Assigning ids to new servers from the spec",);
+    /// Creates the pod of the server with the lowest id that does not have one yet.
+    /// Only one pod is created per reconcile run, the next one is created once it is running.
+    pub async fn create_missing_pods(&mut self) -> ZookeeperReconcileResult {
+        trace!("Starting `create_missing_pods`");
 
-        let id_information = self.id_information.as_mut().ok_or_else(|| error::Error::ReconcileError(
+        let id_information = self.id_information.as_ref().ok_or_else(|| error::Error::ReconcileError(
                         "id_information missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
                     ))?;
 
-        id_information.used_ids.sort_unstable();
+        let servers = self.servers();
+        let (server_name, server) = match servers
+            .iter()
+            .filter(|(name, _)| !id_information.server_to_pod.contains_key(*name))
+            .min_by_key(|(_, server)| server.id)
+        {
+            Some(missing_server) => missing_server,
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let zookeeper_role = ZookeeperRole::for_peer_type(server.peer_type);
+        info!(
+            "Pod for server [{}] with id [{}] in slot [{}] of node [{}] missing, creating now...",
+            server_name, server.id, server.node_slot, server.node_name
+        );
 
-        for role in ZookeeperRole::iter() {
-            if let Some(eligible_nodes_for_role) = self.eligible_nodes.get(&role) {
-                for eligible_nodes in eligible_nodes_for_role.values() {
-                    for node in eligible_nodes {
-                        let node_name = match &node.metadata.name {
-                            Some(name) => name,
-                            None => continue,
-                        };
+        let pod_name = build_pod_name(
+            &self.context.name(),
+            &server.role_group,
+            &zookeeper_role,
+            server.ordinal,
+        );
 
-                        match id_information.node_name_to_pod.get(node_name) {
-                            None => {
-                                // TODO: Need to check whether the topology has changed. If it has we need to restart all servers depending on the ZK version
-                                let new_id = find_first_missing(&id_information.used_ids);
+        let mut pod_labels = build_pod_labels(
+            &zookeeper_role.to_string(),
+            &server.role_group,
+            &self.context.name(),
+            &self.pod_version().to_string(),
+            &server.id.to_string(),
+        );
+        pod_labels.insert(NODE_SLOT_LABEL.to_string(), server.node_slot.to_string());
+        pod_labels.insert(SERVER_LABEL.to_string(), server_name.clone());
 
-                                id_information.used_ids.push(new_id);
-                                id_information.used_ids.sort_unstable();
-                                id_information
-                                    .node_name_to_id
-                                    .insert(node_name.clone(), new_id);
+        // The config map needs to be up to date before the pod starts
+        self.create_config_maps(&pod_name, &server.role_group, server.node_slot)
+            .await?;
+        self.create_jaas_secret(&pod_name, server_name).await?;
+        self.create_pod(server_name, server, &pod_name, pod_labels)
+            .await?;
 
-                                info!(
-                                    "Assigning new id [{}] to server/node [{}]",
-                                    new_id, node_name
-                                )
-                            }
-                            Some(_) => {
-                                trace!(
-                                    "Pod for node [{}] already exists and is assigned id [{:?}]",
-                                    node_name,
-                                    id_information.node_name_to_id.get(node_name)
-                                );
-                            }
-                        }
-                    }
-                }
-            }
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
+
+    /// Replaces the pods that still run an old version with pods running the target version.
+    ///
+    /// This happens one server at a time: followers first and the leader last, so the ensemble
+    /// only needs to elect a new leader once. The next server is only replaced when every server
+    /// is serving requests again.
+    async fn upgrade_pods(&mut self) -> ZookeeperReconcileResult {
+        let target_version = match self
+            .zk_status
+            .as_ref()
+            .and_then(|status| status.target_version.as_ref())
+        {
+            Some(target_version) => target_version.to_string(),
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let outdated_pods = self
+            .existing_pods
+            .iter()
+            .filter(|pod| pod_version_label(pod) != Some(&target_version))
+            .collect::<Vec<_>>();
+        if outdated_pods.is_empty() {
+            return Ok(ReconcileFunctionAction::Continue);
         }
 
-        Ok(ReconcileFunctionAction::Continue)
-    }
+        let server_modes = self.server_modes().await;
+        if !is_quorum_healthy(&server_modes) {
+            info!(
+                "Waiting for all servers to serve requests before upgrading the next one: [{:?}]",
+                server_modes
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
 
-    pub async fn create_missing_pods(&mut self) -> ZookeeperReconcileResult {
-        trace!("Starting `create_missing_pods`");
+        let pod = outdated_pods
+            .into_iter()
+            .min_by_key(|pod| {
+                let pod_name = Resource::name(*pod);
+                let is_leader = server_modes
+                    .get(&pod_name)
+                    .and_then(Option::as_ref)
+                    .map(ServerMode::is_leader)
+                    .unwrap_or(false);
+                (is_leader, pod_name)
+            })
+            .expect("outdated_pods can not be empty at this point");
+
+        info!(
+            "Upgrading pod [{}] from version [{}] to [{}]",
+            Resource::name(pod),
+            pod_version_label(pod)
+                .map(String::as_str)
+                .unwrap_or("<none>"),
+            target_version
+        );
+        // The pod will be recreated with the target version by `create_missing_pods`
+        self.context.client.delete(pod).await?;
+        self.set_upgrade_progress().await?;
 
-        let id_information = self.id_information.as_mut().ok_or_else(|| error::Error::ReconcileError(
-                        "id_information missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
-                    ))?;
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
 
-        // The iteration happens in two stages here, to accommodate the way our operators think
-        // about nodes and roles.
-        // The hierarchy is:
-        // - Roles (for example Datanode, Namenode, Server)
-        //   - Role groups for this role (user defined)
-        for zookeeper_role in ZookeeperRole::iter() {
-            if let Some(nodes_for_role) = self.eligible_nodes.get(&zookeeper_role) {
-                for (role_group, nodes) in nodes_for_role {
-                    debug!(
-                        "Identify missing pods for [{}] role and group [{}]",
-                        zookeeper_role, role_group
-                    );
-                    trace!(
-                        "candidate_nodes[{}]: [{:?}]",
-                        nodes.len(),
-                        nodes
-                            .iter()
-                            .map(|node| node.metadata.name.as_ref().unwrap())
-                            .collect::<Vec<_>>()
-                    );
-                    trace!(
-                        "existing_pods[{}]: [{:?}]",
-                        &self.existing_pods.len(),
-                        &self
-                            .existing_pods
-                            .iter()
-                            .map(|pod| pod.metadata.name.as_ref().unwrap())
-                            .collect::<Vec<_>>()
-                    );
-                    trace!(
-                        "labels: [{:?}]",
-                        get_role_and_group_labels(&zookeeper_role, role_group)
-                    );
-                    let nodes_that_need_pods = k8s_utils::find_nodes_that_need_pods(
-                        nodes,
-                        &self.existing_pods,
-                        &get_role_and_group_labels(&zookeeper_role, role_group),
+    /// Rolls an upgrade back to `currentVersion` when no server could be replaced for
+    /// [`UPGRADE_STEP_TIMEOUT`], e.g. because a server running the new version doesn't become
+    /// ready or doesn't rejoin the quorum.
+    ///
+    /// This runs before waiting for all pods to be ready, because pods of the failed version that
+    /// never become ready would block the rollback otherwise. Those are deleted right away, all
+    /// other pods are rolled back by `upgrade_pods` like in any other upgrade.
+    async fn rollback_failed_upgrade(&mut self) -> ZookeeperReconcileResult {
+        let status = self.zk_status.clone().unwrap_or_default();
+        let (current_version, target_version) =
+            match (&status.current_version, &status.target_version) {
+                (Some(current_version), Some(target_version)) => (current_version, target_version),
+                // Nothing to roll back to during the initial installation
+                _ => return Ok(ReconcileFunctionAction::Continue),
+            };
+
+        if current_version == target_version {
+            // A rollback is running already
+            let failed_version = match &status.rolled_back_version {
+                Some(failed_version) => failed_version.to_string(),
+                None => return Ok(ReconcileFunctionAction::Continue),
+            };
+
+            let mut deleted_pods = false;
+            for pod in &self.existing_pods {
+                if pod_version_label(pod) == Some(&failed_version) && !is_pod_ready(pod) {
+                    info!(
+                        "Deleting pod [{}] running version [{}] which is not ready to roll back to [{}]",
+                        Resource::name(pod),
+                        failed_version,
+                        current_version
                     );
+                    self.context.client.delete(pod).await?;
+                    deleted_pods = true;
+                }
+            }
+            if deleted_pods {
+                return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+            }
+            return Ok(ReconcileFunctionAction::Continue);
+        }
 
-                    for node in nodes_that_need_pods {
-                        let node_name = if let Some(node_name) = &node.metadata.name {
-                            node_name
-                        } else {
-                            warn!("No name found in metadata, this should not happen! Skipping node: [{:?}]", node);
-                            continue;
-                        };
-                        debug!(
-                            "Creating pod on node [{}] for [{}] role and group [{}]",
-                            node.metadata
-                                .name
-                                .as_deref()
-                                .unwrap_or("<no node name found>"),
-                            zookeeper_role,
-                            role_group
-                        );
+        let stuck = match &status.last_upgrade_progress {
+            Some(last_progress) => (Utc::now() - last_progress.0)
+                .to_std()
+                .map(|elapsed| elapsed > UPGRADE_STEP_TIMEOUT)
+                .unwrap_or(false),
+            None => {
+                // The upgrade was started without recording its progress, start counting now
+                self.set_upgrade_progress().await?;
+                false
+            }
+        };
+        if !stuck {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
 
-                        if id_information.node_name_to_pod.get(node_name).is_none() {
-                            info!("Pod for server [{}] missing, creating now...", node_name);
-
-                            let id = *id_information
-                                .node_name_to_id
-                                .get(node_name)
-                                .ok_or_else(|| Error::ReconcileError(format!("We didn't find a `myid` for [{}] but it should have been assigned, this is a bug, please report it", node_name)))?;
-
-                            let pod_name = format!(
-                                "{}-{}-{}-{}-{}",
-                                APP_NAME,
-                                self.context.name(),
-                                role_group,
-                                zookeeper_role,
-                                node_name
-                            )
-                            .to_lowercase();
-
-                            let pod_labels = build_pod_labels(
-                                &zookeeper_role.to_string(),
-                                role_group,
-                                &self.context.name(),
-                                &self.context.resource.spec.version.to_string(),
-                                &id.to_string(),
-                            );
+        let message = format!(
+            "Upgrade from [{}] to [{}] made no progress for [{}] seconds, rolling back to [{}]",
+            current_version,
+            target_version,
+            UPGRADE_STEP_TIMEOUT.as_secs(),
+            current_version
+        );
+        warn!("{}", message);
+        self.zk_status = self
+            .context
+            .client
+            .merge_patch_status(
+                &self.context.resource,
+                &json!({
+                    "targetVersion": current_version,
+                    "rolledBackVersion": target_version,
+                    "lastUpgradeProgress": Time(Utc::now()),
+                }),
+            )
+            .await?
+            .status;
+        self.set_condition(
+            DEGRADED_CONDITION,
+            &message,
+            "UpgradeRolledBack",
+            ConditionStatus::True,
+        )
+        .await?;
+
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
+
+    /// Finishes an upgrade (or the initial installation) once all pods run the target version and
+    /// the quorum is healthy by moving `targetVersion` to `currentVersion`.
+    async fn finish_upgrade(&mut self) -> ZookeeperReconcileResult {
+        let status = self.zk_status.clone().ok_or_else(|| error::Error::ReconcileError(
+            "`zk_status missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
+        ))?;
+
+        let target_version = match &status.target_version {
+            Some(target_version) => target_version,
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let target_version_label = target_version.to_string();
+        if self
+            .existing_pods
+            .iter()
+            .any(|pod| pod_version_label(pod) != Some(&target_version_label))
+        {
+            debug!(
+                "Not all pods run version [{}] yet, upgrade is not finished",
+                target_version
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        let server_modes = self.server_modes().await;
+        if !is_quorum_healthy(&server_modes) {
+            info!(
+                "All pods run version [{}] but the quorum is not healthy yet: [{:?}]",
+                target_version, server_modes
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        info!("All servers are running version [{}]", target_version);
+        if status.current_version.as_ref() == Some(target_version) {
+            if let Some(failed_version) = &status.rolled_back_version {
+                self.set_condition(
+                    DEGRADED_CONDITION,
+                    &format!(
+                        "Upgrade to [{}] was rolled back to [{}]",
+                        failed_version, target_version
+                    ),
+                    "RollbackFinished",
+                    ConditionStatus::False,
+                )
+                .await?;
+            }
+        }
+        self.zk_status = self.set_target_version(None).await?.status;
+        self.zk_status = self.set_current_version(Some(target_version)).await?.status;
+        let conditions = self.zk_status.clone().unwrap_or_default().conditions;
+        self.zk_status = self
+            .set_upgrading_condition(
+                &conditions,
+                &format!(
+                    "No upgrade required [{}] is still the current_version",
+                    target_version
+                ),
+                "",
+                ConditionStatus::False,
+            )
+            .await?
+            .status;
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Asks every server for its statistics and records them in `status.health` together with
+    /// the `Available` (there is a quorum) and `Degraded` (not all servers serve requests)
+    /// conditions.
+    ///
+    /// Every change of the status triggers another reconciliation, so the statistics (which
+    /// change all the time) are only written when a server changed its mode or the last
+    /// observation is older than [`HEALTH_CHECK_INTERVAL`].
+    async fn check_health(&mut self) -> ZookeeperReconcileResult {
+        let servers = self.servers();
+        if servers.is_empty() {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let pod_stats = self.pod_stats().await;
+        let stats = servers
+            .keys()
+            .map(|server_name| {
+                let server_stats = pod_stats
+                    .iter()
+                    .find(|(pod, _)| pod_server_name(pod) == Some(server_name))
+                    .map(|(_, server_stats)| server_stats.clone())
+                    .unwrap_or_default();
+                (server_name.clone(), server_stats)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        // Observers don't vote, they don't count for the quorum
+        let quorum = has_quorum(
+            &stats
+                .iter()
+                .filter(|(server_name, _)| {
+                    servers
+                        .get(*server_name)
+                        .map_or(false, |server| server.peer_type == PeerType::Participant)
+                })
+                .map(|(server_name, server_stats)| (server_name.clone(), server_stats.clone()))
+                .collect(),
+        );
+        let health = stats
+            .iter()
+            .map(|(server_name, server_stats)| (server_name.clone(), server_health(server_stats)))
+            .collect::<BTreeMap<_, _>>();
+
+        let previous = self
+            .zk_status
+            .as_ref()
+            .and_then(|status| status.health.clone());
+        let outdated = match &previous {
+            Some(previous) => {
+                previous.quorum != quorum
+                    || !previous
+                        .servers
+                        .iter()
+                        .map(|(name, server)| (name, &server.mode))
+                        .eq(health.iter().map(|(name, server)| (name, &server.mode)))
+                    || (Utc::now() - previous.observed_at.0)
+                        .to_std()
+                        .map(|elapsed| elapsed >= HEALTH_CHECK_INTERVAL)
+                        .unwrap_or(true)
+            }
+            None => true,
+        };
+        if outdated {
+            // This is a merge patch, so servers that are gone need to be set to null explicitly
+            let mut servers_patch = serde_json::Map::new();
+            for name in previous.iter().flat_map(|previous| previous.servers.keys()) {
+                servers_patch.insert(name.clone(), serde_json::Value::Null);
+            }
+            for (name, server) in &health {
+                servers_patch.insert(name.clone(), serde_json::to_value(server)?);
+            }
+
+            self.zk_status = self
+                .context
+                .client
+                .merge_patch_status(
+                    &self.context.resource,
+                    &json!({
+                        "health": {
+                            "quorum": quorum,
+                            "observedAt": Time(Utc::now()),
+                            "servers": servers_patch,
+                        }
+                    }),
+                )
+                .await?
+                .status;
+        }
+
+        if quorum {
+            self.ensure_condition(
+                AVAILABLE_CONDITION,
+                "A majority of the servers serves requests",
+                "QuorumEstablished",
+                true,
+            )
+            .await?;
+        } else {
+            self.ensure_condition(
+                AVAILABLE_CONDITION,
+                "There is no leader or less than a majority of the servers serves requests",
+                "NoQuorum",
+                false,
+            )
+            .await?;
+        }
+
+        // A rollback marks the cluster as degraded until it is finished
+        if !self.is_rollback_running() {
+            let not_serving = health
+                .iter()
+                .filter(|(_, server)| server.mode.is_none())
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            if not_serving.is_empty() {
+                self.ensure_condition(
+                    DEGRADED_CONDITION,
+                    "All servers serve requests",
+                    "AllServersServing",
+                    false,
+                )
+                .await?;
+            } else {
+                self.ensure_condition(
+                    DEGRADED_CONDITION,
+                    &format!("Servers {:?} don't serve requests", not_serving),
+                    "ServersNotServing",
+                    true,
+                )
+                .await?;
+            }
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
 
-                            self.create_pod(&node_name, &pod_name, pod_labels).await?;
-                            self.create_config_maps(&pod_name, id).await?;
+    /// Reconciles the cluster again after [`HEALTH_CHECK_INTERVAL`], so `status.health` stays up
+    /// to date when nothing else changes.
+    async fn schedule_health_check(&self) -> ZookeeperReconcileResult {
+        Ok(ReconcileFunctionAction::Requeue(HEALTH_CHECK_INTERVAL))
+    }
+
+    /// Asks every server for its mode, servers that can't be reached or are not serving
+    /// requests are `None`.
+    async fn server_modes(&self) -> BTreeMap<String, Option<ServerMode>> {
+        self.pod_stats()
+            .await
+            .into_iter()
+            .map(|(pod, server_stats)| (Resource::name(pod), server_stats.mode))
+            .collect()
+    }
 
-                            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
-                        } else {
+    /// Asks the server of every existing pod for its statistics, servers that can't be reached
+    /// get empty statistics (so their `mode` is `None`).
+    ///
+    /// The servers are asked concurrently, so unreachable servers don't hold up the
+    /// reconciliation for longer than a single [`SERVER_STATS_TIMEOUT`].
+    async fn pod_stats(&self) -> Vec<(&Pod, ServerStats)> {
+        futures::future::join_all(self.existing_pods.iter().map(|pod| async move {
+            let server_stats = match self.client_address(pod) {
+                Some(address) => {
+                    match four_letter_words::server_stats(&address, SERVER_STATS_TIMEOUT).await {
+                        Ok(server_stats) => server_stats,
+                        Err(err) => {
                             debug!(
-                                "Pod for server [{}] already created, skipping ...",
-                                node_name
+                                "Could not get the statistics of server [{}] of pod [{}]: {}",
+                                address,
+                                Resource::name(pod),
+                                err
                             );
+                            ServerStats::default()
                         }
                     }
                 }
+                None => ServerStats::default(),
+            };
+            (pod, server_stats)
+        }))
+        .await
+    }
+
+    /// The address the operator connects to for the server running in this pod, the host is the
+    /// DNS name of the server like in the discovery ConfigMap and the certificate.
+    fn client_address(&self, pod: &Pod) -> Option<String> {
+        let role_group = pod
+            .metadata
+            .labels
+            .as_ref()?
+            .get(labels::APP_ROLE_GROUP_LABEL)?;
+        let server_name = pod_server_name(pod)?;
+
+        Some(format!(
+            "{}:{}",
+            server_dns_name(server_name, &self.context.name(), &self.context.namespace()),
+            self.server_config(role_group, pod_node_slot(pod))
+                .client_port()
+        ))
+    }
+
+    /// Compares the configuration every existing pod was started with to the one it should have
+    /// now (e.g. because servers were added or removed) and restarts the first outdated pod.
+    ///
+    /// Only one pod is restarted per reconcile run, the next one will only be restarted once the
+    /// previous one is running and ready again (which is ensured by the earlier reconcile steps),
+    /// so the ensemble never loses more than one server at a time.
+    ///
+    /// Only changes to `zoo.cfg` require a restart. The dynamic configuration file has already
+    /// been applied by `reconfigure_ensemble` at this point, so it is just updated in place.
+    async fn restart_outdated_pods(&mut self) -> ZookeeperReconcileResult {
+        trace!("Checking for pods with outdated configuration");
+
+        let namespace = self.context.namespace();
+        let mut outdated_pods = vec![];
+        for pod in &self.existing_pods {
+            let labels = pod.metadata.labels.clone().unwrap_or_default();
+            let (role_group, id) = match (
+                labels.get(labels::APP_ROLE_GROUP_LABEL),
+                labels.get(ID_LABEL).and_then(|id| id.parse::<usize>().ok()),
+            ) {
+                (Some(role_group), Some(id)) => (role_group.clone(), id),
+                _ => continue,
+            };
+
+            let pod_name = Resource::name(pod);
+            let node_slot = pod_node_slot(pod);
+            let desired_config = self.build_config_files(&role_group, node_slot)?;
+            let cm_name = format!("{}-config", pod_name);
+            let current_config = match self
+                .context
+                .client
+                .get::<ConfigMap>(&cm_name, Some(namespace.as_str()))
+                .await
+            {
+                Ok(cm) => cm.data,
+                Err(err) => {
+                    warn!(
+                        "Could not read ConfigMap [{}] of pod [{}], treating its configuration as outdated: {}",
+                        cm_name, pod_name, err
+                    );
+                    None
+                }
+            };
+
+            if current_config.as_ref().and_then(|data| data.get(ZOO_CFG))
+                != desired_config.get(ZOO_CFG)
+            {
+                debug!("Pod [{}] runs with an outdated configuration", pod_name);
+                outdated_pods.push((id, pod, role_group));
+            } else if self.has_outdated_certificate(pod) {
+                debug!("Pod [{}] runs with a replaced certificate", pod_name);
+                outdated_pods.push((id, pod, role_group));
+            } else if self.has_outdated_jaas_config(pod).await {
+                debug!(
+                    "Pod [{}] runs with an outdated JAAS configuration",
+                    pod_name
+                );
+                outdated_pods.push((id, pod, role_group));
+            } else if current_config.as_ref() != Some(&desired_config) {
+                debug!(
+                    "Updating the dynamic configuration of pod [{}] without a restart",
+                    pod_name
+                );
+                self.create_config_maps(&pod_name, &role_group, node_slot)
+                    .await?;
             }
         }
 
-        let status = self.zk_status.clone().ok_or_else(|| error::Error::ReconcileError(
-            "`zk_status missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
+        let status = self.zk_status.clone().unwrap_or_default();
+
+        if outdated_pods.is_empty() {
+            if let Some(rolling_restart) = &status.rolling_restart {
+                info!(
+                    "Rolling restart finished, [{}] was the last restarted pod",
+                    rolling_restart.current_pod
+                );
+                self.zk_status = self.set_rolling_restart(None).await?.status;
+            }
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        // Restart in order of the ids to make this predictable
+        outdated_pods.sort_by_key(|(id, _, _)| *id);
+        let (_, pod, role_group) = outdated_pods.remove(0);
+        let pod_name = Resource::name(pod);
+        let rolling_restart = RollingRestartStatus {
+            current_pod: pod_name.clone(),
+            pending_pods: outdated_pods
+                .iter()
+                .map(|(_, pod, _)| Resource::name(*pod))
+                .collect(),
+        };
+
+        info!(
+            "Restarting pod [{}] to apply its new configuration, [{}] more pods pending",
+            pod_name,
+            rolling_restart.pending_pods.len()
+        );
+        self.create_config_maps(&pod_name, &role_group, pod_node_slot(pod))
+            .await?;
+        if let Some(server_name) = pod_server_name(pod) {
+            self.create_jaas_secret(&pod_name, server_name).await?;
+        }
+        self.context.client.delete(pod).await?;
+        self.zk_status = self
+            .set_rolling_restart(Some(&rolling_restart))
+            .await?
+            .status;
+
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
+
+    /// Records the stage of quorum TLS before any pod is created with it.
+    ///
+    /// There is nothing to switch while no pod exists or `tls` is not set (then the stores are
+    /// not mounted), so the target stage is used right away. Pods of clusters that don't have a
+    /// stage yet were started before quorum TLS existed, so they use plaintext.
+    async fn init_quorum_tls_stage(&mut self) -> ZookeeperReconcileResult {
+        let recorded_stage = self
+            .zk_status
+            .as_ref()
+            .and_then(|status| status.quorum_tls_stage);
+        let target_stage = self.zk_spec.target_quorum_tls_stage();
+
+        let stage = if self.existing_pods.is_empty() || self.zk_spec.tls.is_none() {
+            target_stage
+        } else {
+            recorded_stage.unwrap_or(QuorumTlsStage::Plaintext)
+        };
+        if recorded_stage != Some(stage) {
+            info!("Quorum TLS is in stage [{:?}]", stage);
+            self.zk_status = self.set_quorum_tls_stage(stage).await?.status;
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Switches quorum connections of a running cluster to (or from) TLS as documented by
+    /// ZooKeeper: Servers first accept TLS next to plaintext (`portUnification`), then connect with
+    /// TLS (`sslQuorum`) and finally stop accepting plaintext.
+    ///
+    /// Every stage needs all servers to run with it, so this moves one stage further only after
+    /// `restart_outdated_pods` found no outdated pod and the quorum is healthy. The next stage is
+    /// then applied by another rolling restart.
+    async fn advance_quorum_tls_stage(&mut self) -> ZookeeperReconcileResult {
+        let stage = self.quorum_tls_stage();
+        let target_stage = self.zk_spec.target_quorum_tls_stage();
+        let next_stage = match stage.next_towards(target_stage) {
+            Some(next_stage) => next_stage,
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let server_modes = self.server_modes().await;
+        if !is_quorum_healthy(&server_modes) {
+            info!(
+                "Quorum TLS is in stage [{:?}] but the quorum is not healthy yet: [{:?}]",
+                stage, server_modes
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        info!(
+            "Moving quorum TLS from stage [{:?}] to [{:?}] on the way to [{:?}]",
+            stage, next_stage, target_stage
+        );
+        self.zk_status = self.set_quorum_tls_stage(next_stage).await?.status;
+
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
+
+    /// Records the stage of quorum authentication before any pod is created with it, like
+    /// `init_quorum_tls_stage`. Without `authentication` the servers have no JAAS file, so there
+    /// is nothing to switch either.
+    async fn init_quorum_sasl_stage(&mut self) -> ZookeeperReconcileResult {
+        let recorded_stage = self
+            .zk_status
+            .as_ref()
+            .and_then(|status| status.quorum_sasl_stage);
+        let target_stage = self.zk_spec.target_quorum_sasl_stage();
+
+        let stage = if self.existing_pods.is_empty() || self.zk_spec.authentication.is_none() {
+            target_stage
+        } else {
+            recorded_stage.unwrap_or(QuorumSaslStage::Disabled)
+        };
+        if recorded_stage != Some(stage) {
+            info!("Quorum authentication is in stage [{:?}]", stage);
+            self.zk_status = self.set_quorum_sasl_stage(stage).await?.status;
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Lets the servers of a running cluster authenticate each other (or stop doing so) as
+    /// documented by ZooKeeper: Servers first authenticate without requiring it
+    /// (`quorum.auth.enableSasl`), then learners require it (`quorum.auth.learnerRequireSasl`) and
+    /// finally the servers they connect to as well (`quorum.auth.serverRequireSasl`).
+    ///
+    /// Like `advance_quorum_tls_stage` this moves one stage further only when all servers run
+    /// with the current one and the quorum is healthy. Quorum TLS is switched first, so only
+    /// one of them changes per rolling restart.
+    async fn advance_quorum_sasl_stage(&mut self) -> ZookeeperReconcileResult {
+        let stage = self.quorum_sasl_stage();
+        let target_stage = self.zk_spec.target_quorum_sasl_stage();
+        let next_stage = match stage.next_towards(target_stage) {
+            Some(next_stage) => next_stage,
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let server_modes = self.server_modes().await;
+        if !is_quorum_healthy(&server_modes) {
+            info!(
+                "Quorum authentication is in stage [{:?}] but the quorum is not healthy yet: [{:?}]",
+                stage, server_modes
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        info!(
+            "Moving quorum authentication from stage [{:?}] to [{:?}] on the way to [{:?}]",
+            stage, next_stage, target_stage
+        );
+        self.zk_status = self.set_quorum_sasl_stage(next_stage).await?.status;
+
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
+
+    /// Issues a certificate for every server if the operator manages the TLS key material (see
+    /// `certificates`).
+    ///
+    /// Certificates are replaced when the names of their server, the CA or the format of the
+    /// stores change and once less than a third of their validity is left. Pods are annotated
+    /// with the serial number of the certificate they were started with, so
+    /// `restart_outdated_pods` restarts the ones whose certificate was replaced. The password
+    /// of the stores is kept, it is passed to running servers in an environment variable.
+    async fn issue_certificates(&mut self) -> ZookeeperReconcileResult {
+        let tls = match &self.zk_spec.tls {
+            Some(tls) if tls.is_managed() => tls.clone(),
+            _ => return Ok(ReconcileFunctionAction::Continue),
+        };
+        let ca = match self.certificate_authority(&tls).await? {
+            Some(ca) => ca,
+            None => return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10))),
+        };
+
+        let cluster_name = self.context.name();
+        let namespace = self.context.namespace();
+        let now = Utc::now();
+        for (server_name, server) in self.servers() {
+            let pod_name = build_pod_name(
+                &cluster_name,
+                &server.role_group,
+                &ZookeeperRole::for_peer_type(server.peer_type),
+                server.ordinal,
+            );
+            let secret_name = server_tls_secret_name(&pod_name);
+            let dns_names =
+                server_certificate_dns_names(&server_name, &server, &cluster_name, &namespace);
+            let fingerprint = certificates::request_fingerprint(&dns_names, &ca, tls.format());
+
+            let existing_secret = match self
+                .context
+                .client
+                .get::<Secret>(&secret_name, Some(namespace.as_str()))
+                .await
+            {
+                Ok(secret) => Some(secret),
+                Err(stackable_operator::error::Error::KubeError {
+                    source: kube::Error::Api(response),
+                }) if response.code == 404 => None,
+                Err(err) => return Err(err.into()),
+            };
+            let annotations = existing_secret
+                .as_ref()
+                .and_then(|secret| secret.metadata.annotations.clone())
+                .unwrap_or_default();
+            if let Some(serial) = certificates::usable_certificate_serial(
+                &annotations,
+                &fingerprint,
+                now,
+                tls.certificate_validity(),
+            ) {
+                self.issued_certificates.insert(pod_name, serial);
+                continue;
+            }
+
+            let password = existing_secret
+                .and_then(|secret| secret.data)
+                .and_then(|mut data| data.remove(config::TLS_PASSWORD_KEY))
+                .and_then(|password| String::from_utf8(password.0).ok())
+                .unwrap_or_else(reconfig::generate_password);
+
+            info!(
+                "Issuing a certificate for server [{}] valid for [{}]",
+                server_name,
+                dns_names.join(", ")
+            );
+            let certificate = ca.issue(&dns_names, now, tls.certificate_validity())?;
+            let mut metadata =
+                metadata::build_metadata(secret_name, None, &self.context.resource, true)?;
+            metadata.annotations = Some(certificate.annotations(&fingerprint));
+            let secret = Secret {
+                metadata,
+                data: Some(certificates::build_server_tls_data(
+                    &ca,
+                    &certificate,
+                    tls.format(),
+                    &password,
+                    now,
+                )?),
+                ..Secret::default()
+            };
+            self.context.client.apply_patch(&secret, &secret).await?;
+            self.issued_certificates
+                .insert(pod_name, certificate.serial.to_string());
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Reads the CA signing the certificates of the servers, the Secret of the default CA is
+    /// created if it doesn't exist yet. `None` if a referenced Secret doesn't exist (yet).
+    async fn certificate_authority(
+        &self,
+        tls: &TlsConfig,
+    ) -> Result<Option<CertificateAuthority>, Error> {
+        let cluster_name = self.context.name();
+        let secret_name = tls.ca_secret_name(&cluster_name);
+        let namespace = self.context.namespace();
+
+        let data: BTreeMap<String, String> = match self
+            .context
+            .client
+            .get::<Secret>(&secret_name, Some(namespace.as_str()))
+            .await
+        {
+            Ok(secret) => secret
+                .data
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, String::from_utf8(value.0).ok()?)))
+                .collect(),
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+            }) if response.code == 404 && tls.ca_secret_name.is_none() => {
+                info!(
+                    "Creating Secret [{}] with the CA of the cluster",
+                    secret_name
+                );
+                let data = CertificateAuthority::generate(&cluster_name, Utc::now())?;
+                let secret = Secret {
+                    metadata: metadata::build_metadata(
+                        secret_name.clone(),
+                        None,
+                        &self.context.resource,
+                        true,
+                    )?,
+                    string_data: Some(data.clone()),
+                    ..Secret::default()
+                };
+                self.context.client.create(&secret).await?;
+                data
+            }
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+            }) if response.code == 404 => {
+                warn!(
+                    "The Secret [{}] with the CA does not exist, will check again",
+                    secret_name
+                );
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let pem = |key: &str| {
+            data.get(key).ok_or_else(|| {
+                Error::ReconcileError(format!(
+                    "Secret [{}] does not contain a valid [{}]",
+                    secret_name, key
+                ))
+            })
+        };
+        CertificateAuthority::from_pem(
+            pem(certificates::CA_CERTIFICATE_KEY)?,
+            pem(certificates::CA_PRIVATE_KEY_KEY)?,
+        )
+        .map(Some)
+    }
+
+    /// Whether the certificate the pod was started with was replaced by `issue_certificates`.
+    fn has_outdated_certificate(&self, pod: &Pod) -> bool {
+        match self.issued_certificates.get(&Resource::name(pod)) {
+            Some(serial) => {
+                pod.metadata
+                    .annotations
+                    .as_ref()
+                    .and_then(|annotations| annotations.get(certificates::SERIAL_ANNOTATION))
+                    != Some(serial)
+            }
+            None => false,
+        }
+    }
+
+    /// Reads the DIGEST-MD5 users of `authentication` from their Secret, the JAAS configuration of
+    /// the servers is rendered from them.
+    async fn read_digest_users(&mut self) -> ZookeeperReconcileResult {
+        let secret_name = match self
+            .zk_spec
+            .authentication
+            .as_ref()
+            .and_then(|authentication| authentication.digest.as_ref())
+        {
+            Some(digest) => digest.secret_name.clone(),
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let namespace = self.context.namespace();
+        let data = match self
+            .context
+            .client
+            .get::<Secret>(&secret_name, Some(namespace.as_str()))
+            .await
+        {
+            Ok(secret) => secret.data.unwrap_or_default(),
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+            }) if response.code == 404 => {
+                warn!(
+                    "The Secret [{}] with the DIGEST-MD5 users does not exist, will check again",
+                    secret_name
+                );
+                return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut users = BTreeMap::new();
+        for (name, password) in data {
+            if !jaas::is_valid_user_name(&name) {
+                return Err(Error::ReconcileError(format!(
+                    "Secret [{}] contains the invalid user name [{}], only letters, digits, '_', '-' and '$' are allowed",
+                    secret_name, name
+                )));
+            }
+            let password = String::from_utf8(password.0).map_err(|_| {
+                Error::ReconcileError(format!(
+                    "Secret [{}] contains a password for user [{}] which is not valid UTF-8",
+                    secret_name, name
+                ))
+            })?;
+            users.insert(name, password);
+        }
+        self.digest_users = users;
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Reads the credentials the servers authenticate each other with from the Secret of
+    /// `authentication.quorumSecretName`, they are part of the JAAS configuration.
+    async fn read_quorum_credentials(&mut self) -> ZookeeperReconcileResult {
+        let secret_name = match self
+            .zk_spec
+            .authentication
+            .as_ref()
+            .and_then(|authentication| authentication.quorum_secret_name.clone())
+        {
+            Some(secret_name) => secret_name,
+            None => return Ok(ReconcileFunctionAction::Continue),
+        };
+
+        let namespace = self.context.namespace();
+        let mut data = match self
+            .context
+            .client
+            .get::<Secret>(&secret_name, Some(namespace.as_str()))
+            .await
+        {
+            Ok(secret) => secret.data.unwrap_or_default(),
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+            }) if response.code == 404 => {
+                warn!(
+                    "The Secret [{}] with the quorum credentials does not exist, will check again",
+                    secret_name
+                );
+                return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut value = |key: &str| {
+            data.remove(key)
+                .and_then(|value| String::from_utf8(value.0).ok())
+                .ok_or_else(|| {
+                    Error::ReconcileError(format!(
+                        "Secret [{}] does not contain a valid [{}]",
+                        secret_name, key
+                    ))
+                })
+        };
+        let credentials = jaas::QuorumCredentials {
+            username: value(jaas::QUORUM_USERNAME_KEY)?,
+            password: value(jaas::QUORUM_PASSWORD_KEY)?,
+        };
+        if !jaas::is_valid_user_name(&credentials.username) {
+            return Err(Error::ReconcileError(format!(
+                "Secret [{}] contains the invalid user name [{}], only letters, digits, '_', '-' and '$' are allowed",
+                secret_name, credentials.username
+            )));
+        }
+        self.quorum_credentials = Some(credentials);
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Makes sure the Secret with the credentials of the ZooKeeper super user exists.
+    /// The operator needs them to reconfigure the ensemble, the servers only get the digest.
+    async fn init_super_credentials(&mut self) -> ZookeeperReconcileResult {
+        if self.membership_strategy() != MembershipStrategy::DynamicReconfig {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let secret_name = super_credentials_secret_name(&self.context.name());
+        let namespace = self.context.namespace();
+        let password = match self
+            .context
+            .client
+            .get::<Secret>(&secret_name, Some(namespace.as_str()))
+            .await
+        {
+            Ok(secret) => secret
+                .data
+                .unwrap_or_default()
+                .remove(SUPER_PASSWORD_KEY)
+                .and_then(|password| String::from_utf8(password.0).ok())
+                .ok_or_else(|| {
+                    Error::ReconcileError(format!(
+                        "Secret [{}] does not contain a valid [{}]",
+                        secret_name, SUPER_PASSWORD_KEY
+                    ))
+                })?,
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+            }) if response.code == 404 => {
+                info!(
+                    "Creating Secret [{}] with the credentials of the ZooKeeper super user",
+                    secret_name
+                );
+                let password = reconfig::generate_password();
+                let mut data = BTreeMap::new();
+                data.insert(SUPER_PASSWORD_KEY.to_string(), password.clone());
+                data.insert(
+                    SUPER_DIGEST_KEY.to_string(),
+                    reconfig::super_digest(&password),
+                );
+
+                let secret = Secret {
+                    metadata: metadata::build_metadata(
+                        secret_name,
+                        None,
+                        &self.context.resource,
+                        true,
+                    )?,
+                    string_data: Some(data),
+                    ..Secret::default()
+                };
+                self.context.client.create(&secret).await?;
+                password
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        self.super_password = Some(password);
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Returns all pods whose server was removed (e.g. because the role group was scaled down),
+    /// they will be deleted by `delete_excess_pods`.
+    fn find_excess_pods(&self) -> Vec<&Pod> {
+        let servers = self.servers();
+        self.existing_pods
+            .iter()
+            .filter(|pod| match pod_server_name(pod) {
+                Some(server_name) => !servers.contains_key(server_name),
+                // These are adopted by `assign_servers`
+                None => false,
+            })
+            .collect()
+    }
+
+    /// Returns all pods whose server was moved to another node or slot, they will be deleted by
+    /// `delete_excess_pods` and recreated in the new place with the same id and DNS name.
+    fn find_misplaced_pods(&self) -> Vec<&Pod> {
+        let servers = self.servers();
+        self.existing_pods
+            .iter()
+            .filter(|pod| {
+                let server = match pod_server_name(pod).and_then(|name| servers.get(name)) {
+                    Some(server) => server,
+                    None => return false,
+                };
+                let node_name = pod.spec.as_ref().and_then(|spec| spec.node_name.as_ref());
+                node_name != Some(&server.node_name) || pod_node_slot(pod) != server.node_slot
+            })
+            .collect()
+    }
+
+    /// Deletes all pods found by `find_excess_pods` and `find_misplaced_pods`.
+    async fn delete_excess_pods(&self) -> ZookeeperReconcileResult {
+        let pods = self
+            .find_excess_pods()
+            .into_iter()
+            .chain(self.find_misplaced_pods())
+            .collect::<Vec<_>>();
+        if pods.is_empty() {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        for pod in pods {
+            info!("Deleting pod [{}]", Resource::name(pod));
+            self.context.client.delete(pod).await?;
+        }
+        Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+    }
+
+    /// Waits until all pods are running and ready, readiness means that the server serves
+    /// requests (see `build_containers`).
+    ///
+    /// As long as servers are missing pods the ensemble may not be able to form a quorum (e.g.
+    /// during the initial installation), so the pods only need to be running to let the missing
+    /// ones be created.
+    async fn wait_for_ready_pods(&self) -> ZookeeperReconcileResult {
+        let servers_with_pods = self
+            .existing_pods
+            .iter()
+            .filter_map(pod_server_name)
+            .collect::<Vec<_>>();
+        let servers_missing_pods = self
+            .servers()
+            .keys()
+            .any(|server_name| !servers_with_pods.contains(&server_name));
+
+        if !servers_missing_pods {
+            return Ok(self
+                .context
+                .wait_for_running_and_ready_pods(&self.existing_pods)
+                .await?);
+        }
+
+        let not_running = self
+            .existing_pods
+            .iter()
+            .filter(|pod| !is_pod_running(pod))
+            .map(Resource::name)
+            .collect::<Vec<_>>();
+        if !not_running.is_empty() {
+            info!(
+                "Waiting for pods {:?} to be running before creating the missing ones",
+                not_running
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Connects to the first server that can be reached and authenticates as super user.
+    /// Pods in `excluded_pods` are skipped.
+    async fn connect_to_ensemble(
+        &self,
+        excluded_pods: &[String],
+    ) -> Result<ZookeeperClient, Error> {
+        let password = self.super_password.as_ref().ok_or_else(|| error::Error::ReconcileError(
+            "super_password missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
         ))?;
 
-        // If we reach here it means all pods must be running on target_version.
-        // We can now set current_version to target_version (if target_version was set) and
-        // target_version to None
-        if let Some(target_version) = &status.target_version {
-            self.zk_status = self.set_target_version(None).await?.status;
-            self.zk_status = self
-                .set_current_version(Some(&target_version))
-                .await?
-                .status;
-            self.zk_status = self
-                .set_upgrading_condition(
-                    &status.conditions,
-                    &format!(
-                        "No upgrade required [{:?}] is still the current_version",
-                        target_version
+        for pod in &self.existing_pods {
+            let pod_name = Resource::name(pod);
+            if excluded_pods.contains(&pod_name) {
+                continue;
+            }
+
+            let address = match self.client_address(pod) {
+                Some(address) => address,
+                None => continue,
+            };
+            match ZookeeperClient::connect(&address, ZK_REQUEST_TIMEOUT).await {
+                Ok(mut client) => {
+                    client
+                        .add_auth("digest", &format!("{}:{}", SUPER_USER, password))
+                        .await?;
+                    return Ok(client);
+                }
+                Err(err) => warn!(
+                    "Could not connect to ZooKeeper server [{}] of pod [{}], trying the next one: {}",
+                    address, pod_name, err
+                ),
+            }
+        }
+
+        Err(Error::ReconcileError(
+            "Could not connect to any ZooKeeper server of the ensemble".to_string(),
+        ))
+    }
+
+    /// Removes the servers whose pods are about to be deleted from the ensemble first, so the
+    /// remaining servers don't keep counting them when forming a quorum.
+    async fn remove_excess_servers(&self) -> ZookeeperReconcileResult {
+        if self.membership_strategy() != MembershipStrategy::DynamicReconfig {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let excess_pods = self.find_excess_pods();
+        let leaving = excess_pods
+            .iter()
+            .filter_map(|pod| pod.metadata.labels.as_ref()?.get(ID_LABEL)?.parse().ok())
+            .collect::<Vec<usize>>();
+        if leaving.is_empty() {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let excluded_pods = excess_pods
+            .iter()
+            .map(|pod| Resource::name(*pod))
+            .collect::<Vec<_>>();
+        let mut client = self.connect_to_ensemble(&excluded_pods).await?;
+        let current = reconfig::parse_dynamic_config(&String::from_utf8_lossy(
+            &client.get_data(ZOOKEEPER_CONFIG_NODE).await?,
+        ));
+
+        let leaving = leaving
+            .into_iter()
+            .filter(|id| current.contains_key(id))
+            .collect::<Vec<_>>();
+        if leaving.is_empty() {
+            client.close().await;
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+        if leaving.len() >= current.len() {
+            warn!(
+                "Not removing servers [{:?}] from the ensemble because no server would be left",
+                leaving
+            );
+            client.close().await;
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        info!("Removing servers [{:?}] from the ensemble", leaving);
+        let result = client.reconfig(&[], &leaving).await;
+        client.close().await;
+        handle_reconfig_result(result)
+    }
+
+    /// Brings the members the ensemble knows about in line with the id assignments by adding,
+    /// changing and removing servers using `reconfig`.
+    async fn reconfigure_ensemble(&self) -> ZookeeperReconcileResult {
+        if self.membership_strategy() != MembershipStrategy::DynamicReconfig {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let desired = self.build_servers()?;
+        if desired.is_empty() {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let mut client = self.connect_to_ensemble(&[]).await?;
+        let current = reconfig::parse_dynamic_config(&String::from_utf8_lossy(
+            &client.get_data(ZOOKEEPER_CONFIG_NODE).await?,
+        ));
+
+        let change = reconfig::membership_change(&current, &desired);
+        if change.is_empty() {
+            trace!("Members of the ensemble are up to date");
+            client.close().await;
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        info!(
+            "Reconfiguring the ensemble, joining: [{:?}], leaving: [{:?}]",
+            change.joining, change.leaving
+        );
+        let result = client.reconfig(&change.joining, &change.leaving).await;
+        client.close().await;
+        handle_reconfig_result(result)
+    }
+
+    /// Creates the Services of the cluster:
+    /// * the client Service, which balances client connections over all servers
+    /// * the headless Service the DNS names of the servers are registered with, which is used for
+    ///   the quorum traffic
+    /// * a Service per server if `perServerServices` is enabled
+    ///
+    /// The Services refer to the named container ports, so servers with different ports are
+    /// covered as well. Per server Services of servers that were removed are deleted.
+    async fn create_services(&self) -> ZookeeperReconcileResult {
+        let cluster_name = self.context.name();
+        let cluster_config = self.zk_spec.config.clone().unwrap_or_default();
+
+        let tls = self.zk_spec.tls.is_some();
+
+        let client_service = self.build_service(
+            &client_service_name(&cluster_name),
+            build_server_selector(&cluster_name),
+            ServiceSpec {
+                ports: Some(client_service_ports(&cluster_config, tls)),
+                ..ServiceSpec::default()
+            },
+        )?;
+        self.context
+            .client
+            .apply_patch(&client_service, &client_service)
+            .await?;
+
+        let headless_service = self.build_service(
+            &headless_service_name(&cluster_name),
+            build_server_selector(&cluster_name),
+            ServiceSpec {
+                cluster_ip: Some("None".to_string()),
+                ports: Some(vec![
+                    service_port(QUORUM_PORT_NAME, cluster_config.quorum_port()),
+                    service_port(
+                        LEADER_ELECTION_PORT_NAME,
+                        cluster_config.leader_election_port(),
                     ),
-                    "",
-                    ConditionStatus::False,
-                )
-                .await?
-                .status;
+                ]),
+                // The servers need to find each other before they can become ready
+                publish_not_ready_addresses: Some(true),
+                ..ServiceSpec::default()
+            },
+        )?;
+        self.context
+            .client
+            .apply_patch(&headless_service, &headless_service)
+            .await?;
+
+        let servers = self.servers();
+        let per_server_services = self.zk_spec.per_server_services.unwrap_or_default();
+        if per_server_services {
+            for server in servers.values() {
+                let server_config = self.server_config(&server.role_group, server.node_slot);
+                let mut ports = client_service_ports(&server_config, tls);
+                ports.push(service_port(QUORUM_PORT_NAME, server_config.quorum_port()));
+                ports.push(service_port(
+                    LEADER_ELECTION_PORT_NAME,
+                    server_config.leader_election_port(),
+                ));
+                let service = self.build_service(
+                    &server_service_name(&cluster_name, server.id),
+                    build_single_server_selector(&cluster_name, server.id),
+                    ServiceSpec {
+                        ports: Some(ports),
+                        publish_not_ready_addresses: Some(true),
+                        ..ServiceSpec::default()
+                    },
+                )?;
+                self.context.client.apply_patch(&service, &service).await?;
+            }
+        }
+
+        // Only the per server Services have the id label
+        let existing_server_services: Vec<Service> = self
+            .context
+            .client
+            .list_with_label_selector(
+                Some(self.context.namespace().as_str()),
+                &LabelSelector {
+                    match_labels: Some(build_server_selector(&cluster_name)),
+                    match_expressions: Some(vec![LabelSelectorRequirement {
+                        key: ID_LABEL.to_string(),
+                        operator: "Exists".to_string(),
+                        values: None,
+                    }]),
+                },
+            )
+            .await?;
+        for service in existing_server_services {
+            // Services named after an older naming scheme are replaced as well
+            let id = service
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(ID_LABEL))
+                .and_then(|id| id.parse::<usize>().ok());
+            let needed = per_server_services
+                && servers.values().any(|server| {
+                    Some(server.id) == id
+                        && Resource::name(&service) == server_service_name(&cluster_name, server.id)
+                });
+            if !needed {
+                info!("Deleting Service [{}]", Resource::name(&service));
+                self.context.client.delete(&service).await?;
+            }
         }
 
         Ok(ReconcileFunctionAction::Continue)
     }
 
+    /// Publishes how to connect to the cluster in the discovery ConfigMap, see [`discovery`].
+    /// This runs on every reconciliation, so the ConfigMap follows all changes of the servers.
+    async fn create_discovery_config_map(&self) -> ZookeeperReconcileResult {
+        let cluster_name = self.context.name();
+        let data = discovery::build_cluster_discovery_data(
+            &cluster_name,
+            &self.context.namespace(),
+            &self.zk_spec,
+            &self.servers(),
+            None,
+        );
+        let cm = config_map::create_config_map(
+            &self.context.resource,
+            &discovery::discovery_config_map_name(&cluster_name),
+            data,
+        )?;
+        self.context.client.apply_patch(&cm, &cm).await?;
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Builds a Service owned by the cluster, its labels are the same as its selector.
+    fn build_service(
+        &self,
+        name: &str,
+        selector: BTreeMap<String, String>,
+        spec: ServiceSpec,
+    ) -> OperatorResult<Service> {
+        Ok(Service {
+            metadata: metadata::build_metadata(
+                name.to_string(),
+                Some(selector.clone()),
+                &self.context.resource,
+                true,
+            )?,
+            spec: Some(ServiceSpec {
+                selector: Some(selector),
+                ..spec
+            }),
+            ..Service::default()
+        })
+    }
+
     async fn delete_all_pods(&self) -> OperatorResult<ReconcileFunctionAction> {
         let existing_pods = self.context.list_pods().await?;
         for pod in existing_pods {
@@ -614,78 +2560,266 @@ impl ZookeeperState {
         Ok(ReconcileFunctionAction::Done)
     }
 
-    async fn create_config_maps(&self, pod_name: &str, id: usize) -> Result<(), Error> {
-        let mut options = HashMap::new();
-        options.insert("tickTime".to_string(), "2000".to_string());
-        options.insert("dataDir".to_string(), "/tmp/zookeeper".to_string());
-        options.insert("initLimit".to_string(), "5".to_string());
-        options.insert("syncLimit".to_string(), "2".to_string());
-        options.insert("clientPort".to_string(), "2181".to_string());
+    /// Dynamic reconfiguration is only used when all servers support it, so during an upgrade
+    /// from a version without support the ensemble is managed with rolling restarts until the
+    /// upgrade is finished.
+    fn membership_strategy(&self) -> MembershipStrategy {
+        if self.running_versions().iter().all(|version| {
+            MembershipStrategy::for_version(version) == MembershipStrategy::DynamicReconfig
+        }) {
+            MembershipStrategy::DynamicReconfig
+        } else {
+            MembershipStrategy::RollingRestart
+        }
+    }
 
-        let id_information = self.id_information.as_ref().ok_or_else(|| error::Error::ReconcileError(
-                        "id_information missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
-                    ))?;
+    /// Maps the id of every server to its address based on the servers in the status.
+    /// Servers are addressed by their DNS names, which don't change when they are moved.
+    fn build_servers(&self) -> Result<BTreeMap<usize, ServerEntry>, Error> {
+        let cluster_name = self.context.name();
+        let namespace = self.context.namespace();
 
-        for (node_name, id) in &id_information.node_name_to_id {
-            options.insert(format!("server.{}", id), format!("{}:2888:3888", node_name));
-        }
+        Ok(self
+            .servers()
+            .iter()
+            .map(|(name, server)| {
+                // Every server needs to be rendered with the ports of its own role group and slot
+                let server_config = self.server_config(&server.role_group, server.node_slot);
+                (
+                    server.id,
+                    ServerEntry::new(
+                        &server_dns_name(name, &cluster_name, &namespace),
+                        &server_config,
+                        server.peer_type,
+                    ),
+                )
+            })
+            .collect())
+    }
 
-        let mut handlebars = Handlebars::new();
-        handlebars.set_strict_mode(true);
-        handlebars
-            .register_template_string("conf", "{{#each options}}{{@key}}={{this}}\n{{/each}}")
-            .expect("Failure rendering the ZooKeeper config template, this should not happen, please report this issue");
+    /// The configuration of the server in the given slot of a node of a role group.
+    fn server_config(&self, role_group: &str, node_slot: u8) -> ZookeeperConfig {
+        self.zk_spec
+            .merged_config(role_group)
+            .for_node_slot(node_slot)
+    }
 
-        let config = handlebars
-            .render("conf", &json!({ "options": options }))
-            .expect("Failure rendering the ZooKeeper config template, this should not happen, please report this issue");
+    /// Renders the configuration files for a server of the given role group and node slot based
+    /// on the current id assignments.
+    ///
+    /// This is always a `zoo.cfg` and - for versions supporting dynamic reconfiguration - a
+    /// `zoo.cfg.dynamic` with the members of the ensemble.
+    fn build_config_files(
+        &self,
+        role_group: &str,
+        node_slot: u8,
+    ) -> Result<BTreeMap<String, String>, Error> {
+        let zk_config = self.server_config(role_group, node_slot);
+        zk_config.validate()?;
+        let peer_type = self
+            .zk_spec
+            .role_group(role_group)
+            .map(|(_, peer_type)| peer_type)
+            .unwrap_or_default();
+
+        let servers = self.build_servers()?;
+
+        let mut files = BTreeMap::new();
+        let mut options = match self.membership_strategy() {
+            MembershipStrategy::RollingRestart => {
+                config::build_zoo_cfg(&zk_config, peer_type, &servers)
+            }
+            MembershipStrategy::DynamicReconfig => {
+                let dynamic_config_file =
+                    format!("{}/{}", writable_config_dir(&zk_config), ZOO_CFG_DYNAMIC);
+                files.insert(
+                    ZOO_CFG_DYNAMIC.to_string(),
+                    config::render_zoo_cfg(&config::build_dynamic_cfg(&servers)),
+                );
+                config::build_reconfig_zoo_cfg(&zk_config, peer_type, &dynamic_config_file)
+            }
+        };
+        if let Some(tls) = &self.zk_spec.tls {
+            options.extend(config::build_client_tls_options(&zk_config, tls));
+            options.extend(config::build_quorum_tls_options(
+                tls,
+                self.quorum_tls_stage(),
+            ));
+        }
+        if let Some(authentication) = &self.zk_spec.authentication {
+            options.extend(config::build_authentication_options(authentication));
+        }
+        options.extend(config::build_quorum_sasl_options(self.quorum_sasl_stage()));
+        files.insert(ZOO_CFG.to_string(), config::render_zoo_cfg(&options));
+        Ok(files)
+    }
 
-        // Now we need to create two configmaps per server.
-        // The names are "zk-<cluster name>-<node name>-config" and "zk-<cluster name>-<node name>-data"
-        // One for the configuration directory...
-        let mut data = BTreeMap::new();
-        data.insert("zoo.cfg".to_string(), config);
+    async fn create_config_maps(
+        &self,
+        pod_name: &str,
+        role_group: &str,
+        node_slot: u8,
+    ) -> Result<(), Error> {
+        // Now we need to create a configmap per server for the configuration directory.
+        // The name is "<pod name>-config".
+        // The `myid` file is written into the data directory when the server starts.
+        let data = self.build_config_files(role_group, node_slot)?;
 
         let cm_name = format!("{}-config", pod_name);
         let cm = config_map::create_config_map(&self.context.resource, &cm_name, data)?;
         self.context.client.apply_patch(&cm, &cm).await?;
+        Ok(())
+    }
 
-        // ...and one for the data directory (which only contains the myid file)
-        let mut data = BTreeMap::new();
-        data.insert("myid".to_string(), id.to_string());
-        let cm_name = format!("{}-data", pod_name);
-        let cm = config_map::create_config_map(&self.context.resource, &cm_name, data)?;
-        self.context.client.apply_patch(&cm, &cm).await?;
+    /// Renders the JAAS configuration of a server, `None` without `authentication`.
+    fn build_jaas_config(&self, server_name: &str) -> Option<String> {
+        let authentication = self.zk_spec.authentication.as_ref()?;
+        let host = server_dns_name(server_name, &self.context.name(), &self.context.namespace());
+        Some(jaas::build_jaas_config(
+            authentication,
+            &self.digest_users,
+            self.quorum_credentials.as_ref(),
+            &host,
+        ))
+    }
+
+    /// Writes the Secret "<pod name>-jaas" with the JAAS configuration of the server if
+    /// `authentication` is set. It is only read when the server starts.
+    async fn create_jaas_secret(&self, pod_name: &str, server_name: &str) -> Result<(), Error> {
+        let jaas_config = match self.build_jaas_config(server_name) {
+            Some(jaas_config) => jaas_config,
+            None => return Ok(()),
+        };
+
+        let mut string_data = BTreeMap::new();
+        string_data.insert(jaas::JAAS_FILE.to_string(), jaas_config);
+        let secret = Secret {
+            metadata: metadata::build_metadata(
+                jaas::jaas_secret_name(pod_name),
+                None,
+                &self.context.resource,
+                true,
+            )?,
+            string_data: Some(string_data),
+            ..Secret::default()
+        };
+        self.context.client.apply_patch(&secret, &secret).await?;
         Ok(())
     }
 
+    /// Whether the JAAS configuration the pod was started with differs from the current one,
+    /// e.g. because users were added to the Secret of `authentication.digest`.
+    async fn has_outdated_jaas_config(&self, pod: &Pod) -> bool {
+        let desired = match pod_server_name(pod).and_then(|name| self.build_jaas_config(name)) {
+            Some(desired) => desired,
+            None => return false,
+        };
+
+        let pod_name = Resource::name(pod);
+        let secret_name = jaas::jaas_secret_name(&pod_name);
+        let namespace = self.context.namespace();
+        match self
+            .context
+            .client
+            .get::<Secret>(&secret_name, Some(namespace.as_str()))
+            .await
+        {
+            Ok(secret) => {
+                secret
+                    .data
+                    .and_then(|mut data| data.remove(jaas::JAAS_FILE))
+                    .map(|current| current.0)
+                    != Some(desired.into_bytes())
+            }
+            Err(err) => {
+                warn!(
+                    "Could not read Secret [{}] of pod [{}], treating its JAAS configuration as outdated: {}",
+                    secret_name, pod_name, err
+                );
+                true
+            }
+        }
+    }
+
     async fn create_pod(
         &self,
-        node_name: &str,
+        server_name: &str,
+        server: &ServerStatus,
         pod_name: &str,
         labels: BTreeMap<String, String>,
     ) -> Result<Pod, Error> {
-        let pod = self.build_pod(node_name, pod_name, labels)?;
+        let zk_config = self.server_config(&server.role_group, server.node_slot);
+        zk_config.validate()?;
+
+        // The claim is owned by the cluster and not by the pod so it survives pod restarts
+        if let Some(pvc_storage) = &zk_config.storage().persistent_volume_claim {
+            let pvc = self.build_data_volume_claim(pod_name, labels.clone(), pvc_storage)?;
+            self.context.client.apply_patch(&pvc, &pvc).await?;
+        }
+
+        let pod = self.build_pod(server_name, server, pod_name, &zk_config, labels)?;
         Ok(self.context.client.create(&pod).await?)
     }
 
-    fn build_pod(
+    fn build_data_volume_claim(
         &self,
-        node_name: &str,
         pod_name: &str,
         labels: BTreeMap<String, String>,
-    ) -> Result<Pod, Error> {
-        let (containers, volumes) = self.build_containers(pod_name);
+        pvc_storage: &PersistentVolumeClaimStorage,
+    ) -> Result<PersistentVolumeClaim, Error> {
+        let mut requests = BTreeMap::new();
+        requests.insert("storage".to_string(), Quantity(pvc_storage.size.clone()));
 
-        Ok(Pod {
+        Ok(PersistentVolumeClaim {
             metadata: metadata::build_metadata(
-                pod_name.to_string(),
+                data_volume_claim_name(pod_name),
                 Some(labels),
                 &self.context.resource,
                 true,
             )?,
+            spec: Some(PersistentVolumeClaimSpec {
+                access_modes: Some(vec!["ReadWriteOnce".to_string()]),
+                resources: Some(ResourceRequirements {
+                    requests: Some(requests),
+                    ..ResourceRequirements::default()
+                }),
+                storage_class_name: pvc_storage.storage_class_name.clone(),
+                ..PersistentVolumeClaimSpec::default()
+            }),
+            ..PersistentVolumeClaim::default()
+        })
+    }
+
+    fn build_pod(
+        &self,
+        server_name: &str,
+        server: &ServerStatus,
+        pod_name: &str,
+        zk_config: &ZookeeperConfig,
+        labels: BTreeMap<String, String>,
+    ) -> Result<Pod, Error> {
+        let (containers, volumes) = self.build_containers(pod_name, server.id, zk_config)?;
+
+        let mut metadata = metadata::build_metadata(
+            pod_name.to_string(),
+            Some(labels),
+            &self.context.resource,
+            true,
+        )?;
+        if let Some(serial) = self.issued_certificates.get(pod_name) {
+            metadata
+                .annotations
+                .get_or_insert_with(BTreeMap::new)
+                .insert(certificates::SERIAL_ANNOTATION.to_string(), serial.clone());
+        }
+
+        Ok(Pod {
+            metadata,
             spec: Some(PodSpec {
-                node_name: Some(node_name.to_string()),
+                node_name: Some(server.node_name.clone()),
+                // Together these make up the DNS name of the server, see `server_dns_name`
+                hostname: Some(server_name.to_string()),
+                subdomain: Some(headless_service_name(&self.context.name())),
                 tolerations: Some(krustlet::create_tolerations()),
                 containers,
                 volumes: Some(volumes),
@@ -696,58 +2830,275 @@ impl ZookeeperState {
         })
     }
 
-    fn build_containers(&self, pod_name: &str) -> (Vec<Container>, Vec<Volume>) {
-        let version = &self.context.resource.spec.version;
+    fn build_containers(
+        &self,
+        pod_name: &str,
+        id: usize,
+        zk_config: &ZookeeperConfig,
+    ) -> Result<(Vec<Container>, Vec<Volume>), Error> {
+        let version = &self.pod_version();
+
+        let image_name = version.image_name()?;
+        let package_name = version.package_name()?;
+
+        // The `myid` file needs to live in the data directory which is persistent storage and
+        // can't be populated from a ConfigMap, so we write it right before starting the server.
+        let write_myid = format!("echo {} > {}/myid", id, zk_config.data_dir());
+        let config_root = "{{ configroot }}/conf";
+
+        let mut env = vec![];
+        // System properties for the server, they may refer to the variables in `env`
+        let mut jvm_flags = vec![];
+        let start_command = match self.membership_strategy() {
+            MembershipStrategy::RollingRestart => format!(
+                "{} && exec {}/bin/zkServer.sh start-foreground {}/{}",
+                write_myid,
+                package_name,
+                // "--config" TODO: Version 3.4 does not support --config but later versions do
+                config_root,
+                ZOO_CFG
+            ),
+            MembershipStrategy::DynamicReconfig => {
+                // ZooKeeper rewrites its configuration files on reconfiguration so they can't
+                // be used from the (read-only) ConfigMap directly
+                let config_dir = writable_config_dir(zk_config);
+
+                env.push(EnvVar {
+                    name: "ZK_SUPER_DIGEST".to_string(),
+                    value_from: Some(EnvVarSource {
+                        secret_key_ref: Some(SecretKeySelector {
+                            name: Some(super_credentials_secret_name(&self.context.name())),
+                            key: SUPER_DIGEST_KEY.to_string(),
+                            ..SecretKeySelector::default()
+                        }),
+                        ..EnvVarSource::default()
+                    }),
+                    ..EnvVar::default()
+                });
+                jvm_flags.push(
+                    "-Dzookeeper.DigestAuthenticationProvider.superDigest=$(ZK_SUPER_DIGEST)"
+                        .to_string(),
+                );
+
+                format!(
+                    "{write_myid} && mkdir -p {config_dir} && cp {config_root}/{zoo_cfg} {config_root}/{zoo_cfg_dynamic} {config_dir}/ && exec {package}/bin/zkServer.sh start-foreground {config_dir}/{zoo_cfg}",
+                    write_myid = write_myid,
+                    config_dir = config_dir,
+                    config_root = config_root,
+                    zoo_cfg = ZOO_CFG,
+                    zoo_cfg_dynamic = ZOO_CFG_DYNAMIC,
+                    package = package_name,
+                )
+            }
+        };
+
+        // With TLS the plain client port is only used by the operator and the probes, so it
+        // isn't declared (see `client_service_ports`)
+        let mut ports = vec![
+            container_port(QUORUM_PORT_NAME, zk_config.quorum_port()),
+            container_port(LEADER_ELECTION_PORT_NAME, zk_config.leader_election_port()),
+        ];
+        if self.zk_spec.tls.is_none() {
+            ports.push(container_port(CLIENT_PORT_NAME, zk_config.client_port()));
+        }
+        let mut volume_mounts = vec![
+            // One mount for the config directory, this will be relative to the extracted package
+            VolumeMount {
+                mount_path: "conf".to_string(),
+                name: "config-volume".to_string(),
+                ..VolumeMount::default()
+            },
+            // The data directory and the transaction log directory share one volume
+            VolumeMount {
+                mount_path: zk_config.data_dir().to_string(),
+                name: "data-volume".to_string(),
+                sub_path: Some("data".to_string()),
+                ..VolumeMount::default()
+            },
+            VolumeMount {
+                mount_path: zk_config.data_log_dir().to_string(),
+                name: "data-volume".to_string(),
+                sub_path: Some("datalog".to_string()),
+                ..VolumeMount::default()
+            },
+        ];
+        let mut volumes = vec![Volume {
+            name: "config-volume".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(format!("{}-config", pod_name)),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        }];
+
+        if let Some(tls) = &self.zk_spec.tls {
+            let secret_name = tls
+                .secret_name
+                .clone()
+                .unwrap_or_else(|| server_tls_secret_name(pod_name));
+            ports.push(container_port(
+                SECURE_CLIENT_PORT_NAME,
+                zk_config.secure_client_port(),
+            ));
+            volume_mounts.push(VolumeMount {
+                mount_path: config::TLS_DIR.to_string(),
+                name: "tls-volume".to_string(),
+                read_only: Some(true),
+                ..VolumeMount::default()
+            });
+            volumes.push(Volume {
+                name: "tls-volume".to_string(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(secret_name.clone()),
+                    ..SecretVolumeSource::default()
+                }),
+                ..Volume::default()
+            });
+            if tls.format().has_password() {
+                env.push(EnvVar {
+                    name: "ZK_TLS_PASSWORD".to_string(),
+                    value_from: Some(EnvVarSource {
+                        secret_key_ref: Some(SecretKeySelector {
+                            name: Some(secret_name),
+                            key: config::TLS_PASSWORD_KEY.to_string(),
+                            ..SecretKeySelector::default()
+                        }),
+                        ..EnvVarSource::default()
+                    }),
+                    ..EnvVar::default()
+                });
+                jvm_flags.push("-Dzookeeper.ssl.keyStore.password=$(ZK_TLS_PASSWORD)".to_string());
+                jvm_flags
+                    .push("-Dzookeeper.ssl.trustStore.password=$(ZK_TLS_PASSWORD)".to_string());
+                // Only used while quorum connections use TLS, but setting them regardless
+                // keeps the pods unchanged between the stages
+                jvm_flags.push(
+                    "-Dzookeeper.ssl.quorum.keyStore.password=$(ZK_TLS_PASSWORD)".to_string(),
+                );
+                jvm_flags.push(
+                    "-Dzookeeper.ssl.quorum.trustStore.password=$(ZK_TLS_PASSWORD)".to_string(),
+                );
+            }
+        }
+
+        if let Some(authentication) = &self.zk_spec.authentication {
+            volume_mounts.push(VolumeMount {
+                mount_path: jaas::JAAS_DIR.to_string(),
+                name: "jaas-volume".to_string(),
+                read_only: Some(true),
+                ..VolumeMount::default()
+            });
+            volumes.push(Volume {
+                name: "jaas-volume".to_string(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(jaas::jaas_secret_name(pod_name)),
+                    ..SecretVolumeSource::default()
+                }),
+                ..Volume::default()
+            });
+            jvm_flags.push(format!(
+                "-Djava.security.auth.login.config={}/{}",
+                jaas::JAAS_DIR,
+                jaas::JAAS_FILE
+            ));
+
+            if let Some(kerberos) = &authentication.kerberos {
+                volume_mounts.push(VolumeMount {
+                    mount_path: jaas::KERBEROS_DIR.to_string(),
+                    name: "kerberos-volume".to_string(),
+                    read_only: Some(true),
+                    ..VolumeMount::default()
+                });
+                volumes.push(Volume {
+                    name: "kerberos-volume".to_string(),
+                    secret: Some(SecretVolumeSource {
+                        secret_name: Some(kerberos.keytab_secret_name.clone()),
+                        ..SecretVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                });
+                if let Some(krb5_config_map_name) = &kerberos.krb5_config_map_name {
+                    volume_mounts.push(VolumeMount {
+                        mount_path: jaas::KRB5_DIR.to_string(),
+                        name: "krb5-volume".to_string(),
+                        read_only: Some(true),
+                        ..VolumeMount::default()
+                    });
+                    volumes.push(Volume {
+                        name: "krb5-volume".to_string(),
+                        config_map: Some(ConfigMapVolumeSource {
+                            name: Some(krb5_config_map_name.clone()),
+                            ..ConfigMapVolumeSource::default()
+                        }),
+                        ..Volume::default()
+                    });
+                    jvm_flags.push(format!(
+                        "-Djava.security.krb5.conf={}/{}",
+                        jaas::KRB5_DIR,
+                        jaas::KRB5_CONF_KEY
+                    ));
+                }
+            }
+        }
 
-        let image_name = format!("stackable/zookeeper:{}", version.to_string());
+        if !jvm_flags.is_empty() {
+            env.push(EnvVar {
+                name: "SERVER_JVMFLAGS".to_string(),
+                value: Some(jvm_flags.join(" ")),
+                ..EnvVar::default()
+            });
+        }
 
         let containers = vec![Container {
             image: Some(image_name),
             name: "zookeeper".to_string(),
-            command: Some(vec![
-                format!("{}/bin/zkServer.sh", version.package_name()),
-                "start-foreground".to_string(),
-                // "--config".to_string(), TODO: Version 3.4 does not support --config but later versions do
-                "{{ configroot }}/conf/zoo.cfg".to_string(), // TODO: Later versions can probably point to a directory instead, investigate
-            ]),
-            volume_mounts: Some(vec![
-                // One mount for the config directory, this will be relative to the extracted package
-                VolumeMount {
-                    mount_path: "conf".to_string(),
-                    name: "config-volume".to_string(),
-                    ..VolumeMount::default()
-                },
-                // We need a second mount for the data directory
-                // because we need to write the myid file into the data directory
-                VolumeMount {
-                    mount_path: "/tmp/zookeeper".to_string(), // TODO: Make configurable
-                    name: "data-volume".to_string(),
-                    ..VolumeMount::default()
-                },
-            ]),
+            command: Some(vec!["sh".to_string(), "-c".to_string(), start_command]),
+            env: Some(env),
+            ports: Some(ports),
+            readiness_probe: Some(four_letter_word_probe(
+                zk_config.client_port(),
+                "srvr",
+                READINESS_PATTERN,
+                0,
+            )),
+            liveness_probe: Some(four_letter_word_probe(
+                zk_config.client_port(),
+                "ruok",
+                LIVENESS_PATTERN,
+                LIVENESS_INITIAL_DELAY_SECONDS,
+            )),
+            volume_mounts: Some(volume_mounts),
             ..Container::default()
         }];
 
-        let volumes = vec![
-            Volume {
-                name: "config-volume".to_string(),
-                config_map: Some(ConfigMapVolumeSource {
-                    name: Some(format!("{}-config", pod_name)),
-                    ..ConfigMapVolumeSource::default()
+        let storage = zk_config.storage();
+        let data_volume = match (&storage.persistent_volume_claim, &storage.host_path) {
+            (Some(_), _) => Volume {
+                name: "data-volume".to_string(),
+                persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                    claim_name: data_volume_claim_name(pod_name),
+                    ..PersistentVolumeClaimVolumeSource::default()
                 }),
                 ..Volume::default()
             },
-            Volume {
+            (None, host_path) => Volume {
                 name: "data-volume".to_string(),
-                config_map: Some(ConfigMapVolumeSource {
-                    name: Some(format!("{}-data", pod_name)),
-                    ..ConfigMapVolumeSource::default()
+                host_path: Some(HostPathVolumeSource {
+                    path: format!(
+                        "{}/{}/{}",
+                        host_path.clone().unwrap_or_default().path(),
+                        self.context.namespace(),
+                        pod_name
+                    ),
+                    type_: Some("DirectoryOrCreate".to_string()),
                 }),
                 ..Volume::default()
             },
-        ];
+        };
 
-        (containers, volumes)
+        volumes.push(data_volume);
+
+        Ok((containers, volumes))
     }
 }
 
@@ -770,33 +3121,62 @@ impl ReconciliationState for ZookeeperState {
                     true,
                 ))
                 .await?
+                .then(self.check_health())
+                .await?
                 .then(self.context.delete_illegal_pods(
                     self.existing_pods.as_slice(),
                     &self.get_required_labels(),
                     ContinuationStrategy::OneRequeue,
                 ))
                 .await?
+                .then(self.rollback_failed_upgrade())
+                .await?
                 .then(
                     self.context
                         .wait_for_terminating_pods(self.existing_pods.as_slice()),
                 )
                 .await?
-                .then(
-                    self.context
-                        .wait_for_running_and_ready_pods(&self.existing_pods),
-                )
+                .then(self.wait_for_ready_pods())
                 .await?
-                .then(self.context.delete_excess_pods(
-                    self.get_full_pod_node_map().as_slice(),
-                    &self.existing_pods,
-                    ContinuationStrategy::OneRequeue,
-                ))
+                .then(self.init_super_credentials())
+                .await?
+                .then(self.read_digest_users())
+                .await?
+                .then(self.read_quorum_credentials())
+                .await?
+                .then(self.init_quorum_tls_stage())
+                .await?
+                .then(self.init_quorum_sasl_stage())
                 .await?
                 .then(self.read_existing_pod_information())
                 .await?
-                .then(self.assign_ids())
+                .then(self.assign_servers())
+                .await?
+                .then(self.remove_excess_servers())
+                .await?
+                .then(self.delete_excess_pods())
+                .await?
+                .then(self.create_services())
+                .await?
+                .then(self.create_discovery_config_map())
+                .await?
+                .then(self.issue_certificates())
                 .await?
                 .then(self.create_missing_pods())
+                .await?
+                .then(self.reconfigure_ensemble())
+                .await?
+                .then(self.upgrade_pods())
+                .await?
+                .then(self.restart_outdated_pods())
+                .await?
+                .then(self.finish_upgrade())
+                .await?
+                .then(self.advance_quorum_tls_stage())
+                .await?
+                .then(self.advance_quorum_sasl_stage())
+                .await?
+                .then(self.schedule_health_check())
                 .await
         })
     }
@@ -819,7 +3199,7 @@ impl ControllerStrategy for ZookeeperStrategy {
 
     /// Init the ZooKeeper state. Store all available pods owned by this cluster for later processing.
     /// Retrieve nodes that fit selectors and store them for later processing:
-    /// ZookeeperRole (we only have 'server') -> role group -> list of nodes.
+    /// ZookeeperRole -> role group -> list of nodes.
     async fn init_reconcile_state(
         &self,
         context: ReconciliationContext<Self::Item>,
@@ -835,25 +3215,27 @@ impl ControllerStrategy for ZookeeperStrategy {
 
         let mut eligible_nodes = HashMap::new();
 
-        let role_groups: Vec<RoleGroup> = zk_spec
-            .servers
-            .selectors
-            .iter()
-            .map(|(group_name, selector_config)| RoleGroup {
-                name: group_name.to_string(),
-                selector: selector_config.clone().selector.unwrap(),
-            })
-            .collect();
-
-        eligible_nodes.insert(
-            ZookeeperRole::Server,
-            role_utils::find_nodes_that_fit_selectors(
-                &context.client,
-                None,
-                role_groups.as_slice(),
-            )
-            .await?,
-        );
+        for peer_type in &[PeerType::Participant, PeerType::Observer] {
+            let role_groups: Vec<RoleGroup> = zk_spec
+                .role_groups()
+                .into_iter()
+                .filter(|(_, _, role_group_peer_type)| role_group_peer_type == peer_type)
+                .map(|(group_name, selector_config, _)| RoleGroup {
+                    name: group_name.to_string(),
+                    selector: selector_config.selector(),
+                })
+                .collect();
+
+            eligible_nodes.insert(
+                ZookeeperRole::for_peer_type(*peer_type),
+                role_utils::find_nodes_that_fit_selectors(
+                    &context.client,
+                    None,
+                    role_groups.as_slice(),
+                )
+                .await?,
+            );
+        }
 
         Ok(ZookeeperState {
             zk_spec: context.resource.spec.clone(),
@@ -862,6 +3244,10 @@ impl ControllerStrategy for ZookeeperStrategy {
             id_information: None,
             existing_pods,
             eligible_nodes,
+            super_password: None,
+            issued_certificates: BTreeMap::new(),
+            digest_users: BTreeMap::new(),
+            quorum_credentials: None,
         })
     }
 }
@@ -873,10 +3259,12 @@ pub async fn create_controller(client: Client) {
     let zk_api: Api<ZookeeperCluster> = client.get_all_api();
     let pods_api: Api<Pod> = client.get_all_api();
     let config_maps_api: Api<ConfigMap> = client.get_all_api();
+    let services_api: Api<Service> = client.get_all_api();
 
     let controller = Controller::new(zk_api)
         .owns(pods_api, ListParams::default())
-        .owns(config_maps_api, ListParams::default());
+        .owns(config_maps_api, ListParams::default())
+        .owns(services_api, ListParams::default());
 
     let strategy = ZookeeperStrategy::new();
 
@@ -885,22 +3273,251 @@ pub async fn create_controller(client: Client) {
         .await;
 }
 
-/// Return a map with labels and values for role (component) and group (role_group).
-/// Required to find nodes that are a possible match for pods.
-/// TODO: move to operator-rs
-fn get_role_and_group_labels(role: &ZookeeperRole, group_name: &str) -> LabelOptionalValueMap {
+/// The name of the pod running the server with the given ordinal of a role group.
+/// The names of the pod's ConfigMap and PersistentVolumeClaim are derived from this.
+fn build_pod_name(
+    cluster_name: &str,
+    role_group: &str,
+    role: &ZookeeperRole,
+    ordinal: u16,
+) -> String {
+    format!(
+        "{}-{}-{}-{}-{}",
+        APP_NAME, cluster_name, role_group, role, ordinal
+    )
+    .to_lowercase()
+}
+
+/// The name of the Service clients connect to.
+fn client_service_name(cluster_name: &str) -> String {
+    cluster_name.to_string()
+}
+
+/// The name of the Service of a single server, see `perServerServices`.
+fn server_service_name(cluster_name: &str, id: usize) -> String {
+    format!("{}-server-{}", cluster_name, id)
+}
+
+/// The names a server can be reached by, they all go into its certificate: its DNS name (used by
+/// the other servers), the DNS names of its own Service and of the client Service and its node.
+fn server_certificate_dns_names(
+    server_name: &str,
+    server: &ServerStatus,
+    cluster_name: &str,
+    namespace: &str,
+) -> Vec<String> {
+    vec![
+        server_dns_name(server_name, cluster_name, namespace),
+        format!(
+            "{}.{}.svc",
+            server_service_name(cluster_name, server.id),
+            namespace
+        ),
+        format!("{}.{}.svc", client_service_name(cluster_name), namespace),
+        server.node_name.clone(),
+    ]
+}
+
+fn container_port(name: &str, port: u16) -> ContainerPort {
+    ContainerPort {
+        name: Some(name.to_string()),
+        container_port: port.into(),
+        protocol: Some("TCP".to_string()),
+        ..ContainerPort::default()
+    }
+}
+
+/// A port of a Service that is forwarded to the container port with the same name.
+fn service_port(name: &str, port: u16) -> ServicePort {
+    ServicePort {
+        name: Some(name.to_string()),
+        port: port.into(),
+        target_port: Some(IntOrString::String(name.to_string())),
+        protocol: Some("TCP".to_string()),
+        ..ServicePort::default()
+    }
+}
+
+/// The client ports of a Service: only the secure client port with TLS, the plain client port
+/// otherwise. With TLS the servers still listen on the plain client port because the operator
+/// (health checks, reconfiguration, znodes) and the probes use it, but it isn't exposed.
+fn client_service_ports(config: &ZookeeperConfig, tls: bool) -> Vec<ServicePort> {
+    if tls {
+        vec![service_port(
+            SECURE_CLIENT_PORT_NAME,
+            config.secure_client_port(),
+        )]
+    } else {
+        vec![service_port(CLIENT_PORT_NAME, config.client_port())]
+    }
+}
+
+/// Labels matching the pods of all servers of a cluster including observers, these are the
+/// labels of `build_pod_labels` which are the same for all servers.
+fn build_server_selector(cluster_name: &str) -> BTreeMap<String, String> {
     let mut labels = BTreeMap::new();
+    labels.insert(labels::APP_NAME_LABEL.to_string(), APP_NAME.to_string());
     labels.insert(
-        labels::APP_COMPONENT_LABEL.to_string(),
-        Some(role.to_string()),
+        labels::APP_MANAGED_BY_LABEL.to_string(),
+        MANAGED_BY.to_string(),
     );
     labels.insert(
-        labels::APP_ROLE_GROUP_LABEL.to_string(),
-        Some(group_name.to_string()),
+        labels::APP_INSTANCE_LABEL.to_string(),
+        cluster_name.to_string(),
     );
+
     labels
 }
 
+/// Labels matching the pod of the server with the given id.
+fn build_single_server_selector(cluster_name: &str, id: usize) -> BTreeMap<String, String> {
+    let mut selector = build_server_selector(cluster_name);
+    selector.insert(ID_LABEL.to_string(), id.to_string());
+    selector
+}
+
+/// The server running in the pod, pods created before servers were recorded in the status
+/// don't have one.
+fn pod_server_name(pod: &Pod) -> Option<&String> {
+    pod.metadata.labels.as_ref()?.get(SERVER_LABEL)
+}
+
+/// Pods created before several servers per node were supported don't have the label and are
+/// in slot 0.
+fn pod_node_slot(pod: &Pod) -> u8 {
+    pod.metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(NODE_SLOT_LABEL))
+        .and_then(|node_slot| node_slot.parse().ok())
+        .unwrap_or(0)
+}
+
+fn data_volume_claim_name(pod_name: &str) -> String {
+    format!("{}-data", pod_name)
+}
+
+fn is_pod_ready(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .map(|conditions| {
+            conditions
+                .iter()
+                .any(|condition| condition.type_ == "Ready" && condition.status == "True")
+        })
+        .unwrap_or(false)
+}
+
+/// An exec probe which sends a four letter word to the server, see
+/// [`four_letter_words::probe_command`].
+fn four_letter_word_probe(
+    client_port: u16,
+    command: &str,
+    expected: &str,
+    initial_delay_seconds: i32,
+) -> Probe {
+    Probe {
+        exec: Some(ExecAction {
+            command: Some(four_letter_words::probe_command(
+                client_port,
+                command,
+                expected,
+            )),
+        }),
+        initial_delay_seconds: Some(initial_delay_seconds),
+        period_seconds: Some(10),
+        timeout_seconds: Some(5),
+        failure_threshold: Some(3),
+        ..Probe::default()
+    }
+}
+
+fn is_pod_running(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.phase.as_deref())
+        == Some("Running")
+}
+
+fn pod_version_label(pod: &Pod) -> Option<&String> {
+    pod.metadata.labels.as_ref()?.get(labels::APP_VERSION_LABEL)
+}
+
+/// There is a quorum if there is exactly one leader and a majority of the servers serves
+/// requests.
+fn has_quorum(stats: &BTreeMap<String, ServerStats>) -> bool {
+    let serving = stats
+        .values()
+        .filter(|server_stats| server_stats.mode.is_some())
+        .count();
+    let leaders = stats
+        .values()
+        .filter(|server_stats| server_stats.mode.map_or(false, |mode| mode.is_leader()))
+        .count();
+
+    leaders == 1 && serving > stats.len() / 2
+}
+
+fn server_health(stats: &ServerStats) -> ServerHealth {
+    ServerHealth {
+        mode: stats.mode.map(|mode| mode.as_str().to_string()),
+        zxid: stats.zxid.clone(),
+        outstanding_requests: stats.outstanding_requests,
+        min_latency: stats.min_latency,
+        avg_latency: stats.avg_latency,
+        max_latency: stats.max_latency,
+    }
+}
+
+/// The quorum is considered healthy if there is exactly one leader and all other servers are
+/// serving requests as well.
+fn is_quorum_healthy(server_modes: &BTreeMap<String, Option<ServerMode>>) -> bool {
+    server_modes.values().all(Option::is_some)
+        && server_modes
+            .values()
+            .flatten()
+            .filter(|mode| mode.is_leader())
+            .count()
+            == 1
+}
+
+fn super_credentials_secret_name(cluster_name: &str) -> String {
+    format!("{}-{}-super-credentials", APP_NAME, cluster_name)
+}
+
+/// The configuration files are copied from the ConfigMap into this directory on the data volume
+/// when a server starts, because ZooKeeper needs to be able to rewrite them on reconfiguration.
+fn writable_config_dir(zk_config: &ZookeeperConfig) -> String {
+    format!("{}/conf", zk_config.data_dir())
+}
+
+/// Requeues when the ensemble can't be reconfigured right now, e.g. because another
+/// reconfiguration is still running or because not enough of the new members are up yet.
+fn handle_reconfig_result<T>(result: Result<T, Error>) -> ZookeeperReconcileResult {
+    match result {
+        Ok(_) => Ok(ReconcileFunctionAction::Continue),
+        Err(Error::ZookeeperError { code, .. })
+            if code == error_code::RECONFIG_IN_PROGRESS
+                || code == error_code::NEW_CONFIG_NO_QUORUM =>
+        {
+            info!(
+                "Ensemble can not be reconfigured right now (error code [{}]), will try again",
+                code
+            );
+            Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+        }
+        // This happens right after switching to dynamic reconfiguration (e.g. after an upgrade
+        // from 3.4), the servers will be restarted with the new configuration in
+        // `restart_outdated_pods`
+        Err(Error::ZookeeperError { code, .. }) if code == error_code::RECONFIG_DISABLED => {
+            warn!("Dynamic reconfiguration is not enabled on the ensemble yet, skipping reconfiguration until the servers have been restarted");
+            Ok(ReconcileFunctionAction::Continue)
+        }
+        Err(err) => Err(err),
+    }
+}
+
 fn build_pod_labels(
     role: &str,
     role_group: &str,
@@ -908,24 +3525,25 @@ fn build_pod_labels(
     version: &str,
     id: &str,
 ) -> BTreeMap<String, String> {
-    let mut labels = BTreeMap::new();
-    labels.insert(labels::APP_NAME_LABEL.to_string(), APP_NAME.to_string());
-    labels.insert(
-        labels::APP_MANAGED_BY_LABEL.to_string(),
-        MANAGED_BY.to_string(),
-    );
-    labels.insert(labels::APP_COMPONENT_LABEL.to_string(), role.to_string());
+    let mut labels = build_common_labels(role, name);
     labels.insert(
         labels::APP_ROLE_GROUP_LABEL.to_string(),
         role_group.to_string(),
     );
-    labels.insert(labels::APP_INSTANCE_LABEL.to_string(), name.to_string());
     labels.insert(labels::APP_VERSION_LABEL.to_string(), version.to_string());
     labels.insert(ID_LABEL.to_string(), id.to_string());
 
     labels
 }
 
+/// The labels of `build_pod_labels` that all pods of a role of a cluster share.
+fn build_common_labels(role: &str, name: &str) -> BTreeMap<String, String> {
+    let mut labels = build_server_selector(name);
+    labels.insert(labels::APP_COMPONENT_LABEL.to_string(), role.to_string());
+
+    labels
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -940,7 +3558,124 @@ mod tests {
     #[case(vec![1], 2)]
     #[case(vec![2], 1)]
     fn test_first_missing(#[case] input: Vec<usize>, #[case] expected: usize) {
-        let first = find_first_missing(&input);
+        let first = find_first_missing(&input, &[]);
         assert_eq!(first, expected);
     }
+
+    #[rstest]
+    #[case::hole_quarantined(vec![1, 2, 4], vec![3], 5)]
+    #[case::first_hole_quarantined(vec![1, 4], vec![2], 3)]
+    #[case::all_quarantined(vec![], vec![1, 2], 3)]
+    #[case::unsorted(vec![4, 1], vec![3, 2], 5)]
+    #[case::quarantined_and_used(vec![1, 2], vec![2], 3)]
+    #[case::quarantine_above_used(vec![1], vec![5], 2)]
+    fn test_first_missing_skips_quarantined(
+        #[case] used: Vec<usize>,
+        #[case] quarantined: Vec<usize>,
+        #[case] expected: usize,
+    ) {
+        assert_eq!(find_first_missing(&used, &quarantined), expected);
+    }
+
+    #[test]
+    fn test_active_quarantine() {
+        let now = Utc::now();
+        let quarantined = |id: usize, removed_seconds_ago: i64| QuarantinedId {
+            id,
+            removed_at: Time(now - k8s_openapi::chrono::Duration::seconds(removed_seconds_ago)),
+        };
+        let quarantined_ids = vec![
+            quarantined(1, 3601),
+            quarantined(2, 3599),
+            quarantined(3, 0),
+            // Clocks are not always in sync
+            quarantined(4, -10),
+        ];
+
+        let active = active_quarantine(&quarantined_ids, now, Duration::from_secs(3600))
+            .into_iter()
+            .map(|quarantined| quarantined.id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(active, vec![2, 3, 4]);
+    }
+
+    #[rstest]
+    #[case::healthy(vec![Some(ServerMode::Leader), Some(ServerMode::Follower), Some(ServerMode::Follower)], true)]
+    #[case::one_down(vec![Some(ServerMode::Leader), Some(ServerMode::Follower), None], true)]
+    #[case::majority_down(vec![Some(ServerMode::Leader), None, None], false)]
+    #[case::no_leader(vec![Some(ServerMode::Follower), Some(ServerMode::Follower), None], false)]
+    #[case::standalone(vec![Some(ServerMode::Standalone)], true)]
+    #[case::even(vec![Some(ServerMode::Leader), Some(ServerMode::Follower), None, None], false)]
+    fn test_has_quorum(#[case] modes: Vec<Option<ServerMode>>, #[case] expected: bool) {
+        let stats = modes
+            .into_iter()
+            .enumerate()
+            .map(|(ordinal, mode)| {
+                (
+                    format!("default-{}", ordinal),
+                    ServerStats {
+                        mode,
+                        ..ServerStats::default()
+                    },
+                )
+            })
+            .collect();
+
+        assert_eq!(has_quorum(&stats), expected);
+    }
+
+    #[test]
+    fn test_server_selector_matches_all_roles() {
+        let selector = build_server_selector("simple");
+
+        for role in ZookeeperRole::iter() {
+            let labels = build_pod_labels(&role.to_string(), "default", "simple", "3.5.8", "1");
+            assert!(selector
+                .iter()
+                .all(|(key, value)| labels.get(key) == Some(value)));
+        }
+        assert_eq!(
+            build_pod_name("simple", "remote", &ZookeeperRole::Observer, 0),
+            "zookeeper-simple-remote-observer-0"
+        );
+    }
+
+    #[test]
+    fn test_client_service_ports() {
+        let config = ZookeeperConfig::default().for_node_slot(1);
+
+        let ports = client_service_ports(&config, false);
+        assert_eq!(ports, vec![service_port(CLIENT_PORT_NAME, 2182)]);
+
+        // The plain client port must not be reachable through a Service with TLS
+        let ports = client_service_ports(&config, true);
+        assert_eq!(ports, vec![service_port(SECURE_CLIENT_PORT_NAME, 2282)]);
+        assert_eq!(
+            ports[0].target_port,
+            Some(IntOrString::String(SECURE_CLIENT_PORT_NAME.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_server_certificate_dns_names() {
+        let server = ServerStatus {
+            role_group: "default".to_string(),
+            ordinal: 0,
+            id: 3,
+            node_name: "node-1".to_string(),
+            node_slot: 1,
+            peer_type: PeerType::Participant,
+        };
+
+        assert_eq!(
+            server_certificate_dns_names("default-0", &server, "simple", "default"),
+            vec![
+                "default-0.simple-servers.default.svc",
+                "simple-server-3.default.svc",
+                "simple.default.svc",
+                "node-1",
+            ]
+        );
+    }
 }
@@ -0,0 +1,311 @@
+//! Decides which servers of a role group run on which nodes.
+//!
+//! The servers of a role group are identified by their ordinal (`0..instances`), which never
+//! changes, and are bound to a node. A role group asks for `instances` servers on the nodes
+//! matching its selector, with at most `instancesPerNode` servers on a single node. The servers
+//! on a node are told apart by their slot on that node (`0..instancesPerNode`), which is used to
+//! give them distinct ports and data directories.
+//!
+//! Everything in here is deterministic: the same nodes and bindings always lead to the same
+//! placement, no matter in which order the nodes are passed in.
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where a server runs: a node and its slot on that node.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ServerLocation {
+    pub node_name: String,
+    pub node_slot: u8,
+}
+
+impl ServerLocation {
+    pub fn new(node_name: &str, node_slot: u8) -> Self {
+        ServerLocation {
+            node_name: node_name.to_string(),
+            node_slot,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Placement {
+    /// Where every server of the role group runs, by ordinal
+    pub bindings: BTreeMap<u16, ServerLocation>,
+    /// Servers that could not be placed because the eligible nodes are full
+    pub unplaced: Vec<u16>,
+    /// The current locations of unplaced servers that no other server is bound to, these servers
+    /// stay where they are until there is room again
+    pub retained: BTreeMap<u16, ServerLocation>,
+    /// Servers that are not needed anymore because the role group was scaled down
+    pub removed: Vec<u16>,
+}
+
+/// Places the servers `0..instances` on the `eligible_nodes` with at most `instances_per_node`
+/// servers on a single node.
+///
+/// The slots of a node are shared by all role groups, `reserved` are the locations used by the
+/// servers of the other role groups. They are never given to a server of this role group and
+/// count towards `instances_per_node`, so servers of different role groups on the same node get
+/// different ports and directories.
+///
+/// `current_bindings` are kept as long as their node is still eligible and their slot still
+/// exists, all other servers are bound to the nodes running the fewest servers, ties are broken
+/// by the name of the node. Servers with an ordinal of `instances` or above are removed, so
+/// scaling down always removes the servers that were added last.
+/// Servers that don't fit anymore keep their current binding (see `retained`) unless it was
+/// given to another server, so no two servers ever share a location.
+pub fn place_servers(
+    instances: u16,
+    instances_per_node: u8,
+    eligible_nodes: &[String],
+    current_bindings: &BTreeMap<u16, ServerLocation>,
+    reserved: &BTreeSet<ServerLocation>,
+) -> Placement {
+    let eligible_nodes = eligible_nodes.iter().collect::<BTreeSet<_>>();
+    let mut placement = Placement {
+        removed: current_bindings
+            .keys()
+            .filter(|ordinal| **ordinal >= instances)
+            .copied()
+            .collect(),
+        ..Placement::default()
+    };
+
+    let mut taken = reserved.clone();
+    for ordinal in 0..instances {
+        if let Some(location) = current_bindings.get(&ordinal) {
+            let fits = eligible_nodes.contains(&location.node_name)
+                && location.node_slot < instances_per_node
+                && !taken.contains(location);
+            if fits {
+                taken.insert(location.clone());
+                placement.bindings.insert(ordinal, location.clone());
+            }
+        }
+    }
+
+    let mut servers_per_node = eligible_nodes
+        .iter()
+        .map(|node_name| (node_name.as_str(), 0u8))
+        .collect::<BTreeMap<_, _>>();
+    for location in &taken {
+        if let Some(count) = servers_per_node.get_mut(location.node_name.as_str()) {
+            *count += 1;
+        }
+    }
+
+    for ordinal in 0..instances {
+        if placement.bindings.contains_key(&ordinal) {
+            continue;
+        }
+
+        // `min_by_key` returns the first of several equal elements, which is the one with the
+        // lowest node name because the map is sorted
+        let node_name = match servers_per_node
+            .iter()
+            .filter(|(_, count)| **count < instances_per_node)
+            .min_by_key(|(_, count)| **count)
+        {
+            Some((node_name, _)) => node_name.to_string(),
+            None => {
+                placement.unplaced.push(ordinal);
+                continue;
+            }
+        };
+
+        let location = (0..instances_per_node)
+            .map(|node_slot| ServerLocation::new(&node_name, node_slot))
+            .find(|location| !taken.contains(location))
+            .expect("a node with less servers than slots must have a free slot");
+
+        if let Some(count) = servers_per_node.get_mut(node_name.as_str()) {
+            *count += 1;
+        }
+        taken.insert(location.clone());
+        placement.bindings.insert(ordinal, location);
+    }
+
+    for ordinal in &placement.unplaced {
+        if let Some(location) = current_bindings.get(ordinal) {
+            if taken.insert(location.clone()) {
+                placement.retained.insert(*ordinal, location.clone());
+            }
+        }
+    }
+
+    placement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn nodes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn bindings(bindings: &[(u16, &str, u8)]) -> BTreeMap<u16, ServerLocation> {
+        bindings
+            .iter()
+            .map(|(ordinal, node_name, node_slot)| {
+                (*ordinal, ServerLocation::new(node_name, *node_slot))
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case::fewer_than_nodes(1, 1, &["node-3", "node-1", "node-2"], &[(0, "node-1", 0)], &[])]
+    #[case::one_per_node(3, 1, &["node-3", "node-1", "node-2"], &[(0, "node-1", 0), (1, "node-2", 0), (2, "node-3", 0)], &[])]
+    #[case::not_enough_nodes(5, 1, &["node-1", "node-2"], &[(0, "node-1", 0), (1, "node-2", 0)], &[2, 3, 4])]
+    #[case::spread(5, 2, &["node-2", "node-1", "node-3"], &[(0, "node-1", 0), (1, "node-2", 0), (2, "node-3", 0), (3, "node-1", 1), (4, "node-2", 1)], &[])]
+    #[case::single_node(3, 3, &["node-1"], &[(0, "node-1", 0), (1, "node-1", 1), (2, "node-1", 2)], &[])]
+    #[case::no_nodes(3, 1, &[], &[], &[0, 1, 2])]
+    fn test_place_new_servers(
+        #[case] instances: u16,
+        #[case] instances_per_node: u8,
+        #[case] eligible_nodes: &[&str],
+        #[case] expected_bindings: &[(u16, &str, u8)],
+        #[case] expected_unplaced: &[u16],
+    ) {
+        let placement = place_servers(
+            instances,
+            instances_per_node,
+            &nodes(eligible_nodes),
+            &BTreeMap::new(),
+            &BTreeSet::new(),
+        );
+
+        assert_eq!(placement.bindings, bindings(expected_bindings));
+        assert_eq!(placement.unplaced, expected_unplaced);
+        assert!(placement.retained.is_empty());
+        assert!(placement.removed.is_empty());
+    }
+
+    #[test]
+    fn test_keep_current_bindings() {
+        let current = bindings(&[(0, "node-2", 0), (1, "node-1", 0)]);
+
+        let placement = place_servers(
+            3,
+            1,
+            &nodes(&["node-1", "node-2", "node-3"]),
+            &current,
+            &BTreeSet::new(),
+        );
+
+        assert_eq!(
+            placement.bindings,
+            bindings(&[(0, "node-2", 0), (1, "node-1", 0), (2, "node-3", 0)])
+        );
+    }
+
+    #[test]
+    fn test_scale_down_removes_highest_ordinals() {
+        let current = bindings(&[
+            (0, "node-1", 0),
+            (1, "node-2", 0),
+            (2, "node-3", 0),
+            (3, "node-1", 1),
+            (4, "node-2", 1),
+        ]);
+
+        let placement = place_servers(
+            3,
+            2,
+            &nodes(&["node-1", "node-2", "node-3"]),
+            &current,
+            &BTreeSet::new(),
+        );
+
+        assert_eq!(
+            placement.bindings,
+            bindings(&[(0, "node-1", 0), (1, "node-2", 0), (2, "node-3", 0)])
+        );
+        assert_eq!(placement.removed, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_rebind_servers_that_do_not_fit() {
+        let current = bindings(&[
+            // The node is not eligible anymore
+            (0, "node-4", 0),
+            // The slot does not exist anymore
+            (1, "node-1", 1),
+            (2, "node-1", 0),
+        ]);
+
+        let placement = place_servers(
+            3,
+            1,
+            &nodes(&["node-1", "node-2", "node-3"]),
+            &current,
+            &BTreeSet::new(),
+        );
+
+        assert_eq!(
+            placement.bindings,
+            bindings(&[(0, "node-2", 0), (1, "node-3", 0), (2, "node-1", 0)])
+        );
+        assert!(placement.unplaced.is_empty());
+        assert!(placement.removed.is_empty());
+    }
+
+    #[test]
+    fn test_retain_unplaced_servers() {
+        let current = bindings(&[
+            (0, "node-1", 0),
+            // Both claim the same location, only the first one keeps it
+            (1, "node-1", 0),
+            // The node is not eligible anymore and there is no room elsewhere
+            (2, "node-2", 0),
+        ]);
+
+        let placement = place_servers(3, 1, &nodes(&["node-1"]), &current, &BTreeSet::new());
+
+        assert_eq!(placement.bindings, bindings(&[(0, "node-1", 0)]));
+        assert_eq!(placement.unplaced, vec![1, 2]);
+        assert_eq!(placement.retained, bindings(&[(2, "node-2", 0)]));
+    }
+
+    #[test]
+    fn test_share_nodes_with_other_role_groups() {
+        let nodes = nodes(&["node-1", "node-2"]);
+        let servers = place_servers(3, 2, &nodes, &BTreeMap::new(), &BTreeSet::new());
+        assert_eq!(
+            servers.bindings,
+            bindings(&[(0, "node-1", 0), (1, "node-2", 0), (2, "node-1", 1)])
+        );
+
+        // A second role group on the same nodes only gets the slots left over
+        let reserved = servers.bindings.values().cloned().collect();
+        let observers = place_servers(2, 2, &nodes, &BTreeMap::new(), &reserved);
+
+        assert_eq!(observers.bindings, bindings(&[(0, "node-2", 1)]));
+        assert_eq!(observers.unplaced, vec![1]);
+
+        // Current bindings on reserved locations are moved
+        let current = bindings(&[(0, "node-1", 0)]);
+        let observers = place_servers(1, 2, &nodes, &current, &reserved);
+
+        assert_eq!(observers.bindings, bindings(&[(0, "node-2", 1)]));
+        assert!(observers.retained.is_empty());
+    }
+
+    #[test]
+    fn test_fill_free_slots() {
+        let current = bindings(&[(0, "node-1", 1)]);
+
+        let placement = place_servers(
+            3,
+            2,
+            &nodes(&["node-1", "node-2"]),
+            &current,
+            &BTreeSet::new(),
+        );
+
+        assert_eq!(
+            placement.bindings,
+            bindings(&[(0, "node-1", 1), (1, "node-2", 0), (2, "node-1", 0)])
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Everything needed to change the members of a running ensemble with ZooKeeper's dynamic
+//! reconfiguration (available since 3.5).
+use crate::config::ServerEntry;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha1::Sha1;
+use stackable_zookeeper_crd::{PeerType, ZookeeperVersion};
+use std::collections::BTreeMap;
+
+/// The user the operator authenticates as, reconfigurations are only allowed for the super user.
+pub const SUPER_USER: &str = "super";
+/// Keys in the Secret holding the credentials of the super user
+pub const SUPER_PASSWORD_KEY: &str = "password";
+pub const SUPER_DIGEST_KEY: &str = "superDigest";
+
+/// How changes to the members of an ensemble are rolled out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MembershipStrategy {
+    /// Every server gets the full list of members in its `zoo.cfg` and is restarted when it
+    /// changes.
+    RollingRestart,
+    /// The members are kept in a separate dynamic configuration file and changed at runtime
+    /// using `reconfig`, no restarts required.
+    DynamicReconfig,
+}
+
+impl MembershipStrategy {
+    pub fn for_version(version: &ZookeeperVersion) -> Self {
+        if version.supports_dynamic_reconfig() {
+            MembershipStrategy::DynamicReconfig
+        } else {
+            MembershipStrategy::RollingRestart
+        }
+    }
+}
+
+/// The changes needed to get from the current members of an ensemble to the desired ones.
+#[derive(Debug, Default, PartialEq)]
+pub struct MembershipChange {
+    /// Servers that are new or changed, as `server.N=...` entries
+    pub joining: Vec<String>,
+    /// Ids of servers that need to be removed
+    pub leaving: Vec<usize>,
+}
+
+impl MembershipChange {
+    pub fn is_empty(&self) -> bool {
+        self.joining.is_empty() && self.leaving.is_empty()
+    }
+}
+
+/// Parses the `server.N` entries of a dynamic configuration as returned by ZooKeeper, e.g.
+/// `server.1=host:2888:3888:participant;0.0.0.0:2181`.
+/// Entries that can't be parsed are returned as `None` so they will be replaced.
+pub fn parse_dynamic_config(config: &str) -> BTreeMap<usize, Option<ServerEntry>> {
+    config
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let id = key.strip_prefix("server.")?.parse::<usize>().ok()?;
+            Some((id, parse_server_entry(value)))
+        })
+        .collect()
+}
+
+fn parse_server_entry(value: &str) -> Option<ServerEntry> {
+    let (server, client) = value.split_once(';')?;
+    let mut parts = server.split(':');
+    let host = parts.next()?;
+    let quorum_port = parts.next()?.parse().ok()?;
+    let leader_election_port = parts.next()?.parse().ok()?;
+    let peer_type = match parts.next() {
+        None | Some("participant") => PeerType::Participant,
+        Some("observer") => PeerType::Observer,
+        Some(_) => return None,
+    };
+    // The client address is optional and defaults to the wildcard address
+    let client_port = client.rsplit(':').next()?.parse().ok()?;
+
+    Some(ServerEntry {
+        host: host.to_string(),
+        quorum_port,
+        leader_election_port,
+        client_port,
+        peer_type,
+    })
+}
+
+/// Compares the members ZooKeeper currently knows about with the ones we want.
+pub fn membership_change(
+    current: &BTreeMap<usize, Option<ServerEntry>>,
+    desired: &BTreeMap<usize, ServerEntry>,
+) -> MembershipChange {
+    let joining = desired
+        .iter()
+        .filter(|(id, server)| current.get(*id).map(Option::as_ref) != Some(Some(*server)))
+        .map(|(id, server)| format!("server.{}={}", id, server.dynamic_entry()))
+        .collect();
+
+    let leaving = current
+        .keys()
+        .filter(|id| !desired.contains_key(*id))
+        .copied()
+        .collect();
+
+    MembershipChange { joining, leaving }
+}
+
+pub fn generate_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Builds the value for `zookeeper.DigestAuthenticationProvider.superDigest` which is
+/// `super:base64(sha1(super:password))`.
+pub fn super_digest(password: &str) -> String {
+    let hash = Sha1::from(format!("{}:{}", SUPER_USER, password)).digest();
+    format!("{}:{}", SUPER_USER, base64::encode(hash.bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn server(host: &str, client_port: u16) -> ServerEntry {
+        ServerEntry {
+            host: host.to_string(),
+            quorum_port: 2888,
+            leader_election_port: 3888,
+            client_port,
+            peer_type: PeerType::Participant,
+        }
+    }
+
+    fn observer(host: &str, client_port: u16) -> ServerEntry {
+        ServerEntry {
+            peer_type: PeerType::Observer,
+            ..server(host, client_port)
+        }
+    }
+
+    #[test]
+    fn test_strategy_for_version() {
+        assert_eq!(
+            MembershipStrategy::for_version(&ZookeeperVersion::from_str("3.4.14").unwrap()),
+            MembershipStrategy::RollingRestart
+        );
+        assert_eq!(
+            MembershipStrategy::for_version(&ZookeeperVersion::from_str("3.5.8").unwrap()),
+            MembershipStrategy::DynamicReconfig
+        );
+    }
+
+    #[test]
+    fn test_parse_dynamic_config() {
+        let parsed = parse_dynamic_config(
+            "server.1=node-1:2888:3888:participant;0.0.0.0:2181\n\
+             server.2=node-2:2888:3888:participant;2182\n\
+             server.3=garbage\n\
+             server.4=node-4:2888:3888:observer;2181\n\
+             version=100000002",
+        );
+
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed.get(&1), Some(&Some(server("node-1", 2181))));
+        assert_eq!(parsed.get(&2), Some(&Some(server("node-2", 2182))));
+        assert_eq!(parsed.get(&3), Some(&None));
+        assert_eq!(parsed.get(&4), Some(&Some(observer("node-4", 2181))));
+    }
+
+    #[test]
+    fn test_membership_change() {
+        let current = parse_dynamic_config(
+            "server.1=node-1:2888:3888:participant;0.0.0.0:2181\n\
+             server.2=node-2:2888:3888:participant;0.0.0.0:2181\n\
+             server.3=node-3:2888:3888:participant;0.0.0.0:2181",
+        );
+
+        let mut desired = BTreeMap::new();
+        desired.insert(1, server("node-1", 2181));
+        desired.insert(2, server("node-2", 2182));
+        desired.insert(4, server("node-4", 2181));
+
+        let change = membership_change(&current, &desired);
+        assert_eq!(
+            change,
+            MembershipChange {
+                joining: vec![
+                    "server.2=node-2:2888:3888:participant;2182".to_string(),
+                    "server.4=node-4:2888:3888:participant;2181".to_string()
+                ],
+                leaving: vec![3],
+            }
+        );
+
+        desired.remove(&4);
+        desired.insert(2, server("node-2", 2181));
+        desired.insert(3, server("node-3", 2181));
+        assert!(membership_change(&current, &desired).is_empty());
+
+        // Turning a participant into an observer is a change of its entry
+        desired.insert(3, observer("node-3", 2181));
+        assert_eq!(
+            membership_change(&current, &desired).joining,
+            vec!["server.3=node-3:2888:3888:observer;2181".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_super_digest() {
+        assert_eq!(super_digest("secret"), "super:lK75jTNcA+U9vtVEw5vB51mj/w4=");
+        assert_eq!(generate_password().len(), 32);
+    }
+}
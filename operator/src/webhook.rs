@@ -0,0 +1,879 @@
+//! Admission webhooks for `ZookeeperCluster` objects.
+//!
+//! [`mutate`] fills in defaults before an object is persisted so the effective spec is visible to
+//! users, [`validate`] rejects objects that would not work.
+//! This only deals with `AdmissionReview` objects, serving them via HTTPS is done by the server
+//! binary. See `deploy/webhook` for the matching webhook configuration.
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use stackable_zookeeper_crd::error::Error as CrdError;
+use stackable_zookeeper_crd::util::headless_service_name;
+use stackable_zookeeper_crd::{
+    QuorumSaslStage, QuorumTlsStage, ServerStatus, ZookeeperCluster, ZookeeperClusterSpec,
+    DEFAULT_ID_QUARANTINE_SECONDS,
+};
+use std::collections::BTreeMap;
+
+pub const ADMISSION_API_VERSION: &str = "admission.k8s.io/v1";
+pub const ADMISSION_REVIEW_KIND: &str = "AdmissionReview";
+const JSON_PATCH: &str = "JSONPatch";
+
+/// The cluster name and the role group names are used as label values and end up in DNS labels
+/// (the name of the headless Service and the host names of the servers), both are limited to
+/// this length
+const MAX_DNS_LABEL_LENGTH: usize = 63;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionReview {
+    pub api_version: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<AdmissionRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<AdmissionResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionRequest {
+    pub uid: String,
+    /// One of `CREATE`, `UPDATE`, `DELETE` or `CONNECT`
+    pub operation: String,
+    #[serde(default)]
+    pub object: Option<Value>,
+    #[serde(default)]
+    pub old_object: Option<Value>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionResponse {
+    pub uid: String,
+    pub allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<AdmissionStatus>,
+    /// Base64 encoded JSON patch to apply to the object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct AdmissionStatus {
+    pub code: u16,
+    pub message: String,
+}
+
+impl AdmissionReview {
+    fn with_response(response: AdmissionResponse) -> Self {
+        AdmissionReview {
+            api_version: ADMISSION_API_VERSION.to_string(),
+            kind: ADMISSION_REVIEW_KIND.to_string(),
+            request: None,
+            response: Some(response),
+        }
+    }
+}
+
+impl AdmissionResponse {
+    fn allow(uid: &str) -> Self {
+        AdmissionResponse {
+            uid: uid.to_string(),
+            allowed: true,
+            ..AdmissionResponse::default()
+        }
+    }
+
+    fn deny(uid: &str, errors: &[String]) -> Self {
+        AdmissionResponse {
+            uid: uid.to_string(),
+            allowed: false,
+            status: Some(AdmissionStatus {
+                code: 403,
+                message: errors.join(", "),
+            }),
+            ..AdmissionResponse::default()
+        }
+    }
+
+    fn patch(uid: &str, patch: &[Value]) -> Self {
+        if patch.is_empty() {
+            return AdmissionResponse::allow(uid);
+        }
+
+        AdmissionResponse {
+            patch: Some(base64::encode(Value::from(patch).to_string())),
+            patch_type: Some(JSON_PATCH.to_string()),
+            ..AdmissionResponse::allow(uid)
+        }
+    }
+}
+
+/// Answers an `AdmissionReview` for a `ZookeeperCluster` with a JSON patch that sets all
+/// defaults which are not set yet. Values that are set are never changed.
+pub fn mutate(review: AdmissionReview) -> AdmissionReview {
+    let request = match review.request {
+        Some(request) => request,
+        None => {
+            return AdmissionReview::with_response(AdmissionResponse::deny(
+                "",
+                &["AdmissionReview does not contain a request".to_string()],
+            ))
+        }
+    };
+
+    let response = match default_request(&request) {
+        Ok(patch) => AdmissionResponse::patch(&request.uid, &patch),
+        Err(errors) => AdmissionResponse::deny(&request.uid, &errors),
+    };
+    AdmissionReview::with_response(response)
+}
+
+fn default_request(request: &AdmissionRequest) -> Result<Vec<Value>, Vec<String>> {
+    let object = match (request.operation.as_str(), &request.object) {
+        ("CREATE", Some(object)) | ("UPDATE", Some(object)) => object,
+        _ => return Ok(vec![]),
+    };
+
+    let mut cluster = parse_cluster(object)?;
+    apply_defaults(&mut cluster.spec);
+    let spec = serde_json::to_value(&cluster.spec)
+        .map_err(|err| vec![format!("Invalid ZookeeperCluster: {}", err)])?;
+
+    let mut patch = vec![];
+    add_missing_values("/spec", object.get("spec"), &spec, &mut patch);
+    Ok(patch)
+}
+
+/// Sets every value of the spec that has a default. Defaults of the role groups (`instances`
+/// and `instancesPerNode`) are already set when deserializing.
+///
+/// Only the cluster wide configuration is defaulted, the configuration of the role groups
+/// overrides it and must only contain what the user set.
+pub fn apply_defaults(spec: &mut ZookeeperClusterSpec) {
+    spec.downgrade_policy = Some(spec.downgrade_policy.unwrap_or_default());
+    spec.id_quarantine_seconds = Some(
+        spec.id_quarantine_seconds
+            .unwrap_or(DEFAULT_ID_QUARANTINE_SECONDS),
+    );
+    spec.per_server_services = Some(spec.per_server_services.unwrap_or_default());
+    spec.config = Some(spec.config.clone().unwrap_or_default().with_defaults());
+    if let Some(tls) = &mut spec.tls {
+        tls.format = Some(tls.format());
+        tls.client_auth = Some(tls.client_auth());
+        tls.quorum = Some(tls.quorum());
+    }
+    if let Some(authentication) = &mut spec.authentication {
+        authentication.require_client_auth = Some(authentication.require_client_auth());
+        authentication.quorum = Some(authentication.quorum());
+    }
+    for role_group in spec.servers.selectors.values_mut().chain(
+        spec.observers
+            .iter_mut()
+            .flat_map(|observers| observers.selectors.values_mut()),
+    ) {
+        role_group.selector = Some(role_group.selector());
+    }
+}
+
+/// Adds an `add` operation to `patch` for every value in `new` which is missing (or `null`) in
+/// `old`.
+fn add_missing_values(path: &str, old: Option<&Value>, new: &Value, patch: &mut Vec<Value>) {
+    match (old, new) {
+        (_, Value::Null) => {}
+        (Some(Value::Object(old)), Value::Object(new)) => {
+            for (key, value) in new {
+                add_missing_values(
+                    &format!("{}/{}", path, escape_json_pointer(key)),
+                    old.get(key),
+                    value,
+                    patch,
+                );
+            }
+        }
+        (None, _) | (Some(Value::Null), _) => patch.push(json!({
+            "op": "add",
+            "path": path,
+            "value": without_nulls(new),
+        })),
+        _ => {}
+    }
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), without_nulls(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+/// Escapes a key to be used as a segment of a JSON pointer (RFC 6901).
+fn escape_json_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Answers an `AdmissionReview` for a `ZookeeperCluster`: Objects that would not work are denied
+/// with a message listing all problems.
+pub fn validate(review: AdmissionReview) -> AdmissionReview {
+    let request = match review.request {
+        Some(request) => request,
+        None => {
+            return AdmissionReview::with_response(AdmissionResponse::deny(
+                "",
+                &["AdmissionReview does not contain a request".to_string()],
+            ))
+        }
+    };
+
+    let response = match validate_request(&request) {
+        Ok(()) => AdmissionResponse::allow(&request.uid),
+        Err(errors) => AdmissionResponse::deny(&request.uid, &errors),
+    };
+    AdmissionReview::with_response(response)
+}
+
+fn validate_request(request: &AdmissionRequest) -> Result<(), Vec<String>> {
+    let object = match (request.operation.as_str(), &request.object) {
+        ("CREATE", Some(object)) | ("UPDATE", Some(object)) => object,
+        // Deleting is always fine
+        _ => return Ok(()),
+    };
+
+    let cluster = parse_cluster(object)?;
+    let old_cluster = request.old_object.as_ref().map(parse_cluster).transpose()?;
+
+    let errors = validate_cluster(&cluster, old_cluster.as_ref());
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_cluster(object: &Value) -> Result<ZookeeperCluster, Vec<String>> {
+    serde_json::from_value(object.clone())
+        .map_err(|err| vec![format!("Invalid ZookeeperCluster: {}", err)])
+}
+
+/// Returns everything that is wrong with the cluster. `old_cluster` is the currently persisted
+/// object when the cluster is updated.
+pub fn validate_cluster(
+    cluster: &ZookeeperCluster,
+    old_cluster: Option<&ZookeeperCluster>,
+) -> Vec<String> {
+    let mut errors = vec![];
+    validate_names(cluster, &mut errors);
+    validate_version(cluster, old_cluster, &mut errors);
+    validate_servers(&cluster.spec, &mut errors);
+    validate_config(&cluster.spec, &mut errors);
+    validate_tls(&cluster.spec, &mut errors);
+    validate_quorum_tls(cluster, old_cluster, &mut errors);
+    validate_authentication(&cluster.spec, &mut errors);
+    validate_quorum_sasl(cluster, old_cluster, &mut errors);
+    errors
+}
+
+fn validate_names(cluster: &ZookeeperCluster, errors: &mut Vec<String>) {
+    // Objects created with `generateName` get a random suffix of five characters
+    let name = match (&cluster.metadata.name, &cluster.metadata.generate_name) {
+        (Some(name), _) => name.clone(),
+        (None, Some(generate_name)) => format!("{}xxxxx", generate_name),
+        (None, None) => return,
+    };
+
+    let mut longest_service_name_length = headless_service_name(&name).len();
+    if cluster.spec.per_server_services.unwrap_or_default() {
+        longest_service_name_length = longest_service_name_length
+            .max(crate::server_service_name(&name, u16::MAX.into()).len());
+    }
+    let max_name_length = MAX_DNS_LABEL_LENGTH + name.len() - longest_service_name_length;
+    if name.len() > max_name_length {
+        errors.push(format!(
+            "The name [{}] must not be longer than [{}] characters",
+            name, max_name_length
+        ));
+    }
+
+    for (role_group, _, _) in cluster.spec.role_groups() {
+        let longest_host_name = ServerStatus::server_name(role_group, u16::MAX);
+        let max_role_group_length =
+            MAX_DNS_LABEL_LENGTH + role_group.len() - longest_host_name.len();
+        if role_group.len() > max_role_group_length {
+            errors.push(format!(
+                "The role group name [{}] must not be longer than [{}] characters",
+                role_group, max_role_group_length
+            ));
+        }
+    }
+}
+
+fn validate_version(
+    cluster: &ZookeeperCluster,
+    old_cluster: Option<&ZookeeperCluster>,
+    errors: &mut Vec<String>,
+) {
+    let version = &cluster.spec.version;
+    // Only changes are checked, other updates of a cluster with a blocked version are fine
+    if let Some(old_cluster) = old_cluster {
+        if &old_cluster.spec.version == version {
+            return;
+        }
+    }
+
+    if let Err(err) = version.traits() {
+        errors.push(err.to_string());
+        return;
+    }
+
+    if let Some(old_cluster) = old_cluster {
+        let running_version = old_cluster
+            .status
+            .as_ref()
+            .and_then(|status| status.current_version.as_ref())
+            .unwrap_or(&old_cluster.spec.version);
+        if let Err(err) = running_version
+            .check_version_change(version, cluster.spec.downgrade_policy.unwrap_or_default())
+        {
+            errors.push(err.to_string());
+        }
+    }
+}
+
+fn validate_servers(spec: &ZookeeperClusterSpec, errors: &mut Vec<String>) {
+    let selectors = &spec.servers.selectors;
+    if selectors.is_empty() {
+        errors.push("At least one role group is required for servers".to_string());
+        return;
+    }
+
+    if let Some(observers) = &spec.observers {
+        let mut duplicates = observers
+            .selectors
+            .keys()
+            .filter(|role_group| selectors.contains_key(*role_group))
+            .collect::<Vec<_>>();
+        duplicates.sort();
+        for role_group in duplicates {
+            errors.push(format!(
+                "The role group name [{}] is used for servers and observers",
+                role_group
+            ));
+        }
+    }
+
+    for (role_group, selector, _) in spec.role_groups() {
+        if selector.instances_per_node == 0 {
+            errors.push(format!(
+                "The role group [{}] must allow at least one instance per node",
+                role_group
+            ));
+        }
+    }
+
+    // Observers don't vote, so only the servers count for the size of the quorum
+    let instances = selectors
+        .values()
+        .map(|selector| u32::from(selector.instances))
+        .sum::<u32>();
+    if instances == 0 {
+        errors.push("At least one server instance is required".to_string());
+    } else if instances % 2 == 0 {
+        errors.push(format!(
+            "The number of server instances [{}] must be odd, an ensemble of [{}] servers can't tolerate more failures than one of [{}]",
+            instances,
+            instances,
+            instances - 1
+        ));
+    }
+}
+
+fn validate_config(spec: &ZookeeperClusterSpec, errors: &mut Vec<String>) {
+    // Servers of different role groups (and in different slots of a node) might run on the
+    // same node, so a port must always be used for the same purpose
+    let mut ports: BTreeMap<u16, Vec<PortUse>> = BTreeMap::new();
+    for (role_group, selector, _) in spec.role_groups() {
+        let config = spec.merged_config(role_group);
+        let instances_per_node = selector.instances_per_node;
+        let label_selector = selector.selector();
+        match config.validate() {
+            Ok(()) => {}
+            Err(CrdError::InvalidConfig {
+                errors: config_errors,
+            }) => {
+                errors.extend(
+                    config_errors
+                        .into_iter()
+                        .map(|error| format!("Role group [{}]: {}", role_group, error)),
+                );
+                continue;
+            }
+            Err(err) => {
+                errors.push(format!("Role group [{}]: {}", role_group, err));
+                continue;
+            }
+        }
+
+        for node_slot in 0..instances_per_node {
+            let config = config.for_node_slot(node_slot);
+            let mut slot_ports = vec![
+                ("client port", config.client_port()),
+                ("quorum port", config.quorum_port()),
+                ("leader election port", config.leader_election_port()),
+            ];
+            if spec.tls.is_some() {
+                slot_ports.push(("secure client port", config.secure_client_port()));
+            }
+            for (purpose, port) in slot_ports {
+                let uses = ports.entry(port).or_default();
+                // The slots of a node are shared by all role groups, so the same port in the
+                // same slot of two role groups never ends up on one node, but in different
+                // slots it does if the selectors of the role groups can match the same node
+                let shared_node = uses.iter().find(|other| {
+                    other.role_group != role_group.as_str()
+                        && other.node_slot != node_slot
+                        && selectors_may_overlap(&other.selector, &label_selector)
+                });
+                match (uses.first(), shared_node) {
+                    (Some(other), _) if other.purpose != purpose => errors.push(format!(
+                        "Port [{}] is used as {} in role group [{}] and as {} in role group [{}]",
+                        port, other.purpose, other.role_group, purpose, role_group
+                    )),
+                    (_, Some(other)) => errors.push(format!(
+                        "Port [{}] is used as {} in slot [{}] of role group [{}] and in slot [{}] of role group [{}], which may run on the same node",
+                        port, purpose, other.node_slot, other.role_group, node_slot, role_group
+                    )),
+                    _ => {}
+                }
+                uses.push(PortUse {
+                    purpose,
+                    role_group: role_group.as_str(),
+                    node_slot,
+                    selector: label_selector.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A port used by the servers in a slot of a role group, see `validate_config`
+struct PortUse<'a> {
+    purpose: &'static str,
+    role_group: &'a str,
+    node_slot: u8,
+    selector: LabelSelector,
+}
+
+/// Whether a node might match both selectors. Only `matchLabels` of one selector which
+/// contradict the other selector are detected, everything else is assumed to overlap.
+fn selectors_may_overlap(a: &LabelSelector, b: &LabelSelector) -> bool {
+    !contradicts(a, b) && !contradicts(b, a)
+}
+
+/// Whether no node with the labels required by `matchLabels` of `a` can match `b`
+fn contradicts(a: &LabelSelector, b: &LabelSelector) -> bool {
+    a.match_labels.iter().flatten().any(|(key, value)| {
+        let contradicts_labels = b
+            .match_labels
+            .as_ref()
+            .and_then(|labels| labels.get(key))
+            .map_or(false, |other| other != value);
+        let contradicts_expressions = b
+            .match_expressions
+            .iter()
+            .flatten()
+            .filter(|expression| &expression.key == key)
+            .any(|expression| {
+                let values = expression.values.as_deref().unwrap_or_default();
+                match expression.operator.as_str() {
+                    "In" => !values.contains(value),
+                    "NotIn" => values.contains(value),
+                    "DoesNotExist" => true,
+                    _ => false,
+                }
+            });
+        contradicts_labels || contradicts_expressions
+    })
+}
+
+fn validate_tls(spec: &ZookeeperClusterSpec, errors: &mut Vec<String>) {
+    let tls = match &spec.tls {
+        Some(tls) => tls,
+        None => return,
+    };
+
+    match &tls.secret_name {
+        Some(secret_name) if secret_name.is_empty() => {
+            errors.push("The secretName of tls must not be empty".to_string());
+        }
+        Some(_) if tls.ca_secret_name.is_some() || tls.certificate_validity_days.is_some() => {
+            errors.push(
+                "caSecretName and certificateValidityDays of tls can only be used without secretName"
+                    .to_string(),
+            );
+        }
+        _ => {}
+    }
+    if tls.certificate_validity_days == Some(0) {
+        errors.push("The certificateValidityDays of tls must be at least 1".to_string());
+    }
+    // Unsupported versions are already reported by `validate_version`
+    if spec.version.traits().is_ok() && !spec.version.supports_tls() {
+        errors.push(format!(
+            "Version [{}] does not support TLS, at least 3.5 is required",
+            spec.version
+        ));
+    }
+}
+
+fn validate_authentication(spec: &ZookeeperClusterSpec, errors: &mut Vec<String>) {
+    let authentication = match &spec.authentication {
+        Some(authentication) => authentication,
+        None => return,
+    };
+
+    if !authentication.authenticates_clients() {
+        if authentication.quorum_secret_name.is_none() {
+            errors.push("authentication requires digest, kerberos or quorumSecretName".to_string());
+        }
+        if authentication.require_client_auth() {
+            errors.push(
+                "requireClientAuth of authentication requires digest or kerberos".to_string(),
+            );
+        }
+    }
+    match &authentication.quorum_secret_name {
+        Some(secret_name) if secret_name.is_empty() => {
+            errors.push("The quorumSecretName of authentication must not be empty".to_string());
+        }
+        None if authentication.quorum() => {
+            errors.push("quorum of authentication requires quorumSecretName".to_string());
+        }
+        _ => {}
+    }
+    if let Some(digest) = &authentication.digest {
+        if digest.secret_name.is_empty() {
+            errors.push("The secretName of authentication.digest must not be empty".to_string());
+        }
+    }
+    if let Some(kerberos) = &authentication.kerberos {
+        if kerberos.keytab_secret_name.is_empty() {
+            errors.push(
+                "The keytabSecretName of authentication.kerberos must not be empty".to_string(),
+            );
+        }
+        if kerberos.principal.is_empty() {
+            errors.push("The principal of authentication.kerberos must not be empty".to_string());
+        }
+    }
+}
+
+/// `tls` can only be removed once quorum connections are back to plaintext, otherwise the
+/// restarted servers could not talk to the ones still using TLS.
+fn validate_quorum_tls(
+    cluster: &ZookeeperCluster,
+    old_cluster: Option<&ZookeeperCluster>,
+    errors: &mut Vec<String>,
+) {
+    if cluster.spec.tls.is_some() {
+        return;
+    }
+
+    let stage = old_cluster
+        .and_then(|old_cluster| old_cluster.status.as_ref())
+        .and_then(|status| status.quorum_tls_stage)
+        .unwrap_or_default();
+    if stage != QuorumTlsStage::Plaintext {
+        errors.push(format!(
+            "tls can't be removed while quorum connections use TLS (stage [{:?}]), set tls.quorum to false first and wait for the stage to become [Plaintext]",
+            stage
+        ));
+    }
+}
+
+/// The quorum credentials can only be removed once the servers don't authenticate each other
+/// anymore, otherwise the restarted servers could not join the others.
+fn validate_quorum_sasl(
+    cluster: &ZookeeperCluster,
+    old_cluster: Option<&ZookeeperCluster>,
+    errors: &mut Vec<String>,
+) {
+    let has_credentials = cluster
+        .spec
+        .authentication
+        .as_ref()
+        .map_or(false, |authentication| {
+            authentication.quorum_secret_name.is_some()
+        });
+    if has_credentials {
+        return;
+    }
+
+    let stage = old_cluster
+        .and_then(|old_cluster| old_cluster.status.as_ref())
+        .and_then(|status| status.quorum_sasl_stage)
+        .unwrap_or_default();
+    if stage != QuorumSaslStage::Disabled {
+        errors.push(format!(
+            "authentication.quorumSecretName can't be removed while the servers authenticate each other (stage [{:?}]), set authentication.quorum to false first and wait for the stage to become [Disabled]",
+            stage
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn review(fixture: &str) -> AdmissionReview {
+        serde_json::from_str(fixture).unwrap()
+    }
+
+    #[rstest]
+    #[case::valid(include_str!("../fixtures/admission/valid-create.json"), &[])]
+    #[case::valid_update(include_str!("../fixtures/admission/valid-upgrade.json"), &[])]
+    #[case::delete(include_str!("../fixtures/admission/delete.json"), &[])]
+    #[case::name_too_long(
+        include_str!("../fixtures/admission/name-too-long.json"),
+        &[
+            "The name [aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa] must not be longer than [55] characters",
+            "The role group name [bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb] must not be longer than [57] characters"
+        ]
+    )]
+    #[case::per_server_services_name_too_long(
+        include_str!("../fixtures/admission/per-server-services-name-too-long.json"),
+        &["The name [aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa] must not be longer than [50] characters"]
+    )]
+    #[case::downgrade(
+        include_str!("../fixtures/admission/downgrade.json"),
+        &["Downgrade from [3.5.8] to [3.4.14] is not allowed"]
+    )]
+    #[case::unsupported_version(
+        include_str!("../fixtures/admission/unsupported-version.json"),
+        &["Version [3.2.0] is not supported"]
+    )]
+    #[case::even_instances(
+        include_str!("../fixtures/admission/even-instances.json"),
+        &["The number of server instances [2] must be odd"]
+    )]
+    #[case::zero_instances(
+        include_str!("../fixtures/admission/zero-instances.json"),
+        &["At least one server instance is required"]
+    )]
+    #[case::defaults(include_str!("../fixtures/admission/defaults.json"), &[])]
+    #[case::observers(include_str!("../fixtures/admission/observers.json"), &[])]
+    #[case::tls(include_str!("../fixtures/admission/tls.json"), &[])]
+    #[case::tls_unsupported_version(
+        include_str!("../fixtures/admission/tls-unsupported-version.json"),
+        &[
+            "Version [3.4.14] does not support TLS, at least 3.5 is required",
+            "Port [2281] is used as secure client port in role group [default] and as client port in role group [secondary]"
+        ]
+    )]
+    #[case::tls_managed(include_str!("../fixtures/admission/tls-managed.json"), &[])]
+    #[case::tls_managed_invalid(
+        include_str!("../fixtures/admission/tls-managed-invalid.json"),
+        &[
+            "caSecretName and certificateValidityDays of tls can only be used without secretName",
+            "The certificateValidityDays of tls must be at least 1"
+        ]
+    )]
+    #[case::authentication(include_str!("../fixtures/admission/authentication.json"), &[])]
+    #[case::authentication_invalid(
+        include_str!("../fixtures/admission/authentication-invalid.json"),
+        &[
+            "The secretName of authentication.digest must not be empty",
+            "The principal of authentication.kerberos must not be empty"
+        ]
+    )]
+    #[case::authentication_without_mechanism(
+        include_str!("../fixtures/admission/authentication-without-mechanism.json"),
+        &[
+            "authentication requires digest, kerberos or quorumSecretName",
+            "requireClientAuth of authentication requires digest or kerberos",
+            "quorum of authentication requires quorumSecretName"
+        ]
+    )]
+    #[case::quorum_sasl(include_str!("../fixtures/admission/quorum-sasl.json"), &[])]
+    #[case::quorum_sasl_credentials_removed(
+        include_str!("../fixtures/admission/quorum-sasl-credentials-removed.json"),
+        &["authentication.quorumSecretName can't be removed while the servers authenticate each other (stage [LearnerRequired])"]
+    )]
+    #[case::tls_removed_with_quorum_tls(
+        include_str!("../fixtures/admission/tls-removed-with-quorum-tls.json"),
+        &["tls can't be removed while quorum connections use TLS (stage [TlsWithPortUnification])"]
+    )]
+    #[case::observer_role_group_collision(
+        include_str!("../fixtures/admission/observer-role-group-collision.json"),
+        &[
+            "The role group name [default] is used for servers and observers",
+            "Port [2181] is used as client port in role group [default] and as quorum port in role group [remote]"
+        ]
+    )]
+    #[case::port_collision(
+        include_str!("../fixtures/admission/port-collision.json"),
+        &[
+            "Port [2888] is used as quorum port in role group [primary] and as client port in role group [secondary]",
+            "Role group [tertiary]: quorumPort and leaderElectionPort must not use the same port [3888]"
+        ]
+    )]
+    #[case::node_slot_port_collision(
+        include_str!("../fixtures/admission/node-slot-port-collision.json"),
+        &["Port [2183] is used as quorum port in role group [default] and as client port in role group [default]"]
+    )]
+    #[case::shared_node_port_collision(
+        include_str!("../fixtures/admission/shared-node-port-collision.json"),
+        &["Port [2182] is used as client port in slot [1] of role group [default] and in slot [0] of role group [remote], which may run on the same node"]
+    )]
+    fn test_validate(#[case] fixture: &str, #[case] expected_errors: &[&str]) {
+        let request = review(fixture).request.unwrap();
+        let response = validate(review(fixture)).response.unwrap();
+
+        assert_eq!(response.uid, request.uid);
+        assert_eq!(
+            response.allowed,
+            expected_errors.is_empty(),
+            "{:?}",
+            response
+        );
+        let message = response
+            .status
+            .map(|status| status.message)
+            .unwrap_or_default();
+        for expected_error in expected_errors {
+            assert!(
+                message.contains(expected_error),
+                "[{}] does not contain [{}]",
+                message,
+                expected_error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::empty(json!({}), json!({"matchLabels": {"zone": "a"}}), true)]
+    #[case::different_keys(json!({"matchLabels": {"rack": "1"}}), json!({"matchLabels": {"zone": "a"}}), true)]
+    #[case::different_values(json!({"matchLabels": {"zone": "b"}}), json!({"matchLabels": {"zone": "a"}}), false)]
+    #[case::in_values(json!({"matchExpressions": [{"key": "zone", "operator": "In", "values": ["a", "b"]}]}), json!({"matchLabels": {"zone": "a"}}), true)]
+    #[case::not_in_values(json!({"matchExpressions": [{"key": "zone", "operator": "In", "values": ["b"]}]}), json!({"matchLabels": {"zone": "a"}}), false)]
+    #[case::does_not_exist(json!({"matchExpressions": [{"key": "zone", "operator": "DoesNotExist"}]}), json!({"matchLabels": {"zone": "a"}}), false)]
+    fn test_selectors_may_overlap(#[case] a: Value, #[case] b: Value, #[case] expected: bool) {
+        let a: LabelSelector = serde_json::from_value(a).unwrap();
+        let b: LabelSelector = serde_json::from_value(b).unwrap();
+
+        assert_eq!(selectors_may_overlap(&a, &b), expected);
+        assert_eq!(selectors_may_overlap(&b, &a), expected);
+    }
+
+    #[test]
+    fn test_response_format() {
+        let response = validate(review(include_str!(
+            "../fixtures/admission/even-instances.json"
+        )));
+
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({
+                "apiVersion": "admission.k8s.io/v1",
+                "kind": "AdmissionReview",
+                "response": {
+                    "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+                    "allowed": false,
+                    "status": {
+                        "code": 403,
+                        "message": "The number of server instances [2] must be odd, an ensemble of [2] servers can't tolerate more failures than one of [1]"
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_mutate() {
+        let response = mutate(review(include_str!("../fixtures/admission/defaults.json")))
+            .response
+            .unwrap();
+
+        assert!(response.allowed);
+        assert_eq!(response.patch_type.as_deref(), Some("JSONPatch"));
+        let patch: Value =
+            serde_json::from_slice(&base64::decode(response.patch.unwrap()).unwrap()).unwrap();
+        assert_eq!(
+            patch,
+            json!([
+                {
+                    "op": "add",
+                    "path": "/spec/config",
+                    "value": {
+                        "clientPort": 2181,
+                        "dataDir": "/var/lib/zookeeper/data",
+                        "dataLogDir": "/var/lib/zookeeper/datalog",
+                        "initLimit": 5,
+                        "leaderElectionPort": 3888,
+                        "quorumPort": 2888,
+                        "syncLimit": 2,
+                        "tickTime": 2000
+                    }
+                },
+                { "op": "add", "path": "/spec/downgradePolicy", "value": "Forbid" },
+                { "op": "add", "path": "/spec/idQuarantineSeconds", "value": 3600 },
+                { "op": "add", "path": "/spec/perServerServices", "value": false },
+                { "op": "add", "path": "/spec/servers/selectors/default/instances", "value": 1 },
+                { "op": "add", "path": "/spec/servers/selectors/default/instancesPerNode", "value": 1 },
+                { "op": "add", "path": "/spec/servers/selectors/default/selector", "value": {} },
+                { "op": "add", "path": "/spec/servers/selectors/secondary/instancesPerNode", "value": 1 }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mutate_keeps_existing_values() {
+        let response = mutate(review(include_str!(
+            "../fixtures/admission/valid-create.json"
+        )))
+        .response
+        .unwrap();
+
+        let patch: Value =
+            serde_json::from_slice(&base64::decode(response.patch.unwrap()).unwrap()).unwrap();
+        let paths = patch
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|operation| operation["path"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "/spec/config/clientPort",
+                "/spec/config/dataDir",
+                "/spec/config/dataLogDir",
+                "/spec/config/initLimit",
+                "/spec/config/leaderElectionPort",
+                "/spec/config/quorumPort",
+                "/spec/config/syncLimit",
+                "/spec/downgradePolicy",
+                "/spec/idQuarantineSeconds",
+                "/spec/perServerServices",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mutate_delete() {
+        let response = mutate(review(include_str!("../fixtures/admission/delete.json")))
+            .response
+            .unwrap();
+        assert!(response.allowed);
+        assert_eq!(response.patch, None);
+    }
+}
@@ -0,0 +1,548 @@
+//! A minimal ZooKeeper client which speaks just enough of the ZooKeeper wire protocol (jute) for
+//! the administrative tasks of the operator.
+//!
+//! It opens a single session, sends one request at a time and waits for its response.
+//! Watches are never set, so the only unsolicited messages we may see are pings which are skipped.
+use crate::error::Error;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::debug;
+
+/// The znode ZooKeeper (3.5+) publishes its current dynamic configuration in
+pub const ZOOKEEPER_CONFIG_NODE: &str = "/zookeeper/config";
+
+const SESSION_TIMEOUT_MS: i32 = 30_000;
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+const OP_CREATE: i32 = 1;
+const OP_DELETE: i32 = 2;
+const OP_GET_DATA: i32 = 4;
+const OP_GET_CHILDREN: i32 = 8;
+const OP_RECONFIG: i32 = 16;
+const OP_CLOSE: i32 = -11;
+const OP_AUTH: i32 = 100;
+
+const XID_WATCH_EVENT: i32 = -1;
+const XID_PING: i32 = -2;
+const XID_AUTH: i32 = -4;
+
+/// Error codes as defined in `org.apache.zookeeper.KeeperException.Code`
+pub mod error_code {
+    pub const NEW_CONFIG_NO_QUORUM: i32 = -13;
+    pub const RECONFIG_IN_PROGRESS: i32 = -14;
+    pub const NO_NODE: i32 = -101;
+    pub const NODE_EXISTS: i32 = -110;
+    pub const NOT_EMPTY: i32 = -111;
+    pub const RECONFIG_DISABLED: i32 = -123;
+}
+
+/// All permissions (`org.apache.zookeeper.ZooDefs.Perms.ALL`)
+const PERMS_ALL: i32 = 31;
+
+/// Serializes requests in the jute binary format (all numbers are big endian).
+#[derive(Default)]
+pub(crate) struct JuteWriter {
+    buffer: Vec<u8>,
+}
+
+impl JuteWriter {
+    pub(crate) fn write_int(&mut self, value: i32) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn write_long(&mut self, value: i64) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn write_bool(&mut self, value: bool) -> &mut Self {
+        self.buffer.push(value as u8);
+        self
+    }
+
+    /// `None` is serialized as length -1 which ZooKeeper reads as `null`
+    pub(crate) fn write_buffer(&mut self, value: Option<&[u8]>) -> &mut Self {
+        match value {
+            None => self.write_int(-1),
+            Some(value) => {
+                self.write_int(value.len() as i32);
+                self.buffer.extend_from_slice(value);
+                self
+            }
+        }
+    }
+
+    pub(crate) fn write_string(&mut self, value: Option<&str>) -> &mut Self {
+        self.write_buffer(value.map(str::as_bytes))
+    }
+
+    pub(crate) fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Deserializes responses in the jute binary format.
+pub(crate) struct JuteReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> JuteReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        JuteReader { data }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < length {
+            return Err(Error::ZookeeperProtocolError(format!(
+                "expected [{}] more bytes but only [{}] are left",
+                length,
+                self.data.len()
+            )));
+        }
+        let (value, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(value)
+    }
+
+    pub(crate) fn read_int(&mut self) -> Result<i32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(i32::from_be_bytes(bytes))
+    }
+
+    pub(crate) fn read_long(&mut self) -> Result<i64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+
+    pub(crate) fn read_buffer(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let length = self.read_int()?;
+        if length < 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.take(length as usize)?.to_vec()))
+    }
+
+    pub(crate) fn read_string(&mut self) -> Result<Option<String>, Error> {
+        self.read_buffer()?
+            .map(|bytes| {
+                String::from_utf8(bytes).map_err(|err| {
+                    Error::ZookeeperProtocolError(format!("string is not valid UTF-8: {}", err))
+                })
+            })
+            .transpose()
+    }
+
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+pub struct ZookeeperClient {
+    stream: TcpStream,
+    session_id: i64,
+    next_xid: i32,
+    request_timeout: Duration,
+}
+
+impl ZookeeperClient {
+    /// Connects to a single ZooKeeper server and establishes a new session.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - `host:port` of the server
+    /// * `request_timeout` - How long to wait for the connection and for every single response
+    pub async fn connect(address: &str, request_timeout: Duration) -> Result<Self, Error> {
+        let stream = with_timeout(request_timeout, TcpStream::connect(address)).await?;
+        let mut client = ZookeeperClient {
+            stream,
+            session_id: 0,
+            next_xid: 1,
+            request_timeout,
+        };
+
+        let mut request = JuteWriter::default();
+        request
+            .write_int(0) // protocol version
+            .write_long(0) // last zxid seen
+            .write_int(SESSION_TIMEOUT_MS)
+            .write_long(0) // session id, 0 requests a new session
+            .write_buffer(Some(&[0u8; 16][..])) // password
+            .write_bool(false); // read only
+        client.send(request.into_inner()).await?;
+
+        let response = client.receive().await?;
+        let mut reader = JuteReader::new(&response);
+        let _protocol_version = reader.read_int()?;
+        let negotiated_timeout = reader.read_int()?;
+        if negotiated_timeout <= 0 {
+            return Err(Error::ZookeeperProtocolError(format!(
+                "server [{}] refused to create a session",
+                address
+            )));
+        }
+        client.session_id = reader.read_long()?;
+        debug!(
+            "Established session [{:#x}] with ZooKeeper server [{}]",
+            client.session_id, address
+        );
+
+        Ok(client)
+    }
+
+    /// Authenticates the session, e.g. with scheme `digest` and `user:password`.
+    pub async fn add_auth(&mut self, scheme: &str, auth: &str) -> Result<(), Error> {
+        let mut request = JuteWriter::default();
+        request
+            .write_int(0)
+            .write_string(Some(scheme))
+            .write_buffer(Some(auth.as_bytes()));
+        self.call(XID_AUTH, OP_AUTH, request).await?;
+        Ok(())
+    }
+
+    pub async fn get_data(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        let mut request = JuteWriter::default();
+        request.write_string(Some(path)).write_bool(false);
+        let xid = self.next_xid();
+        let response = self.call(xid, OP_GET_DATA, request).await?;
+        Ok(JuteReader::new(&response)
+            .read_buffer()?
+            .unwrap_or_default())
+    }
+
+    /// Creates a persistent znode which everybody may access. The parent has to exist.
+    pub async fn create(&mut self, path: &str, data: &[u8]) -> Result<(), Error> {
+        let mut request = JuteWriter::default();
+        request
+            .write_string(Some(path))
+            .write_buffer(Some(data))
+            .write_int(1) // number of ACL entries
+            .write_int(PERMS_ALL)
+            .write_string(Some("world"))
+            .write_string(Some("anyone"))
+            .write_int(0); // flags, 0 is a persistent znode
+        let xid = self.next_xid();
+        self.call(xid, OP_CREATE, request).await?;
+        Ok(())
+    }
+
+    /// Deletes a znode regardless of its version. It must not have any children.
+    pub async fn delete(&mut self, path: &str) -> Result<(), Error> {
+        let mut request = JuteWriter::default();
+        request.write_string(Some(path)).write_int(-1);
+        let xid = self.next_xid();
+        self.call(xid, OP_DELETE, request).await?;
+        Ok(())
+    }
+
+    /// Returns the names (not the paths) of the children of a znode.
+    pub async fn get_children(&mut self, path: &str) -> Result<Vec<String>, Error> {
+        let mut request = JuteWriter::default();
+        request.write_string(Some(path)).write_bool(false);
+        let xid = self.next_xid();
+        let response = self.call(xid, OP_GET_CHILDREN, request).await?;
+
+        let mut reader = JuteReader::new(&response);
+        let count = reader.read_int()?;
+        let mut children = Vec::with_capacity(count.max(0) as usize);
+        for _ in 0..count {
+            children.extend(reader.read_string()?);
+        }
+        Ok(children)
+    }
+
+    /// Deletes a znode and everything below it, a missing znode is not an error.
+    pub async fn delete_recursive(&mut self, path: &str) -> Result<(), Error> {
+        // Depth first without recursion, async functions can't call themselves
+        let mut pending = vec![path.to_string()];
+        while let Some(current) = pending.pop() {
+            let children = match self.get_children(&current).await {
+                Ok(children) => children,
+                Err(Error::ZookeeperError {
+                    code: error_code::NO_NODE,
+                    ..
+                }) => continue,
+                Err(err) => return Err(err),
+            };
+
+            if children.is_empty() {
+                match self.delete(&current).await {
+                    Ok(())
+                    | Err(Error::ZookeeperError {
+                        code: error_code::NO_NODE,
+                        ..
+                    }) => {}
+                    Err(err) => return Err(err),
+                }
+            } else {
+                // Revisit this znode once its children are gone
+                pending.push(current.clone());
+                pending.extend(
+                    children
+                        .iter()
+                        .map(|child| format!("{}/{}", current.trim_end_matches('/'), child)),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs an incremental reconfiguration and returns the new configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `joining` - Servers to add (or update) in the form `server.N=host:port:port:role;clientPort`
+    /// * `leaving` - The ids of the servers to remove
+    pub async fn reconfig(
+        &mut self,
+        joining: &[String],
+        leaving: &[usize],
+    ) -> Result<Vec<u8>, Error> {
+        let joining = joining.join(",");
+        let leaving = leaving
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut request = JuteWriter::default();
+        request
+            .write_string(non_empty(&joining))
+            .write_string(non_empty(&leaving))
+            .write_string(None) // new members, only used for non-incremental reconfigurations
+            .write_long(-1); // current config id, -1 skips the check
+        let xid = self.next_xid();
+        let response = self.call(xid, OP_RECONFIG, request).await?;
+        Ok(JuteReader::new(&response)
+            .read_buffer()?
+            .unwrap_or_default())
+    }
+
+    /// Closes the session, errors are ignored as the server drops the connection anyway.
+    pub async fn close(mut self) {
+        let xid = self.next_xid();
+        let _ = self.call(xid, OP_CLOSE, JuteWriter::default()).await;
+    }
+
+    fn next_xid(&mut self) -> i32 {
+        let xid = self.next_xid;
+        self.next_xid += 1;
+        xid
+    }
+
+    /// Sends a single request and returns the body of its response.
+    pub(crate) async fn call(
+        &mut self,
+        xid: i32,
+        op_code: i32,
+        body: JuteWriter,
+    ) -> Result<Vec<u8>, Error> {
+        let mut request = JuteWriter::default();
+        request.write_int(xid).write_int(op_code);
+        let mut payload = request.into_inner();
+        payload.extend(body.into_inner());
+        self.send(payload).await?;
+
+        loop {
+            let response = self.receive().await?;
+            let mut reader = JuteReader::new(&response);
+            let response_xid = reader.read_int()?;
+            let _zxid = reader.read_long()?;
+            let err = reader.read_int()?;
+
+            if response_xid == XID_PING || response_xid == XID_WATCH_EVENT {
+                continue;
+            }
+            if response_xid != xid {
+                return Err(Error::ZookeeperProtocolError(format!(
+                    "expected response for request [{}] but got [{}]",
+                    xid, response_xid
+                )));
+            }
+            if err != 0 {
+                return Err(Error::ZookeeperError { op_code, code: err });
+            }
+            return Ok(reader.remaining().to_vec());
+        }
+    }
+
+    async fn send(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        let mut frame = (payload.len() as i32).to_be_bytes().to_vec();
+        frame.extend(payload);
+        with_timeout(self.request_timeout, self.stream.write_all(&frame)).await
+    }
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        let request_timeout = self.request_timeout;
+        let stream = &mut self.stream;
+        with_timeout(request_timeout, async move {
+            let length = stream.read_i32().await?;
+            if length < 0 || length as usize > MAX_FRAME_LENGTH {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid frame length [{}]", length),
+                ));
+            }
+            let mut frame = vec![0; length as usize];
+            stream.read_exact(&mut frame).await?;
+            Ok(frame)
+        })
+        .await
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+async fn with_timeout<T, F>(duration: Duration, future: F) -> Result<T, Error>
+where
+    F: std::future::Future<Output = std::io::Result<T>>,
+{
+    match timeout(duration, future).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(Error::from(std::io::Error::new(
+            ErrorKind::TimedOut,
+            "timed out while talking to ZooKeeper",
+        ))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Reads a single length prefixed frame from the client
+    pub(crate) async fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+        let length = stream.read_i32().await.unwrap();
+        let mut frame = vec![0; length as usize];
+        stream.read_exact(&mut frame).await.unwrap();
+        frame
+    }
+
+    pub(crate) async fn write_frame(stream: &mut TcpStream, payload: Vec<u8>) {
+        let mut frame = (payload.len() as i32).to_be_bytes().to_vec();
+        frame.extend(payload);
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    pub(crate) fn connect_response() -> Vec<u8> {
+        let mut response = JuteWriter::default();
+        response
+            .write_int(0)
+            .write_int(SESSION_TIMEOUT_MS)
+            .write_long(42)
+            .write_buffer(Some(&[0u8; 16][..]))
+            .write_bool(false);
+        response.into_inner()
+    }
+
+    pub(crate) fn reply(xid: i32, err: i32, body: Vec<u8>) -> Vec<u8> {
+        let mut response = JuteWriter::default();
+        response.write_int(xid).write_long(1).write_int(err);
+        let mut response = response.into_inner();
+        response.extend(body);
+        response
+    }
+
+    #[test]
+    fn test_jute_roundtrip() {
+        let mut writer = JuteWriter::default();
+        writer
+            .write_int(-4)
+            .write_long(1 << 40)
+            .write_string(Some("digest"))
+            .write_string(None);
+        let data = writer.into_inner();
+
+        let mut reader = JuteReader::new(&data);
+        assert_eq!(reader.read_int().unwrap(), -4);
+        assert_eq!(reader.read_long().unwrap(), 1 << 40);
+        assert_eq!(reader.read_string().unwrap(), Some("digest".to_string()));
+        assert_eq!(reader.read_string().unwrap(), None);
+        assert!(reader.remaining().is_empty());
+        assert!(reader.read_int().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_data_and_reconfig() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_frame(&mut stream).await;
+            write_frame(&mut stream, connect_response()).await;
+
+            // getData
+            let request = read_frame(&mut stream).await;
+            let mut reader = JuteReader::new(&request);
+            let xid = reader.read_int().unwrap();
+            assert_eq!(reader.read_int().unwrap(), OP_GET_DATA);
+            assert_eq!(
+                reader.read_string().unwrap(),
+                Some(ZOOKEEPER_CONFIG_NODE.to_string())
+            );
+            // A ping in between must be skipped by the client
+            write_frame(&mut stream, reply(XID_PING, 0, vec![])).await;
+            let mut body = JuteWriter::default();
+            body.write_buffer(Some(
+                &b"server.1=node-1:2888:3888:participant;0.0.0.0:2181\nversion=100000000"[..],
+            ));
+            write_frame(&mut stream, reply(xid, 0, body.into_inner())).await;
+
+            // reconfig
+            let request = read_frame(&mut stream).await;
+            let mut reader = JuteReader::new(&request);
+            let xid = reader.read_int().unwrap();
+            assert_eq!(reader.read_int().unwrap(), OP_RECONFIG);
+            assert_eq!(
+                reader.read_string().unwrap(),
+                Some("server.2=node-2:2888:3888:participant;2181".to_string())
+            );
+            assert_eq!(reader.read_string().unwrap(), Some("3".to_string()));
+            assert_eq!(reader.read_string().unwrap(), None);
+            assert_eq!(reader.read_long().unwrap(), -1);
+            write_frame(
+                &mut stream,
+                reply(xid, error_code::RECONFIG_IN_PROGRESS, vec![]),
+            )
+            .await;
+        });
+
+        let mut client = ZookeeperClient::connect(&address, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let data = client.get_data(ZOOKEEPER_CONFIG_NODE).await.unwrap();
+        assert!(String::from_utf8(data)
+            .unwrap()
+            .starts_with("server.1=node-1"));
+
+        let result = client
+            .reconfig(
+                &["server.2=node-2:2888:3888:participant;2181".to_string()],
+                &[3],
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ZookeeperError {
+                code: error_code::RECONFIG_IN_PROGRESS,
+                ..
+            })
+        ));
+
+        server.await.unwrap();
+    }
+}
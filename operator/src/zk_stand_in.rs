@@ -0,0 +1,225 @@
+//! An in-process stand-in for a ZooKeeper server to test code talking to an ensemble.
+//!
+//! It keeps all znodes in memory and answers the requests `ZookeeperClient` sends (sessions,
+//! authentication, creating, reading, listing and deleting znodes), everything else is answered
+//! with `UNIMPLEMENTED`. Every connection gets its own session, there are no watches, ACLs or
+//! versions. With [`ZookeeperStandIn::require_auth`] only sessions that authenticated with the
+//! given credentials may access znodes, like a server with `requireClientAuthScheme`.
+use crate::zk_client::tests::{connect_response, reply, write_frame};
+use crate::zk_client::{error_code, JuteReader, JuteWriter};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+const OP_CREATE: i32 = 1;
+const OP_DELETE: i32 = 2;
+const OP_GET_DATA: i32 = 4;
+const OP_GET_CHILDREN: i32 = 8;
+const OP_CLOSE: i32 = -11;
+const OP_AUTH: i32 = 100;
+
+/// `org.apache.zookeeper.KeeperException.Code.UNIMPLEMENTED`
+const UNIMPLEMENTED: i32 = -6;
+/// `org.apache.zookeeper.KeeperException.Code.NOAUTH`
+const NO_AUTH: i32 = -102;
+
+type Znodes = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+/// `(scheme, auth)` a session has to authenticate with
+type RequiredAuth = Arc<Mutex<Option<(String, String)>>>;
+
+pub struct ZookeeperStandIn {
+    address: String,
+    znodes: Znodes,
+    required_auth: RequiredAuth,
+}
+
+impl ZookeeperStandIn {
+    /// Starts listening on a random local port, the server runs until the test ends.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut znodes = BTreeMap::new();
+        znodes.insert("/".to_string(), vec![]);
+        znodes.insert("/zookeeper".to_string(), vec![]);
+        znodes.insert("/zookeeper/config".to_string(), vec![]);
+        let znodes = Arc::new(Mutex::new(znodes));
+
+        let required_auth = Arc::new(Mutex::new(None));
+
+        let server_znodes = znodes.clone();
+        let server_required_auth = required_auth.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(
+                    stream,
+                    server_znodes.clone(),
+                    server_required_auth.clone(),
+                ));
+            }
+        });
+
+        ZookeeperStandIn {
+            address,
+            znodes,
+            required_auth,
+        }
+    }
+
+    /// `host:port` to connect to
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The paths of all znodes, sorted
+    pub fn paths(&self) -> Vec<String> {
+        self.znodes.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Rejects requests of sessions that didn't authenticate with these credentials with
+    /// `NOAUTH`
+    pub fn require_auth(&self, scheme: &str, auth: &str) {
+        *self.required_auth.lock().unwrap() = Some((scheme.to_string(), auth.to_string()));
+    }
+
+    pub fn insert(&self, path: &str, data: &[u8]) {
+        self.znodes
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), data.to_vec());
+    }
+}
+
+async fn serve_connection(mut stream: TcpStream, znodes: Znodes, required_auth: RequiredAuth) {
+    if read_frame(&mut stream).await.is_none() {
+        return;
+    }
+    write_frame(&mut stream, connect_response()).await;
+
+    let mut session_auth = None;
+    while let Some(request) = read_frame(&mut stream).await {
+        let mut reader = JuteReader::new(&request);
+        let xid = reader.read_int().unwrap();
+        let op_code = reader.read_int().unwrap();
+
+        if op_code == OP_CLOSE {
+            write_frame(&mut stream, reply(xid, 0, vec![])).await;
+            return;
+        }
+
+        if op_code == OP_AUTH {
+            let _auth_type = reader.read_int().unwrap();
+            let scheme = reader.read_string().unwrap().unwrap_or_default();
+            let auth = reader.read_buffer().unwrap().unwrap_or_default();
+            session_auth = Some((scheme, String::from_utf8_lossy(&auth).into_owned()));
+            write_frame(&mut stream, reply(xid, 0, vec![])).await;
+            continue;
+        }
+
+        let authenticated = match &*required_auth.lock().unwrap() {
+            Some(required) => session_auth.as_ref() == Some(required),
+            None => true,
+        };
+        if !authenticated {
+            write_frame(&mut stream, reply(xid, NO_AUTH, vec![])).await;
+            continue;
+        }
+
+        // The lock must be released before the response is sent
+        let (err, body) = {
+            let mut znodes = znodes.lock().unwrap();
+            match op_code {
+                OP_CREATE => create(&mut reader, &mut znodes),
+                OP_DELETE => delete(&mut reader, &mut znodes),
+                OP_GET_DATA => get_data(&mut reader, &znodes),
+                OP_GET_CHILDREN => get_children(&mut reader, &znodes),
+                _ => (UNIMPLEMENTED, vec![]),
+            }
+        };
+        write_frame(&mut stream, reply(xid, err, body)).await;
+    }
+}
+
+/// Reads a single frame, `None` when the client went away
+async fn read_frame(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let length = stream.read_i32().await.ok()?;
+    let mut frame = vec![0; length as usize];
+    stream.read_exact(&mut frame).await.ok()?;
+    Some(frame)
+}
+
+fn create(reader: &mut JuteReader, znodes: &mut BTreeMap<String, Vec<u8>>) -> (i32, Vec<u8>) {
+    let path = reader.read_string().unwrap().unwrap_or_default();
+    let data = reader.read_buffer().unwrap().unwrap_or_default();
+
+    if znodes.contains_key(&path) {
+        return (error_code::NODE_EXISTS, vec![]);
+    }
+    if !znodes.contains_key(parent(&path)) {
+        return (error_code::NO_NODE, vec![]);
+    }
+    znodes.insert(path.clone(), data);
+
+    let mut body = JuteWriter::default();
+    body.write_string(Some(path.as_str()));
+    (0, body.into_inner())
+}
+
+fn delete(reader: &mut JuteReader, znodes: &mut BTreeMap<String, Vec<u8>>) -> (i32, Vec<u8>) {
+    let path = reader.read_string().unwrap().unwrap_or_default();
+
+    if !znodes.contains_key(&path) {
+        return (error_code::NO_NODE, vec![]);
+    }
+    if !children(&path, znodes).is_empty() {
+        return (error_code::NOT_EMPTY, vec![]);
+    }
+    znodes.remove(&path);
+    (0, vec![])
+}
+
+fn get_data(reader: &mut JuteReader, znodes: &BTreeMap<String, Vec<u8>>) -> (i32, Vec<u8>) {
+    let path = reader.read_string().unwrap().unwrap_or_default();
+
+    match znodes.get(&path) {
+        None => (error_code::NO_NODE, vec![]),
+        Some(data) => {
+            // The stat which would follow is never read
+            let mut body = JuteWriter::default();
+            body.write_buffer(Some(data));
+            (0, body.into_inner())
+        }
+    }
+}
+
+fn get_children(reader: &mut JuteReader, znodes: &BTreeMap<String, Vec<u8>>) -> (i32, Vec<u8>) {
+    let path = reader.read_string().unwrap().unwrap_or_default();
+
+    if !znodes.contains_key(&path) {
+        return (error_code::NO_NODE, vec![]);
+    }
+    let children = children(&path, znodes);
+    let mut body = JuteWriter::default();
+    body.write_int(children.len() as i32);
+    for child in children {
+        body.write_string(Some(child));
+    }
+    (0, body.into_inner())
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(index) => &path[..index],
+    }
+}
+
+/// The names of the direct children of `path`
+fn children<'a>(path: &str, znodes: &'a BTreeMap<String, Vec<u8>>) -> Vec<&'a str> {
+    znodes
+        .keys()
+        .filter(|candidate| candidate.as_str() != "/" && parent(candidate) == path)
+        .map(|child| &child[child.rfind('/').map_or(0, |index| index + 1)..])
+        .collect()
+}
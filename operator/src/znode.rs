@@ -0,0 +1,487 @@
+//! The controller for `ZookeeperZnode` objects.
+//!
+//! Every object gets its own znode directly below the root of the referenced cluster, its path
+//! is derived from the uid of the object and recorded in the status before the znode is created.
+//! A discovery ConfigMap with the same name as the object contains the connection string
+//! chrooted to the znode. The znode and everything below it is deleted together with the object.
+//! If the cluster requires clients to authenticate, the controller authenticates as the super
+//! user of the cluster like the cluster controller does.
+use crate::discovery;
+use crate::error::Error;
+use crate::reconfig::{SUPER_PASSWORD_KEY, SUPER_USER};
+use crate::zk_client::{error_code, ZookeeperClient};
+use crate::{super_credentials_secret_name, ZK_REQUEST_TIMEOUT};
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::api::ListParams;
+use kube::Api;
+use stackable_operator::client::Client;
+use stackable_operator::config_map;
+use stackable_operator::controller::Controller;
+use stackable_operator::controller::{ControllerStrategy, ReconciliationState};
+use stackable_operator::error::OperatorResult;
+use stackable_operator::reconcile::{
+    ReconcileFunctionAction, ReconcileResult, ReconciliationContext,
+};
+use stackable_zookeeper_crd::util::server_dns_name;
+use stackable_zookeeper_crd::znode::{znode_path, ZookeeperZnode, ZookeeperZnodeStatus};
+use stackable_zookeeper_crd::ZookeeperCluster;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const FINALIZER_NAME: &str = "zookeeper.stackable.tech/znode";
+
+/// Clusters aren't watched, so the discovery ConfigMap is refreshed in this interval to follow
+/// changes of their servers
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+type ZnodeReconcileResult = ReconcileResult<Error>;
+
+struct ZnodeState {
+    context: ReconciliationContext<ZookeeperZnode>,
+    znode_path: Option<String>,
+}
+
+impl ZnodeState {
+    /// Records the path of the znode in the status, so it never changes afterwards.
+    async fn init_status(&mut self) -> ZnodeReconcileResult {
+        if self.znode_path.is_some() {
+            return Ok(ReconcileFunctionAction::Continue);
+        }
+
+        let uid = self.context.resource.metadata.uid.as_ref().ok_or_else(|| {
+            Error::ReconcileError(format!(
+                "ZookeeperZnode [{}] has no uid",
+                self.context.log_name()
+            ))
+        })?;
+        let status = ZookeeperZnodeStatus {
+            znode_path: Some(znode_path(uid)),
+        };
+        self.context
+            .client
+            .merge_patch_status(&self.context.resource, &status)
+            .await?;
+        self.znode_path = status.znode_path;
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// The referenced cluster, `None` if it doesn't exist (anymore).
+    async fn get_cluster(&self) -> OperatorResult<Option<ZookeeperCluster>> {
+        let cluster_ref = &self.context.resource.spec.cluster_ref;
+        let namespace = cluster_ref
+            .namespace
+            .clone()
+            .unwrap_or_else(|| self.context.namespace());
+
+        match self
+            .context
+            .client
+            .get::<ZookeeperCluster>(&cluster_ref.name, Some(namespace.as_str()))
+            .await
+        {
+            Ok(cluster) => Ok(Some(cluster)),
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+            }) if response.code == 404 => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Creates the znode, this is repeated on every reconciliation in case the cluster lost it
+    /// (e.g. because it was recreated).
+    async fn create_znode(&self) -> ZnodeReconcileResult {
+        let path = self.path()?;
+        let cluster = match self.get_cluster().await? {
+            Some(cluster) => cluster,
+            None => {
+                warn!(
+                    "{}: The referenced ZookeeperCluster [{}] does not exist, will check again",
+                    self.context.log_name(),
+                    self.context.resource.spec.cluster_ref.name
+                );
+                return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+            }
+        };
+
+        let result = match self.super_auth(&cluster).await {
+            Ok(auth) => ensure_znode(&server_addresses(&cluster), auth.as_deref(), path).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(
+                "{}: Could not create znode [{}], will try again: {}",
+                self.context.log_name(),
+                path,
+                err
+            );
+            return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)));
+        }
+
+        Ok(ReconcileFunctionAction::Continue)
+    }
+
+    /// Publishes the connection string chrooted to the znode.
+    async fn create_discovery_config_map(&self) -> ZnodeReconcileResult {
+        let path = self.path()?;
+        let cluster = match self.get_cluster().await? {
+            Some(cluster) => cluster,
+            None => return Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10))),
+        };
+
+        let cluster_name = cluster.metadata.name.clone().unwrap_or_default();
+        let cluster_namespace = cluster.metadata.namespace.clone().unwrap_or_default();
+        let servers = cluster
+            .status
+            .as_ref()
+            .map(|status| status.servers.clone())
+            .unwrap_or_default();
+        let data = discovery::build_cluster_discovery_data(
+            &cluster_name,
+            &cluster_namespace,
+            &cluster.spec,
+            &servers,
+            Some(path),
+        );
+        let cm = config_map::create_config_map(&self.context.resource, &self.context.name(), data)?;
+        self.context.client.apply_patch(&cm, &cm).await?;
+
+        Ok(ReconcileFunctionAction::Requeue(REFRESH_INTERVAL))
+    }
+
+    /// Deletes the znode with everything below it. Nothing needs to be done if the cluster is
+    /// gone, the znode went with it.
+    async fn delete_znode(&self) -> OperatorResult<ReconcileFunctionAction> {
+        let path = match &self.znode_path {
+            Some(path) => path,
+            None => return Ok(ReconcileFunctionAction::Done),
+        };
+        let cluster = match self.get_cluster().await? {
+            Some(cluster) => cluster,
+            None => return Ok(ReconcileFunctionAction::Done),
+        };
+
+        let result = match self.super_auth(&cluster).await {
+            Ok(auth) => remove_znode(&server_addresses(&cluster), auth.as_deref(), path).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => {
+                info!("{}: Deleted znode [{}]", self.context.log_name(), path);
+                Ok(ReconcileFunctionAction::Done)
+            }
+            Err(err) => {
+                warn!(
+                    "{}: Could not delete znode [{}], will try again: {}",
+                    self.context.log_name(),
+                    path,
+                    err
+                );
+                Ok(ReconcileFunctionAction::Requeue(Duration::from_secs(10)))
+            }
+        }
+    }
+
+    /// The `user:password` of the super user of the cluster if it requires clients to
+    /// authenticate, see `init_super_credentials` of the cluster controller.
+    async fn super_auth(&self, cluster: &ZookeeperCluster) -> Result<Option<String>, Error> {
+        let require_client_auth = cluster
+            .spec
+            .authentication
+            .as_ref()
+            .map_or(false, |authentication| authentication.require_client_auth());
+        if !require_client_auth {
+            return Ok(None);
+        }
+
+        let secret_name =
+            super_credentials_secret_name(&cluster.metadata.name.clone().unwrap_or_default());
+        let namespace = cluster.metadata.namespace.clone().unwrap_or_default();
+        let secret = self
+            .context
+            .client
+            .get::<Secret>(&secret_name, Some(namespace.as_str()))
+            .await?;
+        let password = secret
+            .data
+            .unwrap_or_default()
+            .remove(SUPER_PASSWORD_KEY)
+            .and_then(|password| String::from_utf8(password.0).ok())
+            .ok_or_else(|| {
+                Error::ReconcileError(format!(
+                    "Secret [{}] does not contain a valid [{}]",
+                    secret_name, SUPER_PASSWORD_KEY
+                ))
+            })?;
+
+        Ok(Some(format!("{}:{}", SUPER_USER, password)))
+    }
+
+    fn path(&self) -> Result<&str, Error> {
+        self.znode_path.as_deref().ok_or_else(|| Error::ReconcileError(
+            "znode_path missing, this is a programming error and should never happen. Please report in our issue tracker.".to_string(),
+        ))
+    }
+}
+
+impl ReconciliationState for ZnodeState {
+    type Error = Error;
+
+    fn reconcile(
+        &mut self,
+    ) -> Pin<Box<dyn Future<Output = Result<ReconcileFunctionAction, Self::Error>> + Send + '_>>
+    {
+        Box::pin(async move {
+            self.init_status()
+                .await?
+                .then(self.context.handle_deletion(
+                    Box::pin(self.delete_znode()),
+                    FINALIZER_NAME,
+                    true,
+                ))
+                .await?
+                .then(self.create_znode())
+                .await?
+                .then(self.create_discovery_config_map())
+                .await
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ZnodeStrategy {}
+
+#[async_trait]
+impl ControllerStrategy for ZnodeStrategy {
+    type Item = ZookeeperZnode;
+    type State = ZnodeState;
+    type Error = Error;
+
+    async fn init_reconcile_state(
+        &self,
+        context: ReconciliationContext<Self::Item>,
+    ) -> Result<Self::State, Self::Error> {
+        Ok(ZnodeState {
+            znode_path: context
+                .resource
+                .status
+                .as_ref()
+                .and_then(|status| status.znode_path.clone()),
+            context,
+        })
+    }
+}
+
+/// This creates an instance of a [`Controller`] for `ZookeeperZnode` objects.
+///
+/// This is an async method and the returned future needs to be consumed to make progress.
+pub async fn create_znode_controller(client: Client) {
+    let znode_api: Api<ZookeeperZnode> = client.get_all_api();
+    let config_maps_api: Api<ConfigMap> = client.get_all_api();
+
+    let controller = Controller::new(znode_api).owns(config_maps_api, ListParams::default());
+
+    controller
+        .run(client, ZnodeStrategy {}, Duration::from_secs(10))
+        .await;
+}
+
+/// The `host:port` of every server recorded in the status of the cluster, the host is the DNS
+/// name of the server like in the discovery ConfigMaps.
+fn server_addresses(cluster: &ZookeeperCluster) -> Vec<String> {
+    let cluster_name = cluster.metadata.name.clone().unwrap_or_default();
+    let cluster_namespace = cluster.metadata.namespace.clone().unwrap_or_default();
+    cluster
+        .status
+        .as_ref()
+        .map(|status| {
+            status
+                .servers
+                .iter()
+                .map(|(server_name, server)| {
+                    format!(
+                        "{}:{}",
+                        server_dns_name(server_name, &cluster_name, &cluster_namespace),
+                        cluster
+                            .spec
+                            .merged_config(&server.role_group)
+                            .for_node_slot(server.node_slot)
+                            .client_port()
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Connects to the first server that can be reached and authenticates with the digest `auth`
+/// (`user:password`) if given.
+async fn connect(addresses: &[String], auth: Option<&str>) -> Result<ZookeeperClient, Error> {
+    for address in addresses {
+        match ZookeeperClient::connect(address, ZK_REQUEST_TIMEOUT).await {
+            Ok(mut client) => {
+                if let Some(auth) = auth {
+                    client.add_auth("digest", auth).await?;
+                }
+                return Ok(client);
+            }
+            Err(err) => warn!(
+                "Could not connect to ZooKeeper server [{}], trying the next one: {}",
+                address, err
+            ),
+        }
+    }
+
+    Err(Error::ReconcileError(
+        "Could not connect to any ZooKeeper server of the ensemble".to_string(),
+    ))
+}
+
+/// Creates the znode unless it already exists.
+async fn ensure_znode(addresses: &[String], auth: Option<&str>, path: &str) -> Result<(), Error> {
+    let mut client = connect(addresses, auth).await?;
+    let result = match client.create(path, &[]).await {
+        Ok(())
+        | Err(Error::ZookeeperError {
+            code: error_code::NODE_EXISTS,
+            ..
+        }) => Ok(()),
+        Err(err) => Err(err),
+    };
+    client.close().await;
+    result
+}
+
+/// Deletes the znode and everything below it, a missing znode is not an error.
+async fn remove_znode(addresses: &[String], auth: Option<&str>, path: &str) -> Result<(), Error> {
+    let mut client = connect(addresses, auth).await?;
+    let result = client.delete_recursive(path).await;
+    client.close().await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zk_stand_in::ZookeeperStandIn;
+    use serde_json::json;
+
+    #[test]
+    fn test_server_addresses() {
+        let cluster: ZookeeperCluster = serde_json::from_value(json!({
+            "apiVersion": "zookeeper.stackable.tech/v1",
+            "kind": "ZookeeperCluster",
+            "metadata": { "name": "simple", "namespace": "default" },
+            "spec": {
+                "version": "3.5.8",
+                "servers": {
+                    "selectors": {
+                        "default": {
+                            "instances": 1,
+                            "instancesPerNode": 2,
+                            "selector": {}
+                        }
+                    }
+                }
+            },
+            "status": {
+                "servers": {
+                    "default-0": {
+                        "roleGroup": "default",
+                        "ordinal": 0,
+                        "id": 1,
+                        "nodeName": "node-1",
+                        "nodeSlot": 0
+                    },
+                    "default-1": {
+                        "roleGroup": "default",
+                        "ordinal": 1,
+                        "id": 2,
+                        "nodeName": "node-1",
+                        "nodeSlot": 1
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            server_addresses(&cluster),
+            vec![
+                "default-0.simple-servers.default.svc:2181",
+                "default-1.simple-servers.default.svc:2182",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ensure_znode() {
+        let zookeeper = ZookeeperStandIn::start().await;
+        let addresses = vec![zookeeper.address().to_string()];
+
+        ensure_znode(&addresses, None, "/znode-1234").await.unwrap();
+        // Creating it again must not fail
+        ensure_znode(&addresses, None, "/znode-1234").await.unwrap();
+
+        assert!(zookeeper.paths().contains(&"/znode-1234".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_ensure_znode_skips_unreachable_servers() {
+        let zookeeper = ZookeeperStandIn::start().await;
+        let unreachable = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let addresses = vec![unreachable, zookeeper.address().to_string()];
+
+        ensure_znode(&addresses, None, "/znode-1234").await.unwrap();
+
+        assert!(zookeeper.paths().contains(&"/znode-1234".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_remove_znode() {
+        let zookeeper = ZookeeperStandIn::start().await;
+        let addresses = vec![zookeeper.address().to_string()];
+        zookeeper.insert("/znode-1234", b"");
+        zookeeper.insert("/znode-1234/brokers", b"");
+        zookeeper.insert("/znode-1234/brokers/ids", b"1");
+        zookeeper.insert("/znode-5678", b"");
+
+        remove_znode(&addresses, None, "/znode-1234").await.unwrap();
+        // Removing it again must not fail
+        remove_znode(&addresses, None, "/znode-1234").await.unwrap();
+
+        assert_eq!(
+            zookeeper.paths(),
+            vec!["/", "/znode-5678", "/zookeeper", "/zookeeper/config"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_znode_with_required_auth() {
+        let zookeeper = ZookeeperStandIn::start().await;
+        let addresses = vec![zookeeper.address().to_string()];
+        zookeeper.require_auth("digest", "super:secret");
+
+        assert!(ensure_znode(&addresses, None, "/znode-1234").await.is_err());
+        assert!(ensure_znode(&addresses, Some("super:wrong"), "/znode-1234")
+            .await
+            .is_err());
+        ensure_znode(&addresses, Some("super:secret"), "/znode-1234")
+            .await
+            .unwrap();
+        assert!(zookeeper.paths().contains(&"/znode-1234".to_string()));
+
+        assert!(remove_znode(&addresses, None, "/znode-1234").await.is_err());
+        remove_znode(&addresses, Some("super:secret"), "/znode-1234")
+            .await
+            .unwrap();
+        assert!(!zookeeper.paths().contains(&"/znode-1234".to_string()));
+    }
+}
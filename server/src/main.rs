@@ -1,6 +1,15 @@
 use stackable_operator::logging;
 use stackable_operator::{client, error};
+use stackable_zookeeper_operator::webhook::{self, AdmissionReview};
+use std::net::SocketAddr;
 use tracing::info;
+use warp::Filter;
+
+/// The webhooks are only served if both a certificate and a key are configured
+const WEBHOOK_CERT_ENV: &str = "ZOOKEEPER_OPERATOR_WEBHOOK_CERT";
+const WEBHOOK_KEY_ENV: &str = "ZOOKEEPER_OPERATOR_WEBHOOK_KEY";
+const WEBHOOK_ADDRESS_ENV: &str = "ZOOKEEPER_OPERATOR_WEBHOOK_ADDRESS";
+const DEFAULT_WEBHOOK_ADDRESS: &str = "0.0.0.0:8443";
 
 #[tokio::main]
 async fn main() -> Result<(), error::Error> {
@@ -8,6 +17,59 @@ async fn main() -> Result<(), error::Error> {
 
     info!("Starting Stackable Operator for Apache ZooKeeper");
     let client = client::create_client(Some("zookeeper.stackable.tech".to_string())).await?;
-    stackable_zookeeper_operator::create_controller(client).await;
+
+    match (
+        std::env::var(WEBHOOK_CERT_ENV),
+        std::env::var(WEBHOOK_KEY_ENV),
+    ) {
+        (Ok(cert_path), Ok(key_path)) => {
+            let address = std::env::var(WEBHOOK_ADDRESS_ENV)
+                .unwrap_or_else(|_| DEFAULT_WEBHOOK_ADDRESS.to_string());
+            let address: SocketAddr = address.parse().unwrap_or_else(|err| {
+                panic!(
+                    "Invalid webhook address [{}] in [{}]: {}",
+                    address, WEBHOOK_ADDRESS_ENV, err
+                )
+            });
+
+            info!("Serving admission webhooks on [{}]", address);
+            tokio::join!(
+                stackable_zookeeper_operator::create_controller(client.clone()),
+                stackable_zookeeper_operator::create_znode_controller(client),
+                serve_webhooks(address, cert_path, key_path)
+            );
+        }
+        _ => {
+            info!(
+                "[{}] and [{}] are not set, admission webhooks are disabled",
+                WEBHOOK_CERT_ENV, WEBHOOK_KEY_ENV
+            );
+            tokio::join!(
+                stackable_zookeeper_operator::create_controller(client.clone()),
+                stackable_zookeeper_operator::create_znode_controller(client)
+            );
+        }
+    }
+
     Ok(())
 }
+
+async fn serve_webhooks(address: SocketAddr, cert_path: String, key_path: String) {
+    let validate = warp::post()
+        .and(warp::path("validate"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .map(|review: AdmissionReview| warp::reply::json(&webhook::validate(review)));
+    let mutate = warp::post()
+        .and(warp::path("mutate"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .map(|review: AdmissionReview| warp::reply::json(&webhook::mutate(review)));
+
+    warp::serve(validate.or(mutate))
+        .tls()
+        .cert_path(cert_path)
+        .key_path(key_path)
+        .run(address)
+        .await;
+}